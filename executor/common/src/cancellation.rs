@@ -42,3 +42,40 @@ pub fn make() -> (Arc<Token>, impl Clone + Fn()) {
         }
     })
 }
+
+/// Creates a token that is cancelled either together with `parent`,
+/// after `timeout` elapses or when returned canceller is called.
+///
+/// Caller must eventually call the canceller, otherwise watcher task lives as long as the parent
+pub fn make_child(
+    parent: &Arc<Token>,
+    timeout: Option<std::time::Duration>,
+) -> (Arc<Token>, impl Clone + Fn()) {
+    let (child, canceller) = make();
+
+    if parent.is_cancelled() {
+        canceller();
+        return (child, canceller);
+    }
+
+    let parent = parent.clone();
+    let child_copy = child.clone();
+    let canceller_copy = canceller.clone();
+
+    tokio::spawn(async move {
+        let sleep = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = parent.chan.closed() => canceller_copy(),
+            _ = sleep => canceller_copy(),
+            _ = child_copy.chan.closed() => {},
+        }
+    });
+
+    (child, canceller)
+}
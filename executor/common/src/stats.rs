@@ -3,6 +3,8 @@ pub mod metric {
     pub struct Time(std::sync::atomic::AtomicU64);
     #[derive(serde::Serialize)]
     pub struct Count(std::sync::atomic::AtomicU64);
+    #[derive(serde::Serialize)]
+    pub struct Max(std::sync::atomic::AtomicU64);
 
     impl std::fmt::Debug for Time {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -19,6 +21,13 @@ pub mod metric {
         }
     }
 
+    impl std::fmt::Debug for Max {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let i = self.0.load(std::sync::atomic::Ordering::SeqCst);
+            write!(f, "{i}")
+        }
+    }
+
     impl Time {
         pub fn new() -> Self {
             Self(std::sync::atomic::AtomicU64::new(0))
@@ -53,6 +62,22 @@ pub mod metric {
             Self::new()
         }
     }
+
+    impl Max {
+        pub fn new() -> Self {
+            Self(std::sync::atomic::AtomicU64::new(0))
+        }
+
+        pub fn record(&self, value: u64) {
+            self.0.fetch_max(value, std::sync::atomic::Ordering::AcqRel);
+        }
+    }
+
+    impl Default for Max {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
 }
 
 pub mod tracker {
@@ -464,6 +464,149 @@ impl<T> Default for CacheMap<T> {
     }
 }
 
+struct LruEntry<T: 'static> {
+    cell: DArc<tokio::sync::OnceCell<T>>,
+    /// zero until value is created
+    size: u64,
+    last_use: std::sync::atomic::AtomicU64,
+}
+
+/// Result of [`LruCacheMap::get_or_create`]
+pub struct LruLookup<T: 'static> {
+    pub value: DArc<T>,
+    /// whenever value was already present
+    pub hit: bool,
+    /// amount of entries that were evicted to fit the value
+    pub evicted: u32,
+}
+
+/// Same as [`CacheMap`], but least recently used entries are evicted when total size exceeds the budget.
+/// Evicted values stay alive while someone holds them
+pub struct LruCacheMap<T: 'static> {
+    map: dashmap::DashMap<symbol_table::GlobalSymbol, LruEntry<T>>,
+    budget: Option<u64>,
+    used: std::sync::atomic::AtomicU64,
+    tick: std::sync::atomic::AtomicU64,
+}
+
+impl<T> LruCacheMap<T> {
+    /// `None` budget means that nothing is ever evicted
+    pub fn new(budget: Option<u64>) -> Self {
+        Self {
+            map: dashmap::DashMap::new(),
+            budget,
+            used: std::sync::atomic::AtomicU64::new(0),
+            tick: std::sync::atomic::AtomicU64::new(0),
+        }
+    }
+
+    /// Total size of created entries
+    pub fn used(&self) -> u64 {
+        self.used.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub async fn get_or_create<Err, F>(
+        &self,
+        key: symbol_table::GlobalSymbol,
+        size_of: impl FnOnce(&T) -> u64,
+        creator: impl FnOnce() -> F,
+    ) -> Result<LruLookup<T>, Err>
+    where
+        F: std::future::Future<Output = Result<T, Err>>,
+    {
+        let tick = self.next_tick();
+
+        let cell = match self.map.entry(key) {
+            dashmap::Entry::Occupied(occupied_entry) => {
+                let entry = occupied_entry.get();
+                entry
+                    .last_use
+                    .store(tick, std::sync::atomic::Ordering::SeqCst);
+                entry.cell.clone()
+            }
+            dashmap::Entry::Vacant(vacant_entry) => vacant_entry
+                .insert(LruEntry {
+                    cell: DArc::new(tokio::sync::OnceCell::new()),
+                    size: 0,
+                    last_use: std::sync::atomic::AtomicU64::new(tick),
+                })
+                .cell
+                .clone(),
+        };
+
+        let created = std::sync::atomic::AtomicBool::new(false);
+
+        let value = cell
+            .clone()
+            .into_get_sub_async(|cell| {
+                cell.get_or_try_init(|| {
+                    created.store(true, std::sync::atomic::Ordering::SeqCst);
+                    creator()
+                })
+            })
+            .await
+            .lift_result()?
+            .into_arc();
+
+        let hit = !created.load(std::sync::atomic::Ordering::SeqCst);
+        let mut evicted = 0;
+
+        if !hit {
+            let size = size_of(&value);
+
+            // accounted under the shard lock, so that concurrent eviction sees either both or none
+            if let Some(mut entry) = self.map.get_mut(&key) {
+                if std::ptr::eq::<tokio::sync::OnceCell<T>>(&*entry.cell, &*cell) {
+                    entry.size = size;
+                    self.used
+                        .fetch_add(size, std::sync::atomic::Ordering::SeqCst);
+                }
+            }
+
+            evicted = self.evict(key);
+        }
+
+        Ok(LruLookup {
+            value,
+            hit,
+            evicted,
+        })
+    }
+
+    fn evict(&self, keep: symbol_table::GlobalSymbol) -> u32 {
+        let Some(budget) = self.budget else {
+            return 0;
+        };
+
+        let mut evicted = 0;
+
+        while self.used() > budget {
+            let victim = self
+                .map
+                .iter()
+                .filter(|e| *e.key() != keep && e.cell.initialized())
+                .min_by_key(|e| e.last_use.load(std::sync::atomic::Ordering::SeqCst))
+                .map(|e| *e.key());
+
+            let Some(victim) = victim else {
+                break;
+            };
+
+            if let Some((_, entry)) = self.map.remove_if(&victim, |_, e| e.cell.initialized()) {
+                self.used
+                    .fetch_sub(entry.size, std::sync::atomic::Ordering::SeqCst);
+                evicted += 1;
+            }
+        }
+
+        evicted
+    }
+}
+
 pub struct Lock<T, Token>(T, Token);
 
 impl<T, Token> Lock<T, Token> {
@@ -522,3 +665,45 @@ impl<F: FnOnce()> Drop for DropGuard<F> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn put(cache: &LruCacheMap<u64>, key: &str, size: u64) -> LruLookup<u64> {
+        cache
+            .get_or_create(symbol_table::GlobalSymbol::from(key), |x| *x, || async {
+                Ok::<_, ()>(size)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn lru_evicts_least_recently_used() {
+        let cache = LruCacheMap::new(Some(10));
+
+        assert!(!put(&cache, "a", 4).await.hit);
+        assert!(!put(&cache, "b", 4).await.hit);
+        assert!(put(&cache, "a", 4).await.hit);
+
+        let lookup = put(&cache, "c", 4).await;
+        assert!(!lookup.hit);
+        assert_eq!(lookup.evicted, 1);
+        assert_eq!(cache.used(), 8);
+
+        assert!(put(&cache, "a", 4).await.hit);
+        assert!(!put(&cache, "b", 4).await.hit);
+    }
+
+    #[tokio::test]
+    async fn lru_keeps_oversized_entry() {
+        let cache = LruCacheMap::new(Some(10));
+
+        put(&cache, "a", 4).await;
+        let lookup = put(&cache, "b", 20).await;
+        assert_eq!(lookup.evicted, 1);
+        assert_eq!(*lookup.value, 20);
+        assert!(put(&cache, "b", 20).await.hit);
+    }
+}
@@ -67,6 +67,16 @@ impl From<&[u8]> for SharedBytes {
     }
 }
 
+impl From<Vec<u8>> for SharedBytes {
+    fn from(value: Vec<u8>) -> Self {
+        let data: Box<[u8]> = value.into_boxed_slice();
+
+        assert!(data.len() as u64 <= u32::MAX as u64);
+
+        Self(DArc::new(data).gep(|x| x.as_ref()))
+    }
+}
+
 impl SharedBytes {
     pub fn len(&self) -> usize {
         self.0.len()
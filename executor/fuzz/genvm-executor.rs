@@ -707,6 +707,9 @@ async fn run_with(
         cache_dir: "/dev/null".to_owned(),
         runners_dir: "/tmp".to_owned(), // we have no runners
         registry_dir: registry_dir.to_string_lossy().to_string(),
+        engine: Default::default(),
+        cache_limits: Default::default(),
+        runner_verification: Default::default(),
         base: BaseConfig {
             threads: 2,
             blocking_threads: 4,
@@ -849,19 +852,14 @@ struct FuzzingInput {
 }
 
 impl FuzzingInput {
-    fn get_perms(&self) -> String {
-        let mut perms = "r".to_owned();
-
-        if self.can_write {
-            perms.push('w');
-        }
-        if self.can_send {
-            perms.push('s');
-        }
-        if self.can_nondet {
-            perms.push('n');
+    fn get_perms(&self) -> genvm::Permissions {
+        genvm::Permissions {
+            read_storage: true,
+            write_storage: self.can_write,
+            send_messages: self.can_send,
+            call_others: false,
+            spawn_nondet: self.can_nondet,
         }
-        perms
     }
 }
 
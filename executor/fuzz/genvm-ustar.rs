@@ -0,0 +1,188 @@
+use arbitrary::Arbitrary;
+use genvm_common::util;
+
+const BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    File(Vec<u8>),
+    Dir,
+    Symlink(String),
+    HardLink(String),
+    Raw(u8),
+}
+
+#[derive(Debug, Arbitrary)]
+enum NameEncoding {
+    Plain,
+    Prefix,
+    Pax,
+    GnuLongName,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Entry {
+    name: String,
+    kind: Kind,
+    encoding: NameEncoding,
+    /// applied to the header before checksum is computed
+    header_patches: Vec<(u16, u8)>,
+    break_checksum: bool,
+}
+
+#[derive(Debug, Arbitrary)]
+struct FuzzInput {
+    entries: Vec<Entry>,
+    /// applied to the whole archive after it is built
+    patches: Vec<(u32, u8)>,
+    truncate: Option<u16>,
+    terminate: bool,
+}
+
+fn pad(out: &mut Vec<u8>) {
+    out.resize(out.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let s = format!("{:0width$o}", value, width = field.len() - 1);
+    let s = &s.as_bytes()[s.len().saturating_sub(field.len() - 1)..];
+    field[..s.len()].copy_from_slice(s);
+    field[s.len()..].fill(0);
+}
+
+fn copy_truncated(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+fn header(name: &[u8], link: &[u8], type_flag: u8, size: u64, entry: &Entry) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+
+    match entry.encoding {
+        NameEncoding::Prefix if name.len() > 100 => {
+            let split = name.len() - 100;
+            copy_truncated(&mut header[345..500], &name[..split.saturating_sub(1)]);
+            copy_truncated(&mut header[0..100], &name[split..]);
+        }
+        _ => copy_truncated(&mut header[0..100], name),
+    }
+
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[124..136], size);
+    header[156] = type_flag;
+    copy_truncated(&mut header[157..257], link);
+    header[257..265].copy_from_slice(b"ustar\x0000");
+
+    for (offset, value) in &entry.header_patches {
+        header[*offset as usize % BLOCK_SIZE] = *value;
+    }
+
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|x| *x as u64).sum();
+    write_octal(&mut header[148..155], checksum);
+    if entry.break_checksum {
+        header[148] ^= 1;
+    }
+
+    header
+}
+
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body_len = key.len() + value.len() + 3;
+    let mut len = body_len + 1;
+    while len != body_len + len.to_string().len() {
+        len = body_len + len.to_string().len();
+    }
+
+    let mut res = format!("{len} {key}=").into_bytes();
+    res.extend_from_slice(value);
+    res.push(b'\n');
+    res
+}
+
+fn build(input: &FuzzInput) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for entry in &input.entries {
+        let name = entry.name.as_bytes();
+
+        let (type_flag, link, contents): (u8, &[u8], &[u8]) = match &entry.kind {
+            Kind::File(contents) => (b'0', &[], contents),
+            Kind::Dir => (b'5', &[], &[]),
+            Kind::Symlink(target) => (b'2', target.as_bytes(), &[]),
+            Kind::HardLink(target) => (b'1', target.as_bytes(), &[]),
+            Kind::Raw(type_flag) => (*type_flag, &[], &[]),
+        };
+
+        match entry.encoding {
+            NameEncoding::Pax => {
+                let mut records = pax_record("path", name);
+                if !link.is_empty() {
+                    records.extend(pax_record("linkpath", link));
+                }
+                out.extend(header(b"PaxHeader", &[], b'x', records.len() as u64, entry));
+                out.extend(records);
+                pad(&mut out);
+            }
+            NameEncoding::GnuLongName => {
+                let mut long_name = name.to_vec();
+                long_name.push(0);
+                out.extend(header(
+                    b"././@LongLink",
+                    &[],
+                    b'L',
+                    long_name.len() as u64,
+                    entry,
+                ));
+                out.extend(long_name);
+                pad(&mut out);
+            }
+            NameEncoding::Plain | NameEncoding::Prefix => {}
+        }
+
+        out.extend(header(name, link, type_flag, contents.len() as u64, entry));
+        out.extend_from_slice(contents);
+        pad(&mut out);
+    }
+
+    if input.terminate {
+        out.extend([0u8; BLOCK_SIZE * 2]);
+    }
+
+    if !out.is_empty() {
+        let len = out.len();
+        for (offset, value) in &input.patches {
+            out[*offset as usize % len] = *value;
+        }
+    }
+
+    if let Some(truncate) = input.truncate {
+        out.truncate(out.len().saturating_sub(truncate as usize));
+    }
+
+    out
+}
+
+fn main() {
+    afl::fuzz!(|input: FuzzInput| {
+        let data = build(&input);
+        let len = data.len();
+
+        let Ok(arch) = genvm::runners::Archive::from_ustar(util::SharedBytes::new(data)) else {
+            return;
+        };
+
+        assert_eq!(arch.total_size as usize, len);
+
+        for name in arch.data.keys() {
+            assert!(!name.is_empty(), "empty name");
+            assert!(!name.starts_with('/'), "absolute name {name}");
+            assert!(
+                !name
+                    .split('/')
+                    .any(|c| c.is_empty() || c == "." || c == ".."),
+                "not normalized name {name}"
+            );
+        }
+    });
+}
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// tries to get cache directory
 pub fn get_cache_dir(base_path: &str) -> Result<PathBuf> {
@@ -19,6 +19,9 @@ pub struct DetNonDetSuffixes {
 
 pub const PRECOMPILE_DIR_NAME: &str = "pc";
 
+/// Extension appended to a cached artifact path to get its checksum sidecar
+pub const CHECKSUM_EXTENSION: &str = "sha3";
+
 pub const DET_NON_DET_PRECOMPILED_SUFFIX: DetNonDetSuffixes = DetNonDetSuffixes {
     det: "det",
     non_det: "non-det",
@@ -36,3 +39,112 @@ pub fn path_in_zip_to_hash(path: &str) -> String {
 
     base32::encode(base32::Alphabet::Rfc4648 { padding: false }, digits)
 }
+
+struct Sha3Hasher(sha3::Sha3_224);
+
+impl std::hash::Hasher for Sha3Hasher {
+    fn finish(&self) -> u64 {
+        unreachable!()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        use sha3::Digest;
+
+        self.0.update(bytes);
+    }
+}
+
+/// Hash of everything that affects compatibility of artifacts produced by the engine
+pub fn engine_config_hash(engine: &wasmtime::Engine) -> String {
+    use sha3::digest::FixedOutput;
+    use sha3::Digest;
+    use std::hash::Hash;
+
+    let mut hasher = Sha3Hasher(sha3::Sha3_224::new());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    let digits = hasher.0.finalize_fixed();
+
+    base32::encode(
+        base32::Alphabet::Rfc4648 { padding: false },
+        digits.as_slice(),
+    )
+}
+
+/// Path of a precompiled module: `<dir>/<path hash>.<engine hash>.<det|non-det>`
+///
+/// Engine hash also covers wasmtime version, so that artifacts of other builds are never picked up
+pub fn precompiled_module_path(
+    dir: &Path,
+    path_in_zip: &str,
+    engine_hash: &str,
+    suffix: &str,
+) -> PathBuf {
+    dir.join(format!(
+        "{}.{engine_hash}.{suffix}",
+        path_in_zip_to_hash(path_in_zip)
+    ))
+}
+
+pub fn checksum(data: &[u8]) -> String {
+    use sha3::{Digest, Sha3_256};
+
+    hex::encode(Sha3_256::digest(data))
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut res = path.as_os_str().to_owned();
+    res.push(".");
+    res.push(CHECKSUM_EXTENSION);
+    PathBuf::from(res)
+}
+
+fn write_with_sidecar(path: &Path, data: &[u8], sidecar_contents: String) -> Result<()> {
+    let sidecar = checksum_path(path);
+
+    let _ = std::fs::remove_file(&sidecar);
+    std::fs::write(path, data).with_context(|| format!("writing to {path:?}"))?;
+    std::fs::write(&sidecar, sidecar_contents)
+        .with_context(|| format!("writing to {sidecar:?}"))?;
+
+    Ok(())
+}
+
+fn read_with_sidecar(
+    path: &Path,
+    expected: impl FnOnce(&[u8]) -> String,
+) -> Result<Option<impl AsRef<[u8]>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let sidecar = checksum_path(path);
+    let recorded =
+        std::fs::read_to_string(&sidecar).with_context(|| format!("reading {sidecar:?}"))?;
+
+    let data = genvm_common::util::mmap_file(path)?;
+    if expected(data.as_ref()) != recorded.trim() {
+        anyhow::bail!("checksum mismatch for {path:?}");
+    }
+
+    Ok(Some(data))
+}
+
+/// Writes artifact with its checksum sidecar. Sidecar is written last, so that interrupted writes are detected
+pub fn write_with_checksum(path: &Path, data: &[u8]) -> Result<()> {
+    write_with_sidecar(path, data, checksum(data))
+}
+
+/// Reads artifact if it exists, fails if it does not match its checksum sidecar
+pub fn read_verified(path: &Path) -> Result<Option<impl AsRef<[u8]>>> {
+    read_with_sidecar(path, checksum)
+}
+
+/// Same as [`write_with_checksum`], sidecar also records [`engine_config_hash`] of the engine that compiled the module
+pub fn write_precompiled(path: &Path, data: &[u8], engine_hash: &str) -> Result<()> {
+    write_with_sidecar(path, data, format!("{} {engine_hash}", checksum(data)))
+}
+
+/// Same as [`read_verified`], also fails if module was compiled by an engine with a different configuration
+pub fn read_precompiled(path: &Path, engine_hash: &str) -> Result<Option<impl AsRef<[u8]>>> {
+    read_with_sidecar(path, |data| format!("{} {engine_hash}", checksum(data)))
+}
@@ -12,6 +12,81 @@ pub struct Modules {
     pub web: Module,
 }
 
+fn default_instances_per_vm() -> u32 {
+    4
+}
+
+fn default_table_elements() -> u32 {
+    65536
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Pooling instance allocator, each engine (det and non-det) gets its own pool
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Pooling {
+    /// maximum amount of VMs that exist at the same time, more VMs wait for a free slot.
+    /// Memory slots are as large as memory limiter can account for
+    pub total_vms: u32,
+    /// maximum amount of modules instantiated in a single VM, including linked ones
+    #[serde(default = "default_instances_per_vm")]
+    pub instances_per_vm: u32,
+    #[serde(default = "default_table_elements")]
+    pub table_elements: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Engine {
+    #[serde(default)]
+    pub pooling: Option<Pooling>,
+    /// initialize linear memories as copy-on-write mappings of module data
+    #[serde(default = "default_true")]
+    pub memory_init_cow: bool,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            pooling: None,
+            memory_init_cow: true,
+        }
+    }
+}
+
+fn default_cache_budget() -> Option<u64> {
+    Some(1 << 30)
+}
+
+/// Budgets in bytes of in-memory caches that are shared between executions, `null` means unbounded
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CacheLimits {
+    /// mapped runner archives
+    #[serde(default = "default_cache_budget")]
+    pub runner_archives: Option<u64>,
+    /// compiled runner modules, det and non-det are counted together
+    #[serde(default = "default_cache_budget")]
+    pub wasm_modules: Option<u64>,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            runner_archives: default_cache_budget(),
+            wasm_modules: default_cache_budget(),
+        }
+    }
+}
+
+/// Checks of runner archives, hash check is always performed
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RunnerVerification {
+    /// hex encoded ed25519 public keys, if not empty each archive must be signed by one of them
+    #[serde(default)]
+    pub trusted_signers: Vec<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Config {
     pub modules: Modules,
@@ -19,6 +94,13 @@ pub struct Config {
     pub runners_dir: String,
     pub registry_dir: String,
 
+    #[serde(default)]
+    pub engine: Engine,
+    #[serde(default)]
+    pub cache_limits: CacheLimits,
+    #[serde(default)]
+    pub runner_verification: RunnerVerification,
+
     #[serde(flatten)]
     pub base: genvm_common::BaseConfig,
 }
@@ -39,6 +121,26 @@ impl Config {
             bail!("Config Error: Critical directory paths (cache/runners) cannot be empty");
         }
 
+        if let Some(pooling) = &self.engine.pooling {
+            if pooling.total_vms == 0 || pooling.instances_per_vm == 0 {
+                bail!("Config Error: engine.pooling sizes must be positive");
+            }
+
+            // otherwise a chain of nested VMs can wait for a slot held by itself
+            if pooling.total_vms <= crate::public_abi::MAX_VM_DEPTH {
+                bail!(
+                    "Config Error: engine.pooling.total_vms must exceed {}",
+                    crate::public_abi::MAX_VM_DEPTH
+                );
+            }
+        }
+
+        for key in &self.runner_verification.trusted_signers {
+            if let Err(e) = crate::runners::verify::parse_signer(key) {
+                bail!("Config Error: runner_verification.trusted_signers: {e:#}");
+            }
+        }
+
         log_info!("Configuration validated successfully");
         Ok(())
     }
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use genvm::{config, runners};
+
+use genvm_common::*;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(help = "path to contract code, as it would be deployed")]
+    contract: std::path::PathBuf,
+    #[arg(
+        long,
+        help = "whenever to allow `:latest` and `:test` as runners version"
+    )]
+    debug_mode: bool,
+    #[arg(long, help = "report code that is larger than this amount of bytes")]
+    max_code_size: Option<u64>,
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let code =
+        std::fs::read(&args.contract).with_context(|| format!("reading {:?}", args.contract))?;
+
+    let runners = runners::cache::Reader::new(
+        std::path::Path::new(&config.runners_dir),
+        std::path::Path::new(&config.registry_dir),
+        args.debug_mode,
+        None,
+        &config.runner_verification,
+    )?;
+    let engines = genvm::rt::supervisor::create_runtime_engines(&config, None, Default::default())?;
+
+    let report = runners::check::check_contract(
+        util::SharedBytes::new(code),
+        &runners::check::Env {
+            runners: &runners,
+            engines: &engines,
+            max_code_size: args.max_code_size,
+        },
+    );
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if !report.is_ok() {
+        anyhow::bail!("{} problems found", report.problems.len());
+    }
+
+    Ok(())
+}
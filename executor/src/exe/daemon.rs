@@ -0,0 +1,308 @@
+use std::{
+    io::{Read, Write},
+    os::unix::fs::FileTypeExt,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use genvm_common::*;
+
+use anyhow::{Context, Result};
+use genvm::{config, executor, rt};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(long, help = "unix socket to accept execution requests on")]
+    socket: std::path::PathBuf,
+    #[arg(
+        long,
+        help = "whenever to allow `:latest` and `:test` as runners version, tracing, etc."
+    )]
+    debug_mode: bool,
+    #[arg(
+        long,
+        default_value_t = 1024,
+        help = "exit after this many executions, 0 for unlimited"
+    )]
+    max_executions: u64,
+    #[arg(long, help = "exit after an execution if resident memory exceeds this amount")]
+    max_memory_mb: Option<u64>,
+    #[arg(
+        long,
+        default_value_t = 64,
+        help = "reject requests larger than this amount without reading them"
+    )]
+    max_request_mb: u64,
+    #[arg(
+        long,
+        default_value_t = 30000,
+        help = "drop a client that does not send or receive data for this many milliseconds, 0 to wait forever"
+    )]
+    io_timeout_ms: u64,
+}
+
+fn default_permissions() -> String {
+    "rwscn".to_owned()
+}
+
+/// Same as arguments of `genvm run`
+#[derive(serde::Deserialize)]
+struct Request {
+    host: String,
+    execution_data: domain::ExecutionData,
+    storage_pages: u64,
+    #[serde(default = "default_permissions")]
+    permissions: String,
+    #[serde(default)]
+    sync: bool,
+    #[serde(default)]
+    genvm_id: Option<u64>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+#[derive(serde::Serialize)]
+struct Response<'a> {
+    result: Option<&'a rt::vm::FullResult>,
+    nondet_disagreement: Option<u32>,
+    error: Option<String>,
+}
+
+fn read_request(stream: &mut std::os::unix::net::UnixStream, max_size: u64) -> Result<Request> {
+    let mut len_buf = [0; 4];
+    stream.read_exact(&mut len_buf)?;
+
+    let len = u32::from_le_bytes(len_buf);
+    if len as u64 > max_size {
+        anyhow::bail!("request of {len} bytes exceeds limit of {max_size} bytes");
+    }
+
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data)?;
+
+    let value = calldata::decode(&data)?;
+    Ok(calldata::from_value(value)?)
+}
+
+fn write_response(
+    stream: &mut std::os::unix::net::UnixStream,
+    res: &Result<executor::ExecutionOutcome>,
+) -> Result<()> {
+    let response = match res {
+        Ok(outcome) => Response {
+            result: Some(&outcome.result),
+            nondet_disagreement: outcome.nondet_disagreement,
+            error: None,
+        },
+        Err(e) => Response {
+            result: None,
+            nondet_disagreement: None,
+            error: Some(format!("{e:#}")),
+        },
+    };
+
+    let data = calldata::encode(&calldata::to_value(&response)?);
+
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(&data)?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Makes accepted stream blocking, so that a stalled client fails its request after `io_timeout` instead of blocking the daemon
+fn configure_stream(
+    stream: &std::os::unix::net::UnixStream,
+    io_timeout: Option<std::time::Duration>,
+) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(io_timeout)?;
+    stream.set_write_timeout(io_timeout)?;
+
+    Ok(())
+}
+
+fn handle_connection(
+    executor: &mut genvm::Executor,
+    mut stream: std::os::unix::net::UnixStream,
+    max_request_size: u64,
+    io_timeout: Option<std::time::Duration>,
+    should_stop: &AtomicBool,
+) -> Result<()> {
+    configure_stream(&stream, io_timeout)?;
+
+    let res = read_request(&mut stream, max_request_size)
+        .with_context(|| "reading request")
+        .and_then(|req| {
+            // signal could arrive while request was read, executor is cancelled then
+            if should_stop.load(std::sync::atomic::Ordering::SeqCst) {
+                anyhow::bail!("daemon is stopping");
+            }
+
+            let overrides = executor::Overrides {
+                host: Some(executor::HostBackend::Uri(req.host)),
+                permissions: Some(req.permissions.parse()?),
+                limits: Some(executor::Limits {
+                    storage_pages: req.storage_pages,
+                    timeout: req.timeout_ms.map(std::time::Duration::from_millis),
+                }),
+                is_sync: Some(req.sync),
+                genvm_id: req.genvm_id,
+            };
+
+            executor.execute_with(req.execution_data, overrides)
+        });
+
+    if let Err(err) = &res {
+        log_error!(error:ah = err; "error running genvm");
+    }
+
+    write_response(&mut stream, &res)
+}
+
+/// Resident set size of the current process, read from procfs
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    if let Ok(meta) = std::fs::metadata(&args.socket) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{:?} exists and is not a socket", &args.socket);
+        }
+        std::fs::remove_file(&args.socket)?;
+    }
+
+    let listener = std::os::unix::net::UnixListener::bind(&args.socket)
+        .with_context(|| format!("binding {:?}", &args.socket))?;
+    listener.set_nonblocking(true)?;
+
+    let mut executor = genvm::Executor::builder(config)
+        .debug_mode(args.debug_mode)
+        .build()?;
+
+    let should_stop = Arc::new(AtomicBool::new(false));
+
+    let cancellation = executor.cancellation_handle();
+    let should_stop_copy = should_stop.clone();
+    let handle_sigterm = move || {
+        log_warn!("sigterm received");
+        should_stop_copy.store(true, std::sync::atomic::Ordering::SeqCst);
+        cancellation.cancel();
+    };
+    unsafe {
+        signal_hook::low_level::register(signal_hook::consts::SIGTERM, handle_sigterm.clone())?;
+        signal_hook::low_level::register(signal_hook::consts::SIGINT, handle_sigterm)?;
+    }
+
+    log_info!(socket:? = &args.socket; "daemon started");
+
+    let io_timeout = match args.io_timeout_ms {
+        0 => None,
+        ms => Some(std::time::Duration::from_millis(ms)),
+    };
+
+    let mut executions = 0u64;
+
+    while !should_stop.load(std::sync::atomic::Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Err(e) = handle_connection(
+            &mut executor,
+            stream,
+            args.max_request_mb.saturating_mul(1024 * 1024),
+            io_timeout,
+            &should_stop,
+        ) {
+            log_error!(error:ah = e; "failed to serve request");
+        }
+
+        executions += 1;
+
+        if should_stop.load(std::sync::atomic::Ordering::SeqCst) {
+            log_info!(executions = executions; "stop requested");
+            break;
+        }
+
+        if args.max_executions != 0 && executions >= args.max_executions {
+            log_info!(executions = executions; "execution limit reached, recycling");
+            break;
+        }
+
+        if let (Some(max_mb), Some(rss)) = (args.max_memory_mb, resident_memory_bytes()) {
+            if rss > max_mb * 1024 * 1024 {
+                log_info!(rss = rss, max_mb = max_mb; "memory limit reached, recycling");
+                break;
+            }
+        }
+    }
+
+    std::mem::drop(listener);
+    let _ = std::fs::remove_file(&args.socket);
+
+    std::mem::drop(executor);
+
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn oversized_request() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        configure_stream(&server, Some(std::time::Duration::from_secs(5))).unwrap();
+
+        // only the length is sent, body must not be awaited
+        client.write_all(&1025u32.to_le_bytes()).unwrap();
+
+        let err = read_request(&mut server, 1024).err().unwrap();
+        assert!(format!("{err:#}").contains("exceeds limit"), "{err:#}");
+    }
+
+    #[test]
+    fn stalled_client() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let timeout = std::time::Duration::from_millis(100);
+        configure_stream(&server, Some(timeout)).unwrap();
+
+        client.write_all(&16u32.to_le_bytes()).unwrap();
+        client.write_all(&[0; 4]).unwrap();
+
+        let start = std::time::Instant::now();
+        let err = read_request(&mut server, 1024).err().unwrap();
+
+        let io_err = err.downcast_ref::<std::io::Error>().unwrap();
+        assert!(matches!(
+            io_err.kind(),
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+        ));
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+        // otherwise read above could observe end of the stream instead of the timeout
+        drop(client);
+    }
+}
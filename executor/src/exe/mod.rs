@@ -1,3 +1,7 @@
+pub mod check_contract;
+pub mod daemon;
 pub mod parse_version;
 pub mod precompile;
 pub mod run;
+pub mod runners;
+pub mod symbolize;
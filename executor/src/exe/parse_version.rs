@@ -13,7 +13,7 @@ pub fn handle(_args: Args, _config: config::Config) -> Result<()> {
     std::io::stdin().read_to_end(&mut code)?;
 
     let code = util::SharedBytes::new(code);
-    let arch = genvm::runners::parse(code)?;
+    let (arch, _) = genvm::runners::parse(code)?;
     let version = arch.data.get("version");
 
     if let Some(v) = version {
@@ -1,4 +1,6 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use anyhow::{Context, Result};
 use genvm::{caching, config, runners};
@@ -13,58 +15,164 @@ pub struct Args {
         help = "instead of precompiling show information"
     )]
     info: bool,
+    #[arg(
+        long,
+        default_value_t = false,
+        help = "remove artifacts of runners that are no longer present and outdated artifacts of present ones"
+    )]
+    gc: bool,
+    #[arg(
+        long,
+        help = "amount of parallel compilations, defaults to available parallelism"
+    )]
+    jobs: Option<usize>,
 }
 
-fn compile_single_file_single_mode(
-    result_path: &std::path::Path,
-    engine: &wasmtime::Engine,
-    wasm_data: &[u8],
-    engine_type: &str,
-    runner_path: &std::path::Path,
-    path_in_runner: &str,
+/// Extension of a sidecar that holds checksum of the wasm an artifact was compiled from
+const SOURCE_EXTENSION: &str = "source";
+
+fn with_extra_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut res = path.as_os_str().to_owned();
+    res.push(".");
+    res.push(ext);
+    PathBuf::from(res)
+}
+
+struct Job {
+    result_path: PathBuf,
+    is_det: bool,
+    contents: util::SharedBytes,
+    runner_path: PathBuf,
+    path_in_runner: String,
+}
+
+#[derive(Default)]
+struct Summary {
+    compiled: AtomicU64,
+    compiled_size: AtomicU64,
+    skipped: AtomicU64,
+    skipped_size: AtomicU64,
+    removed: AtomicU64,
+    removed_size: AtomicU64,
+}
+
+impl Summary {
+    fn add(count: &AtomicU64, total_size: &AtomicU64, size: u64) {
+        count.fetch_add(1, Ordering::SeqCst);
+        total_size.fetch_add(size, Ordering::SeqCst);
+    }
+}
+
+/// Returns size of the artifact if it is intact and was compiled from the same source by an engine with the same configuration
+fn up_to_date_size(job: &Job, source_checksum: &str, engine_hash: &str) -> Option<u64> {
+    let recorded =
+        std::fs::read_to_string(with_extra_extension(&job.result_path, SOURCE_EXTENSION)).ok()?;
+    if recorded.trim() != source_checksum {
+        return None;
+    }
+
+    let data = caching::read_precompiled(&job.result_path, engine_hash).ok()??;
+    Some(data.as_ref().len() as u64)
+}
+
+fn run_job(
+    job: &Job,
+    engines: &genvm::rt::DetNondet<wasmtime::Engine>,
+    summary: &Summary,
 ) -> Result<()> {
+    let engine_type = if job.is_det {
+        caching::DET_NON_DET_PRECOMPILED_SUFFIX.det
+    } else {
+        caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det
+    };
+
+    let engine = engines.get(job.is_det);
+    let engine_hash = caching::engine_config_hash(engine);
+    let source_checksum = caching::checksum(job.contents.as_ref());
+
+    if let Some(size) = up_to_date_size(job, &source_checksum, &engine_hash) {
+        log_debug!(result:? = job.result_path, engine = engine_type, runner:? = job.runner_path, runner_path = job.path_in_runner; "artifact is up to date");
+        Summary::add(&summary.skipped, &summary.skipped_size, size);
+        return Ok(());
+    }
+
     let time_start = std::time::Instant::now();
     let precompiled = engine
-        .precompile_module(wasm_data)
+        .precompile_module(job.contents.as_ref())
         .with_context(|| "precompiling")?;
 
-    log_info!(engine = engine_type, runner:? = runner_path, runner_path:? = path_in_runner, duration:? = time_start.elapsed();  "wasm compilation done");
+    log_info!(engine = engine_type, runner:? = job.runner_path, runner_path = job.path_in_runner, duration:? = time_start.elapsed();  "wasm compilation done");
 
-    std::fs::create_dir_all(result_path.parent().unwrap())
-        .with_context(|| format!("creating directory for {result_path:?}"))?;
+    std::fs::create_dir_all(job.result_path.parent().unwrap())
+        .with_context(|| format!("creating directory for {:?}", job.result_path))?;
 
     let sz = precompiled.len();
 
-    std::fs::write(result_path, precompiled)
-        .with_context(|| format!("writing to {result_path:?}"))?;
+    caching::write_precompiled(&job.result_path, &precompiled, &engine_hash)?;
 
-    log_info!("size" = sz, result:? = result_path, engine = engine_type, runner:? = runner_path, runner_path:? = path_in_runner, duration:? = time_start.elapsed(); "wasm writing done");
+    let source_path = with_extra_extension(&job.result_path, SOURCE_EXTENSION);
+    std::fs::write(&source_path, source_checksum)
+        .with_context(|| format!("writing to {source_path:?}"))?;
+
+    log_info!("size" = sz, result:? = job.result_path, engine = engine_type, runner:? = job.runner_path, runner_path = job.path_in_runner, duration:? = time_start.elapsed(); "wasm writing done");
+
+    Summary::add(&summary.compiled, &summary.compiled_size, sz as u64);
 
     Ok(())
 }
 
-fn compile_single_file(
-    precompile_dir: &std::path::Path,
+/// Adds jobs for both engines, artifacts are keyed by hashes of the engines that compile them
+fn add_module_jobs(
+    result_dir: &Path,
     engines: &genvm::rt::DetNondet<wasmtime::Engine>,
-    runners_dir: &std::path::Path,
-    zip_path: &std::path::Path,
-) -> Result<()> {
-    let base_path = zip_path
-        .strip_prefix(runners_dir)
-        .with_context(|| format!("stripping {runners_dir:?} from {runners_dir:?}"))?;
+    runner_path: &Path,
+    entry_name: &str,
+    contents: &util::SharedBytes,
+    jobs: &mut Vec<Job>,
+) {
+    for is_det in [true, false] {
+        let suffix = if is_det {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det
+        } else {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det
+        };
 
-    let base_path = if let Some(no_stem) = base_path.file_stem() {
-        base_path.with_file_name(no_stem)
-    } else {
-        base_path.to_owned()
-    };
+        jobs.push(Job {
+            result_path: caching::precompiled_module_path(
+                result_dir,
+                entry_name,
+                &caching::engine_config_hash(engines.get(is_det)),
+                suffix,
+            ),
+            is_det,
+            contents: contents.clone(),
+            runner_path: runner_path.to_owned(),
+            path_in_runner: entry_name.to_string(),
+        });
+    }
+}
 
+/// Adds jobs for all wasm files of the runner, returns directory of its artifacts
+fn collect_jobs(
+    precompile_dir: &Path,
+    engines: &genvm::rt::DetNondet<wasmtime::Engine>,
+    runners_dir: &Path,
+    runner_id: &str,
+    runner_hash: &str,
+    jobs: &mut Vec<Job>,
+) -> Result<PathBuf> {
     let mut result_dir_path = precompile_dir.to_owned();
-    result_dir_path.push(base_path);
+    runners::append_runner_subpath(runner_id, runner_hash, &mut result_dir_path);
+
+    let Some((runner_path, format)) =
+        runners::find_runner_archive(runners_dir, runner_id, runner_hash)
+    else {
+        anyhow::bail!("runner {runner_id}:{runner_hash} not found");
+    };
 
-    let data = util::mmap_file(zip_path)?;
+    let data = runners::read_runner_archive(&runner_path, format, u32::MAX)?;
 
-    let arch = genvm::runners::Archive::from_ustar(util::SharedBytes::new(data))?;
+    let arch = genvm::runners::Archive::from_ustar(data)?;
 
     for (entry_name, contents) in arch
         .data
@@ -75,36 +183,116 @@ fn compile_single_file(
             continue;
         }
 
-        let entry_name_hash = caching::path_in_zip_to_hash(entry_name);
-        let result_file = result_dir_path.join(entry_name_hash);
-
-        compile_single_file_single_mode(
-            result_file
-                .with_extension(caching::DET_NON_DET_PRECOMPILED_SUFFIX.det)
-                .as_path(),
-            &engines.det,
-            contents.as_ref(),
-            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det,
-            zip_path,
-            entry_name,
-        )
-        .with_context(|| format!("processing det {entry_name}"))?;
-
-        compile_single_file_single_mode(
-            result_file
-                .with_extension(caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det)
-                .as_path(),
-            &engines.non_det,
-            contents.as_ref(),
-            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det,
-            zip_path,
+        add_module_jobs(
+            &result_dir_path,
+            engines,
+            &runner_path,
             entry_name,
-        )
-        .with_context(|| format!("processing non-det {entry_name}"))?;
+            contents,
+            jobs,
+        );
+    }
+
+    Ok(result_dir_path)
+}
+
+fn remove_counted(path: &Path, summary: &Summary) -> Result<()> {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    std::fs::remove_file(path).with_context(|| format!("removing {path:?}"))?;
+
+    log_info!(path:? = path, size = size; "removed");
+
+    Summary::add(&summary.removed, &summary.removed_size, size);
+    Ok(())
+}
+
+fn remove_dir_counted(path: &Path, summary: &Summary) -> Result<()> {
+    for entry in std::fs::read_dir(path).with_context(|| format!("reading {path:?}"))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            remove_dir_counted(&entry.path(), summary)?;
+        } else {
+            remove_counted(&entry.path(), summary)?;
+        }
+    }
+
+    std::fs::remove_dir(path).with_context(|| format!("removing {path:?}"))?;
+    Ok(())
+}
+
+fn sub_dirs(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut res = Vec::new();
+
+    if !path.exists() {
+        return Ok(res);
+    }
+
+    for entry in std::fs::read_dir(path).with_context(|| format!("reading {path:?}"))? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            res.push(entry.path());
+        }
+    }
+
+    Ok(res)
+}
+
+/// Precompile directory mirrors runners directory as `<id>/<hash prefix>/<hash rest>/`
+fn collect_garbage(
+    precompile_dir: &Path,
+    runners_dir: &Path,
+    expected: &BTreeMap<PathBuf, BTreeSet<std::ffi::OsString>>,
+    summary: &Summary,
+) -> Result<()> {
+    for id_dir in sub_dirs(precompile_dir)? {
+        for prefix_dir in sub_dirs(&id_dir)? {
+            for runner_dir in sub_dirs(&prefix_dir)? {
+                let rel = runner_dir.strip_prefix(precompile_dir)?;
+                let runner_path = runners_dir.join(rel);
+
+                let present = runners::ArchiveFormat::ALL
+                    .into_iter()
+                    .any(|format| runner_path.with_extension(format.extension()).exists());
+                if !present {
+                    remove_dir_counted(&runner_dir, summary)?;
+                    continue;
+                }
+
+                let Some(expected_files) = expected.get(&runner_dir) else {
+                    continue;
+                };
+
+                for entry in std::fs::read_dir(&runner_dir)? {
+                    let entry = entry?;
+                    let name = entry.file_name();
+
+                    // snapshots are keyed by initialization actions, which are not known here
+                    if expected_files.contains(&name) || name.to_string_lossy().ends_with(".snap") {
+                        continue;
+                    }
+
+                    if entry.file_type()?.is_dir() {
+                        remove_dir_counted(&entry.path(), summary)?;
+                    } else {
+                        remove_counted(&entry.path(), summary)?;
+                    }
+                }
+            }
+
+            // fails if directory is not empty
+            let _ = std::fs::remove_dir(&prefix_dir);
+        }
+
+        let _ = std::fs::remove_dir(&id_dir);
     }
+
     Ok(())
 }
 
+fn format_size(size: u64) -> String {
+    format!("{:.1} MiB", size as f64 / (1024.0 * 1024.0))
+}
+
 pub fn handle(args: Args, config: config::Config) -> Result<()> {
     log_info!(version = genvm_common::version::CURRENT.clone(); "current version");
 
@@ -119,10 +307,8 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
     if args.info {
         return Ok(());
     }
-    let engines = genvm::rt::supervisor::create_engines(|conf| {
-        conf.cranelift_opt_level(wasmtime::OptLevel::Speed);
-        Ok(())
-    })?;
+    // artifacts are looked up by hashes of engines used for execution, so they must be compiled by the same ones
+    let engines = genvm::rt::supervisor::create_runtime_engines(&config, None, Default::default())?;
 
     let all_json_path = registry_dir.join("all.json");
     let all_json = std::fs::read_to_string(&all_json_path)
@@ -131,16 +317,203 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
 
     let runners_dir = std::path::Path::new(&config.runners_dir);
 
+    let mut jobs = Vec::new();
+    let mut expected: BTreeMap<PathBuf, BTreeSet<std::ffi::OsString>> = BTreeMap::new();
+
     for (runner_id, hashes) in all {
         for hash in hashes {
-            let mut runner_path = runners_dir.to_owned();
-            runners::append_runner_subpath(&runner_id, &hash, &mut runner_path);
-            runner_path.set_extension("tar");
+            let first_job = jobs.len();
 
-            compile_single_file(&precompile_dir, &engines, runners_dir, &runner_path)
-                .with_context(|| format!("processing {runner_path:?}"))?;
+            let result_dir = collect_jobs(
+                &precompile_dir,
+                &engines,
+                runners_dir,
+                &runner_id,
+                &hash,
+                &mut jobs,
+            )
+            .with_context(|| format!("processing {runner_id}:{hash}"))?;
+
+            let expected_files = expected.entry(result_dir).or_default();
+            for job in &jobs[first_job..] {
+                let artifact = &job.result_path;
+                for path in [
+                    artifact.clone(),
+                    with_extra_extension(artifact, caching::CHECKSUM_EXTENSION),
+                    with_extra_extension(artifact, SOURCE_EXTENSION),
+                ] {
+                    expected_files.insert(path.file_name().unwrap().to_owned());
+                }
+            }
         }
     }
 
+    let threads = args
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()))
+        .clamp(1, jobs.len().max(1));
+
+    log_info!(jobs = jobs.len(), threads = threads; "precompiling");
+
+    let summary = Summary::default();
+    let next_job = AtomicUsize::new(0);
+
+    let errors: Vec<anyhow::Error> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    while let Some(job) = jobs.get(next_job.fetch_add(1, Ordering::SeqCst)) {
+                        run_job(job, &engines, &summary).with_context(|| {
+                            format!("processing {:?} {}", job.runner_path, job.path_in_runner)
+                        })?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .filter_map(|w| match w.join() {
+                Ok(res) => res.err(),
+                Err(_) => Some(anyhow::anyhow!("precompilation thread panicked")),
+            })
+            .collect()
+    });
+
+    if let Some(err) = errors.into_iter().next() {
+        return Err(err);
+    }
+
+    if args.gc {
+        collect_garbage(&precompile_dir, runners_dir, &expected, &summary)?;
+    }
+
+    let load = |x: &AtomicU64| x.load(Ordering::SeqCst);
+
+    println!(
+        "compiled: {} ({})",
+        load(&summary.compiled),
+        format_size(load(&summary.compiled_size))
+    );
+    println!(
+        "skipped: {} ({})",
+        load(&summary.skipped),
+        format_size(load(&summary.skipped_size))
+    );
+    if args.gc {
+        println!(
+            "removed: {} ({})",
+            load(&summary.removed),
+            format_size(load(&summary.removed_size))
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile_all(
+        dir: &Path,
+        engines: &genvm::rt::DetNondet<wasmtime::Engine>,
+        summary: &Summary,
+    ) -> Vec<Job> {
+        let wasm =
+            util::SharedBytes::from(wat::parse_str(r#"(module (func (export "f")))"#).unwrap());
+
+        let mut jobs = Vec::new();
+        add_module_jobs(
+            dir,
+            engines,
+            Path::new("runner.tar"),
+            "a.wasm",
+            &wasm,
+            &mut jobs,
+        );
+        for job in &jobs {
+            run_job(job, engines, summary).unwrap();
+        }
+
+        jobs
+    }
+
+    #[test]
+    fn engine_config_change_recompiles() {
+        let dir = std::env::temp_dir().join(format!("genvm-precompile-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let load = |x: &AtomicU64| x.load(Ordering::SeqCst);
+        let summary = Summary::default();
+
+        let engines = genvm::rt::supervisor::create_engines(|_| Ok(())).unwrap();
+        compile_all(&dir, &engines, &summary);
+        compile_all(&dir, &engines, &summary);
+        assert_eq!(load(&summary.compiled), 2);
+        assert_eq!(load(&summary.skipped), 2);
+
+        let changed = genvm::rt::supervisor::create_engines(|conf| {
+            conf.cranelift_opt_level(wasmtime::OptLevel::Speed);
+            Ok(())
+        })
+        .unwrap();
+        let jobs = compile_all(&dir, &changed, &summary);
+        assert_eq!(load(&summary.compiled), 4);
+        assert_eq!(load(&summary.skipped), 2);
+
+        // artifact found by a hash can be loaded by the engine with that hash
+        for job in &jobs {
+            let data = caching::read_precompiled(
+                &job.result_path,
+                &caching::engine_config_hash(changed.get(job.is_det)),
+            )
+            .unwrap()
+            .unwrap();
+            unsafe { wasmtime::Module::deserialize(changed.get(job.is_det), data) }.unwrap();
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn artifact_of_other_engine_is_rejected() {
+        let dir = std::env::temp_dir().join(format!(
+            "genvm-precompile-other-engine-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let summary = Summary::default();
+
+        let engines = genvm::rt::supervisor::create_engines(|_| Ok(())).unwrap();
+        let changed = genvm::rt::supervisor::create_engines(|conf| {
+            conf.cranelift_opt_level(wasmtime::OptLevel::Speed);
+            Ok(())
+        })
+        .unwrap();
+
+        let jobs = compile_all(&dir, &engines, &summary);
+        let other_jobs = compile_all(&dir, &changed, &summary);
+
+        // artifact of another engine placed under the key of this one
+        for (job, other) in jobs.iter().zip(&other_jobs) {
+            std::fs::copy(&other.result_path, &job.result_path).unwrap();
+            std::fs::copy(
+                with_extra_extension(&other.result_path, caching::CHECKSUM_EXTENSION),
+                with_extra_extension(&job.result_path, caching::CHECKSUM_EXTENSION),
+            )
+            .unwrap();
+
+            let engine_hash = caching::engine_config_hash(engines.get(job.is_det));
+            assert!(caching::read_precompiled(&job.result_path, &engine_hash).is_err());
+        }
+
+        compile_all(&dir, &engines, &summary);
+        assert_eq!(summary.compiled.load(Ordering::SeqCst), 6);
+        assert_eq!(summary.skipped.load(Ordering::SeqCst), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -18,6 +18,8 @@ enum PrintOption {
     Result,
     Fingerprint,
     StderrFull,
+    /// stdout and stderr of contracts, requires `--debug-mode`
+    Output,
 }
 
 impl std::fmt::Display for PrintOption {
@@ -54,6 +56,62 @@ pub struct Args {
         help = "r?w?s?c?n?, read/write/send messages/call contracts/spawn nondet"
     )]
     permissions: String,
+    #[clap(
+        long,
+        help = "write guest profile of each VM in Firefox profiler format, requires `--debug-mode`"
+    )]
+    profile: Option<std::path::PathBuf>,
+    #[clap(
+        long,
+        help = "wait for Debug Adapter Protocol client on this local port, requires `--debug-mode`"
+    )]
+    dap_port: Option<u16>,
+}
+
+fn print_output(output: &[rt::vm::VMOutput]) {
+    for (i, vm) in output.iter().enumerate() {
+        let mode = if vm.is_deterministic { "det" } else { "nondet" };
+        let truncated = if vm.truncated { ", truncated" } else { "" };
+        println!(
+            "vm #{i} {} {} {mode}{truncated}",
+            vm.contract,
+            vm.entry_kind.str_snake_case()
+        );
+
+        for (name, text) in [("stdout", &vm.stdout), ("stderr", &vm.stderr)] {
+            if text.is_empty() {
+                continue;
+            }
+            println!("--- {name}");
+            print!("{text}");
+            if !text.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+}
+
+/// First VM is written to `path`, others to `path.N` where `N` is the index of VM
+fn write_profiles(path: &std::path::Path, profiles: &[rt::profiling::VMProfile]) -> Result<()> {
+    for (i, profile) in profiles.iter().enumerate() {
+        let mut vm_path = path.as_os_str().to_owned();
+        if i != 0 {
+            vm_path.push(format!(".{i}"));
+        }
+        let vm_path = std::path::PathBuf::from(vm_path);
+
+        std::fs::write(&vm_path, &profile.json).with_context(|| format!("writing {vm_path:?}"))?;
+
+        log_info!(
+            path:? = vm_path,
+            contract:? = profile.contract,
+            entry_kind = profile.entry_kind.str_snake_case(),
+            is_deterministic = profile.is_deterministic;
+            "profile written"
+        );
+    }
+
+    Ok(())
 }
 
 pub fn handle(args: Args, config: config::Config) -> Result<()> {
@@ -75,69 +133,47 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
 
     let execution_data = calldata::decode(&execution_data_bytes)?;
     let execution_data = calldata::from_value::<domain::ExecutionData>(execution_data)?;
-    let message = &execution_data.message;
-    let host_data = rt::parse_host_data(&execution_data)?;
 
-    let runtime = config.base.create_rt()?;
+    let permissions: genvm::Permissions = args.permissions.parse()?;
 
-    let (token, canceller) = genvm_common::cancellation::make();
+    if args.print.contains(&PrintOption::Output) && !args.debug_mode {
+        log_warn!("output of contracts is captured only in debug mode");
+    }
 
+    let mut builder = genvm::Executor::builder(config)
+        .host_uri(args.host)
+        .permissions(permissions)
+        .limits(genvm::executor::Limits {
+            storage_pages: args.storage_pages,
+            ..Default::default()
+        })
+        .debug_mode(args.debug_mode)
+        .sync(args.sync);
+
+    if args.profile.is_some() {
+        builder = builder.profile(rt::profiling::DEFAULT_INTERVAL);
+    }
+    if let Some(port) = args.dap_port {
+        builder = builder.dap_port(port);
+    }
+
+    if let Some(genvm_id) = args.genvm_id {
+        builder = builder.genvm_id(genvm_id);
+    }
+
+    let mut executor = builder.build()?;
+
+    let cancellation = executor.cancellation_handle();
     let handle_sigterm = move || {
         log_warn!("sigterm received");
-        canceller();
+        cancellation.cancel();
     };
     unsafe {
         signal_hook::low_level::register(signal_hook::consts::SIGTERM, handle_sigterm.clone())?;
         signal_hook::low_level::register(signal_hook::consts::SIGINT, handle_sigterm)?;
     }
 
-    let genvm_id = match &args.genvm_id {
-        None => {
-            let mut random_bytes = [0; 8];
-            let _ = getrandom::fill(&mut random_bytes);
-            u64::from_le_bytes(random_bytes)
-        }
-        Some(v) => *v,
-    };
-
-    let shared_data = sync::DArc::new(genvm::rt::SharedData {
-        cancellation: token,
-        is_sync: args.sync,
-        genvm_id: genvm_modules_interfaces::GenVMId(genvm_id),
-        debug_mode: args.debug_mode,
-        metrics: genvm::Metrics::default(),
-        storage_pages_limit: std::sync::atomic::AtomicU64::new(args.storage_pages),
-    });
-
-    let host = genvm::Host::connect(&args.host, shared_data.gep(|x| &x.metrics.host))?;
-
-    let mut perm_size = 0;
-    for perm in ["r", "w", "s", "c", "n"] {
-        if args.permissions.contains(perm) {
-            perm_size += 1;
-        }
-    }
-
-    if perm_size != args.permissions.len() {
-        anyhow::bail!("Invalid permissions {}", &args.permissions)
-    }
-
-    log_info!(genvm_id = genvm_id; "genvm id");
-
-    let rt = runtime.enter();
-
-    let supervisor = genvm::create_supervisor(&config, host, host_data, shared_data, message)
-        .with_context(|| "creating supervisor")?;
-
-    std::mem::drop(rt);
-
-    let res = runtime
-        .block_on(genvm::run_with(
-            execution_data,
-            supervisor.clone(),
-            &args.permissions,
-        ))
-        .with_context(|| "running genvm");
+    let res = executor.execute(execution_data);
 
     if let Err(err) = &res {
         log_error!(error:ah = err; "error running genvm");
@@ -149,7 +185,8 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
 
     if args.print.contains(&PrintOption::Result) {
         match &res {
-            Ok((res, nondet)) => {
+            Ok(outcome) => {
+                let res = &outcome.result;
                 match res.kind {
                     genvm::public_abi::ResultCode::VmError => {
                         println!("executed with `VMError({})`", res.data);
@@ -162,7 +199,7 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
                     }
                     _ => {}
                 }
-                if let Some(disag) = nondet {
+                if let Some(disag) = outcome.nondet_disagreement {
                     println!("nondet disagreement: {disag}");
                 }
             }
@@ -174,20 +211,29 @@ pub fn handle(args: Args, config: config::Config) -> Result<()> {
     }
 
     if args.print.contains(&PrintOption::Fingerprint) {
-        if let Ok((rt::vm::FullResult { fingerprint, .. }, _)) = &res {
+        if let Ok(genvm::ExecutionOutcome {
+            result: rt::vm::FullResult { fingerprint, .. },
+            ..
+        }) = &res
+        {
             println!("Fingerprint: {fingerprint:?}");
         }
     }
 
-    runtime.block_on(async {
-        supervisor.modules.llm.close().await;
-        supervisor.modules.web.close().await;
-    });
+    if args.print.contains(&PrintOption::Output) {
+        if let Ok(outcome) = &res {
+            print_output(&outcome.result.output);
+        }
+    }
+
+    if let (Some(path), Ok(outcome)) = (&args.profile, &res) {
+        write_profiles(path, &outcome.profiles)?;
+    }
 
     let _ = std::io::stdout().flush();
     let _ = std::io::stderr().flush();
 
-    runtime.shutdown_timeout(std::time::Duration::from_millis(30));
+    std::mem::drop(executor);
 
     let _ = std::io::stdout().flush();
     let _ = std::io::stderr().flush();
@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use genvm::{config, runners};
+
+use genvm_common::*;
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// print ids, hashes, sizes (on disk) and versions of installed runners
+    List,
+    /// print initialization actions of a runner
+    Show {
+        #[arg(help = "runner id in `<id>:<hash>` or `<id>:latest` form")]
+        runner: String,
+    },
+    /// check hashes and signatures of runners and that their archives parse
+    Verify {
+        #[arg(help = "runner to verify, all runners if absent")]
+        runner: Option<String>,
+    },
+    /// print dependency graph in DOT format, fails if it has cycles
+    Graph {
+        #[arg(help = "runner to start from, all runners if absent")]
+        runner: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+struct Registry {
+    runners_dir: PathBuf,
+    all: BTreeMap<String, Vec<String>>,
+    latest: BTreeMap<String, String>,
+}
+
+struct Runner {
+    version: version::Version,
+    actions: runners::InitAction,
+}
+
+impl Registry {
+    fn new(config: &config::Config) -> Result<Self> {
+        let registry_dir = Path::new(&config.registry_dir);
+
+        let read = |name: &str| -> Result<String> {
+            let path = registry_dir.join(name);
+            std::fs::read_to_string(&path).with_context(|| format!("reading {path:?}"))
+        };
+
+        let all = serde_json::from_str(&read("all.json")?)?;
+
+        // latest.json is present only in debug installations
+        let latest = match read("latest.json") {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(_) => BTreeMap::new(),
+        };
+
+        Ok(Self {
+            runners_dir: PathBuf::from(&config.runners_dir),
+            all,
+            latest,
+        })
+    }
+
+    fn all_runners(&self) -> Vec<(String, String)> {
+        self.all
+            .iter()
+            .flat_map(|(id, hashes)| hashes.iter().map(|h| (id.clone(), h.clone())))
+            .collect()
+    }
+
+    /// Resolves `:latest` and `:test` the same way execution does
+    fn resolve(&self, uid: &str) -> Result<(String, String)> {
+        let Some((id, hash)) = runners::verify_runner(uid) else {
+            anyhow::bail!("invalid runner id: {uid}");
+        };
+
+        let hash = if hash == "latest" || hash == "test" {
+            self.latest
+                .get(id)
+                .with_context(|| format!("no latest version of {id}"))?
+                .as_str()
+        } else {
+            hash
+        };
+
+        Ok((id.to_owned(), hash.to_owned()))
+    }
+
+    fn is_latest(&self, id: &str, hash: &str) -> bool {
+        self.latest.get(id).is_some_and(|x| x == hash)
+    }
+
+    fn archive_path(&self, id: &str, hash: &str) -> Result<(PathBuf, runners::ArchiveFormat)> {
+        runners::find_runner_archive(&self.runners_dir, id, hash)
+            .with_context(|| format!("runner {id}:{hash} not found"))
+    }
+
+    fn load(&self, id: &str, hash: &str) -> Result<Runner> {
+        let (path, format) = self.archive_path(id, hash)?;
+        let data = runners::read_runner_archive(&path, format, u32::MAX)?;
+        let arch = runners::Archive::from_ustar(data)?;
+
+        let arch = runners::ArchiveCache::new(
+            symbol_table::GlobalSymbol::from(format!("{id}:{hash}")),
+            arch,
+        );
+
+        let version = arch.get_version()?;
+        let actions = serde_json::from_slice(arch.get_file("runner.json")?.as_ref())
+            .with_context(|| format!("parsing runner.json of {id}:{hash}"))?;
+
+        Ok(Runner { version, actions })
+    }
+}
+
+fn list(registry: &Registry) -> Result<()> {
+    for (id, hash) in registry.all_runners() {
+        let size = match registry
+            .archive_path(&id, &hash)
+            .and_then(|(path, _)| Ok(std::fs::metadata(path)?))
+        {
+            Ok(meta) => meta.len().to_string(),
+            Err(_) => "missing".to_owned(),
+        };
+
+        let version = match registry.load(&id, &hash) {
+            Ok(runner) => runner.version.to_string(),
+            Err(e) => {
+                log_warn!(error:ah = e, runner = format!("{id}:{hash}"); "failed to load runner");
+                "?".to_owned()
+            }
+        };
+
+        let latest = if registry.is_latest(&id, &hash) {
+            " latest"
+        } else {
+            ""
+        };
+
+        println!("{id}:{hash}\t{size}\t{version}{latest}");
+    }
+
+    Ok(())
+}
+
+fn print_action(out: &mut String, action: &runners::InitAction, depth: usize) {
+    let _ = write!(out, "{:width$}", "", width = depth * 2);
+
+    match action {
+        runners::InitAction::MapFile { to, file } => {
+            let _ = writeln!(out, "MapFile {file} -> {to}");
+        }
+        runners::InitAction::AddEnv { name, val } => {
+            let _ = writeln!(out, "AddEnv {name}={val}");
+        }
+        runners::InitAction::SetArgs(args) => {
+            let _ = writeln!(out, "SetArgs {args:?}");
+        }
+        runners::InitAction::Depends(runner) => {
+            let _ = writeln!(out, "Depends {runner}");
+        }
+        runners::InitAction::LinkWasm(path) => {
+            let _ = writeln!(out, "LinkWasm {path}");
+        }
+        runners::InitAction::StartWasm(path) => {
+            let _ = writeln!(out, "StartWasm {path}");
+        }
+        runners::InitAction::When { cond, action } => {
+            let _ = writeln!(out, "When {cond:?}");
+            print_action(out, action, depth + 1);
+        }
+        runners::InitAction::WhenVersion { min, max, action } => {
+            let bound = |x: &Option<version::Version>| x.map_or("*".to_owned(), |x| x.to_string());
+            let _ = writeln!(out, "WhenVersion {}..={}", bound(min), bound(max));
+            print_action(out, action, depth + 1);
+        }
+        runners::InitAction::Fail { message } => {
+            let _ = writeln!(out, "Fail {message:?}");
+        }
+        runners::InitAction::Seq(actions) => {
+            let _ = writeln!(out, "Seq");
+            for action in actions {
+                print_action(out, action, depth + 1);
+            }
+        }
+        runners::InitAction::With { runner, action } => {
+            let _ = writeln!(out, "With {runner}");
+            print_action(out, action, depth + 1);
+        }
+    }
+}
+
+fn show(registry: &Registry, uid: &str) -> Result<()> {
+    let (id, hash) = registry.resolve(uid)?;
+    let runner = registry.load(&id, &hash)?;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{id}:{hash}");
+    let _ = writeln!(out, "version: {}", runner.version);
+    print_action(&mut out, &runner.actions, 0);
+
+    print!("{out}");
+
+    Ok(())
+}
+
+fn verify(registry: &Registry, config: &config::Config, uid: Option<&str>) -> Result<()> {
+    let verifier = runners::verify::Verifier::new(&config.runner_verification)?;
+
+    let to_check = match uid {
+        Some(uid) => vec![registry.resolve(uid)?],
+        None => registry.all_runners(),
+    };
+
+    let mut failed = 0;
+
+    for (id, hash) in &to_check {
+        let uid = format!("{id}:{hash}");
+
+        let res = (|| -> Result<()> {
+            let (path, format) = registry.archive_path(id, hash)?;
+            let data = runners::read_runner_archive(&path, format, u32::MAX)?;
+            verifier.verify(
+                symbol_table::GlobalSymbol::from(uid.as_str()),
+                hash,
+                &path,
+                data.as_ref(),
+            )?;
+            registry.load(id, hash)?;
+            Ok(())
+        })();
+
+        match res {
+            Ok(()) => println!("ok\t{uid}"),
+            Err(e) => {
+                failed += 1;
+                println!("failed\t{uid}\t{e:#}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} runners failed verification", to_check.len());
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum EdgeKind {
+    Depends,
+    With,
+}
+
+fn collect_edges(action: &runners::InitAction, edges: &mut Vec<(EdgeKind, String)>) {
+    match action {
+        runners::InitAction::Depends(runner) => {
+            edges.push((EdgeKind::Depends, runner.as_str().to_owned()));
+        }
+        runners::InitAction::With { runner, action } => {
+            edges.push((EdgeKind::With, runner.as_str().to_owned()));
+            collect_edges(action, edges);
+        }
+        runners::InitAction::When { action, .. }
+        | runners::InitAction::WhenVersion { action, .. } => collect_edges(action, edges),
+        runners::InitAction::Seq(actions) => {
+            for action in actions {
+                collect_edges(action, edges);
+            }
+        }
+        runners::InitAction::MapFile { .. }
+        | runners::InitAction::AddEnv { .. }
+        | runners::InitAction::SetArgs(_)
+        | runners::InitAction::LinkWasm(_)
+        | runners::InitAction::StartWasm(_)
+        | runners::InitAction::Fail { .. } => {}
+    }
+}
+
+/// Returns a cycle if graph has one, nodes are visited in order
+fn find_cycle(graph: &BTreeMap<String, Vec<(EdgeKind, String)>>) -> Option<Vec<String>> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        InProgress,
+        Done,
+    }
+
+    fn visit(
+        node: &str,
+        graph: &BTreeMap<String, Vec<(EdgeKind, String)>>,
+        state: &mut BTreeMap<String, State>,
+        stack: &mut Vec<String>,
+    ) -> Option<Vec<String>> {
+        match state.get(node) {
+            Some(State::Done) => return None,
+            Some(State::InProgress) => {
+                let start = stack.iter().position(|x| x == node).unwrap();
+                let mut cycle = stack[start..].to_vec();
+                cycle.push(node.to_owned());
+                return Some(cycle);
+            }
+            None => {}
+        }
+
+        state.insert(node.to_owned(), State::InProgress);
+        stack.push(node.to_owned());
+
+        for (_, next) in graph.get(node).into_iter().flatten() {
+            if let Some(cycle) = visit(next, graph, state, stack) {
+                return Some(cycle);
+            }
+        }
+
+        stack.pop();
+        state.insert(node.to_owned(), State::Done);
+
+        None
+    }
+
+    let mut state = BTreeMap::new();
+    let mut stack = Vec::new();
+
+    graph
+        .keys()
+        .find_map(|node| visit(node, graph, &mut state, &mut stack))
+}
+
+fn graph(registry: &Registry, uid: Option<&str>) -> Result<()> {
+    let mut queue = match uid {
+        Some(uid) => {
+            let (id, hash) = registry.resolve(uid)?;
+            vec![format!("{id}:{hash}")]
+        }
+        None => registry
+            .all_runners()
+            .into_iter()
+            .map(|(id, hash)| format!("{id}:{hash}"))
+            .collect(),
+    };
+
+    let mut graph: BTreeMap<String, Vec<(EdgeKind, String)>> = BTreeMap::new();
+
+    while let Some(node) = queue.pop() {
+        if graph.contains_key(&node) {
+            continue;
+        }
+
+        let (id, hash) = registry.resolve(&node)?;
+        let runner = registry
+            .load(&id, &hash)
+            .with_context(|| format!("loading {node}"))?;
+
+        let mut edges = Vec::new();
+        collect_edges(&runner.actions, &mut edges);
+
+        for edge in &mut edges {
+            // contract is not a part of the registry
+            if edge.1 == "<contract>" {
+                continue;
+            }
+            let (id, hash) = registry.resolve(&edge.1)?;
+            edge.1 = format!("{id}:{hash}");
+            queue.push(edge.1.clone());
+        }
+
+        graph.insert(node, edges);
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph runners {{");
+    for (node, edges) in &graph {
+        let _ = writeln!(out, "  {node:?};");
+        for (kind, next) in edges {
+            match kind {
+                EdgeKind::Depends => {
+                    let _ = writeln!(out, "  {node:?} -> {next:?};");
+                }
+                EdgeKind::With => {
+                    let _ = writeln!(
+                        out,
+                        "  {node:?} -> {next:?} [style=dashed, label=\"with\"];"
+                    );
+                }
+            }
+        }
+    }
+    let _ = writeln!(out, "}}");
+
+    print!("{out}");
+
+    if let Some(cycle) = find_cycle(&graph) {
+        anyhow::bail!("dependency cycle: {}", cycle.join(" -> "));
+    }
+
+    Ok(())
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let registry = Registry::new(&config)?;
+
+    match args.command {
+        Command::List => list(&registry),
+        Command::Show { runner } => show(&registry, &runner),
+        Command::Verify { runner } => verify(&registry, &config, runner.as_deref()),
+        Command::Graph { runner } => graph(&registry, runner.as_deref()),
+    }
+}
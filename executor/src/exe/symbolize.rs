@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use genvm::{config, rt, runners};
+
+use genvm_common::*;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[arg(
+        help = "path to fingerprint in json, for instance `fp` of `captured fingerprint` log entry"
+    )]
+    fingerprint: std::path::PathBuf,
+    #[arg(
+        long,
+        help = "path to code of the contract that produced the fingerprint"
+    )]
+    contract: std::path::PathBuf,
+    #[arg(
+        long,
+        help = "whenever to allow `:latest` and `:test` as runners version"
+    )]
+    debug_mode: bool,
+}
+
+/// Only frames are needed, memories of module instances are ignored
+#[derive(serde::Deserialize)]
+struct RecordedFingerprint {
+    frames: Vec<rt::errors::Frame>,
+}
+
+pub fn handle(args: Args, config: config::Config) -> Result<()> {
+    let fingerprint = std::fs::read(&args.fingerprint)
+        .with_context(|| format!("reading {:?}", args.fingerprint))?;
+    let fingerprint: RecordedFingerprint = serde_json::from_slice(&fingerprint)?;
+
+    let code =
+        std::fs::read(&args.contract).with_context(|| format!("reading {:?}", args.contract))?;
+
+    let runners = runners::cache::Reader::new(
+        std::path::Path::new(&config.runners_dir),
+        std::path::Path::new(&config.registry_dir),
+        args.debug_mode,
+        None,
+        &config.runner_verification,
+    )?;
+    let engines = rt::supervisor::create_runtime_engines(&config, None, Default::default())?;
+
+    let report = runners::check::check_contract(
+        util::SharedBytes::new(code),
+        &runners::check::Env {
+            runners: &runners,
+            engines: &engines,
+            max_code_size: None,
+        },
+    );
+
+    if !report.is_ok() {
+        log_warn!(problems = report.problems.len(); "contract has problems, some modules may be missing");
+    }
+
+    // fingerprints are captured only in deterministic mode, so its modules take precedence
+    let mut modules: BTreeMap<&str, &runners::check::Module> = BTreeMap::new();
+    for mode in [runners::WasmMode::Det, runners::WasmMode::Nondet] {
+        for module in report.modules.iter().filter(|m| m.mode == mode) {
+            if let Some(name) = &module.name {
+                modules.entry(name.as_str()).or_insert(module);
+            }
+        }
+    }
+
+    for (i, frame) in fingerprint.frames.iter().enumerate() {
+        let module = modules.get(frame.module_name.as_str());
+
+        let func_name = module
+            .and_then(|m| m.functions.get(&frame.func))
+            .or(frame.func_name.as_ref());
+
+        let func = match func_name {
+            Some(name) => format!("{name} (func {})", frame.func),
+            None => format!("<func {}>", frame.func),
+        };
+        let module_name = if frame.module_name.is_empty() {
+            "<unnamed>"
+        } else {
+            &frame.module_name
+        };
+
+        println!("{i:>4}: {module_name}!{func}");
+
+        if let Some(module) = module {
+            println!("      in {}/{}", module.runner, module.path);
+        }
+        if let Some(location) = &frame.location {
+            println!("      at {location}");
+        }
+    }
+
+    Ok(())
+}
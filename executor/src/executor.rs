@@ -0,0 +1,504 @@
+//! Embedding API: configure once with [`Executor::builder`], then [`Executor::execute`] transactions
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use genvm_common::*;
+
+use crate::{config, host, rt};
+
+/// Permissions of the entrypoint contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read_storage: bool,
+    pub write_storage: bool,
+    pub send_messages: bool,
+    pub call_others: bool,
+    pub spawn_nondet: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            read_storage: true,
+            write_storage: true,
+            send_messages: true,
+            call_others: true,
+            spawn_nondet: true,
+        }
+    }
+}
+
+impl Permissions {
+    pub fn none() -> Self {
+        Self {
+            read_storage: false,
+            write_storage: false,
+            send_messages: false,
+            call_others: false,
+            spawn_nondet: false,
+        }
+    }
+}
+
+/// Parses `r?w?s?c?n?` format, which is used by the command line interface
+impl std::str::FromStr for Permissions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut res = Self::none();
+
+        for c in s.chars() {
+            let flag = match c {
+                'r' => &mut res.read_storage,
+                'w' => &mut res.write_storage,
+                's' => &mut res.send_messages,
+                'c' => &mut res.call_others,
+                'n' => &mut res.spawn_nondet,
+                _ => anyhow::bail!("Invalid permissions {s}"),
+            };
+
+            if *flag {
+                anyhow::bail!("Invalid permissions {s}");
+            }
+            *flag = true;
+        }
+
+        Ok(res)
+    }
+}
+
+impl std::fmt::Display for Permissions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (flag, c) in [
+            (self.read_storage, 'r'),
+            (self.write_storage, 'w'),
+            (self.send_messages, 's'),
+            (self.call_others, 'c'),
+            (self.spawn_nondet, 'n'),
+        ] {
+            if flag {
+                f.write_fmt(format_args!("{c}"))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// max amount of storage pages to be written
+    pub storage_pages: u64,
+    /// transaction is cancelled after this duration
+    pub timeout: Option<std::time::Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            storage_pages: u64::MAX,
+            timeout: None,
+        }
+    }
+}
+
+pub enum HostBackend {
+    /// `unix://` or tcp address
+    Uri(String),
+    /// already established connection, can be used for a single execution
+    Sock(Box<dyn host::Sock>),
+}
+
+/// Per-execution replacements of values set in [`ExecutorBuilder`]
+#[derive(Default)]
+pub struct Overrides {
+    pub host: Option<HostBackend>,
+    pub permissions: Option<Permissions>,
+    pub limits: Option<Limits>,
+    pub is_sync: Option<bool>,
+    pub genvm_id: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct ExecutionOutcome {
+    pub result: rt::vm::FullResult,
+    /// call number of the first nondet block validator disagreed with
+    pub nondet_disagreement: Option<u32>,
+    /// guest profiles of all VMs, collected only if [`ExecutorBuilder::profile`] is set
+    pub profiles: Vec<rt::profiling::VMProfile>,
+}
+
+pub struct ExecutorBuilder {
+    config: config::Config,
+    host: Option<HostBackend>,
+    permissions: Permissions,
+    limits: Limits,
+    debug_mode: bool,
+    is_sync: bool,
+    genvm_id: Option<u64>,
+    profile_interval: Option<std::time::Duration>,
+    dap_port: Option<u16>,
+}
+
+impl ExecutorBuilder {
+    pub fn host(mut self, host: HostBackend) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    pub fn host_uri(self, uri: impl Into<String>) -> Self {
+        self.host(HostBackend::Uri(uri.into()))
+    }
+
+    pub fn llm_module(mut self, address: impl Into<String>) -> Self {
+        self.config.modules.llm.address = address.into();
+        self
+    }
+
+    pub fn web_module(mut self, address: impl Into<String>) -> Self {
+        self.config.modules.web.address = address.into();
+        self
+    }
+
+    pub fn permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// whenever to allow `:latest` and `:test` as runners version, tracing, etc.
+    pub fn debug_mode(mut self, debug_mode: bool) -> Self {
+        self.debug_mode = debug_mode;
+        self
+    }
+
+    /// validator mode, leader results are requested from the host
+    pub fn sync(mut self, is_sync: bool) -> Self {
+        self.is_sync = is_sync;
+        self
+    }
+
+    /// id to pass to modules, random one is generated for each execution if not set
+    pub fn genvm_id(mut self, genvm_id: u64) -> Self {
+        self.genvm_id = Some(genvm_id);
+        self
+    }
+
+    /// samples guest stacks each `interval`, requires debug mode, see [`rt::profiling`]
+    pub fn profile(mut self, interval: std::time::Duration) -> Self {
+        self.profile_interval = Some(interval);
+        self
+    }
+
+    /// serves Debug Adapter Protocol on local `port`, requires debug mode, see [`rt::debugger`]
+    pub fn dap_port(mut self, port: u16) -> Self {
+        self.dap_port = Some(port);
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Executor> {
+        self.config.validate()?;
+
+        if self.profile_interval.is_some() && !self.debug_mode {
+            anyhow::bail!("profiling is available only in debug mode");
+        }
+        if self.dap_port.is_some() && !self.debug_mode {
+            anyhow::bail!("debugger is available only in debug mode");
+        }
+
+        let debugger = self
+            .dap_port
+            .map(rt::debugger::Debugger::bind)
+            .transpose()
+            .with_context(|| "binding debugger port")?;
+
+        let runtime = self.config.base.create_rt()?;
+        let caches = rt::supervisor::Caches::new(
+            &self.config,
+            self.debug_mode,
+            self.profile_interval.is_some(),
+        )?;
+
+        Ok(Executor {
+            runtime: Some(runtime),
+            caches,
+            config: self.config,
+            host: Mutex::new(self.host),
+            permissions: self.permissions,
+            limits: self.limits,
+            debug_mode: self.debug_mode,
+            is_sync: self.is_sync,
+            genvm_id: self.genvm_id,
+            profile_interval: self.profile_interval,
+            debugger,
+            canceller: Arc::new(Mutex::new(None)),
+            current: None,
+        })
+    }
+}
+
+type Canceller = Box<dyn Fn() + Send + Sync>;
+
+/// Cancels execution that is currently running in the [`Executor`], can be used from signal handlers
+#[derive(Clone)]
+pub struct CancellationHandle(Arc<Mutex<Option<Canceller>>>);
+
+impl CancellationHandle {
+    pub fn cancel(&self) {
+        if let Ok(lock) = self.0.lock() {
+            if let Some(canceller) = lock.as_ref() {
+                canceller();
+            }
+        }
+    }
+}
+
+/// Engines and runner caches are shared between executions, while all per-transaction data is not
+pub struct Executor {
+    runtime: Option<tokio::runtime::Runtime>,
+    caches: Arc<rt::supervisor::Caches>,
+    config: config::Config,
+    host: Mutex<Option<HostBackend>>,
+    permissions: Permissions,
+    limits: Limits,
+    debug_mode: bool,
+    is_sync: bool,
+    genvm_id: Option<u64>,
+    profile_interval: Option<std::time::Duration>,
+    debugger: Option<Arc<rt::debugger::Debugger>>,
+    canceller: Arc<Mutex<Option<Canceller>>>,
+    /// supervisor of the running execution, its modules must be closed
+    current: Option<Arc<rt::supervisor::Supervisor>>,
+}
+
+impl Executor {
+    pub fn builder(config: config::Config) -> ExecutorBuilder {
+        ExecutorBuilder {
+            config,
+            host: None,
+            permissions: Permissions::default(),
+            limits: Limits::default(),
+            debug_mode: false,
+            is_sync: false,
+            genvm_id: None,
+            profile_interval: None,
+            dap_port: None,
+        }
+    }
+
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        CancellationHandle(self.canceller.clone())
+    }
+
+    fn runtime(&self) -> &tokio::runtime::Runtime {
+        self.runtime.as_ref().unwrap()
+    }
+
+    fn connect_host(&self, metrics: sync::DArc<host::Metrics>) -> anyhow::Result<host::Host> {
+        let mut lock = self
+            .host
+            .lock()
+            .map_err(|_| anyhow::anyhow!("host lock poisoned"))?;
+
+        match lock.take() {
+            None => anyhow::bail!("host backend is not set or connection was already used"),
+            Some(HostBackend::Uri(uri)) => {
+                let res = host::Host::connect(&uri, metrics);
+                *lock = Some(HostBackend::Uri(uri));
+                res
+            }
+            Some(HostBackend::Sock(sock)) => Ok(host::Host::new(sock, metrics)),
+        }
+    }
+
+    pub fn execute(
+        &mut self,
+        execution_data: domain::ExecutionData,
+    ) -> anyhow::Result<ExecutionOutcome> {
+        self.execute_with(execution_data, Overrides::default())
+    }
+
+    pub fn execute_with(
+        &mut self,
+        execution_data: domain::ExecutionData,
+        overrides: Overrides,
+    ) -> anyhow::Result<ExecutionOutcome> {
+        let host_data = rt::parse_host_data(&execution_data)?;
+
+        let permissions = overrides.permissions.unwrap_or(self.permissions);
+        let limits = overrides.limits.unwrap_or_else(|| self.limits.clone());
+
+        let (token, canceller) = cancellation::make();
+
+        if let Ok(mut lock) = self.canceller.lock() {
+            *lock = Some(Box::new(canceller.clone()));
+        }
+        // created before the first fallible step, so that handle never cancels a finished or failed execution
+        let registered = self.canceller.clone();
+        let _unregister = sync::DropGuard::new(move || {
+            if let Ok(mut lock) = registered.lock() {
+                *lock = None;
+            }
+        });
+
+        let genvm_id = match overrides.genvm_id.or(self.genvm_id) {
+            Some(v) => v,
+            None => {
+                let mut random_bytes = [0; 8];
+                let _ = getrandom::fill(&mut random_bytes);
+                u64::from_le_bytes(random_bytes)
+            }
+        };
+
+        log_info!(genvm_id = genvm_id; "genvm id");
+
+        if let Some(debugger) = &self.debugger {
+            debugger.attach(&token)?;
+        }
+
+        let shared_data = sync::DArc::new(rt::SharedData {
+            cancellation: token,
+            is_sync: overrides.is_sync.unwrap_or(self.is_sync),
+            genvm_id: genvm_modules_interfaces::GenVMId(genvm_id),
+            debug_mode: self.debug_mode,
+            metrics: crate::Metrics::default(),
+            storage_pages_limit: std::sync::atomic::AtomicU64::new(limits.storage_pages),
+            profile_interval: self.profile_interval,
+            debugger: self.debugger.clone(),
+        });
+
+        let host_metrics = shared_data.gep(|x| &x.metrics.host);
+        let host = match overrides.host {
+            Some(HostBackend::Uri(uri)) => host::Host::connect(&uri, host_metrics)?,
+            Some(HostBackend::Sock(sock)) => host::Host::new(sock, host_metrics),
+            None => self.connect_host(host_metrics)?,
+        };
+
+        let supervisor = {
+            let _guard = self.runtime().enter();
+
+            crate::create_supervisor_with_caches(
+                &self.config,
+                self.caches.clone(),
+                host,
+                host_data,
+                shared_data,
+                &execution_data.message,
+            )
+            .with_context(|| "creating supervisor")?
+        };
+
+        self.current = Some(supervisor.clone());
+
+        let timeout = limits.timeout;
+
+        let res = self.runtime().block_on(async {
+            let _timeout_guard = timeout.map(|timeout| {
+                let canceller = canceller.clone();
+                let task = tokio::spawn(async move {
+                    tokio::time::sleep(timeout).await;
+                    log_warn!(timeout:? = timeout; "execution timeout");
+                    canceller();
+                });
+
+                sync::DropGuard::new(move || task.abort())
+            });
+
+            crate::run_with(execution_data, supervisor, &permissions).await
+        });
+
+        let profiles = self
+            .current
+            .as_ref()
+            .map(|supervisor| supervisor.take_profiles())
+            .unwrap_or_default();
+
+        self.close_current();
+
+        res.with_context(|| "running genvm")
+            .map(|(result, nondet_disagreement)| ExecutionOutcome {
+                result,
+                nondet_disagreement,
+                profiles,
+            })
+    }
+
+    fn close_current(&mut self) {
+        let Some(supervisor) = self.current.take() else {
+            return;
+        };
+
+        self.runtime().block_on(async {
+            supervisor.modules.llm.close().await;
+            supervisor.modules.web.close().await;
+        });
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.close_current();
+
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_timeout(std::time::Duration::from_millis(30));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_after_failed_setup() {
+        let dir = std::env::temp_dir().join(format!("genvm-executor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("all.json"), "{}").unwrap();
+
+        let config: config::Config = serde_json::from_value(serde_json::json!({
+            "modules": {
+                "llm": { "address": "ws://127.0.0.1:1" },
+                "web": { "address": "ws://127.0.0.1:1" },
+            },
+            "cache_dir": dir.join("cache"),
+            "runners_dir": dir,
+            "registry_dir": dir,
+            "threads": 1,
+            "blocking_threads": 1,
+            "log_disable": "",
+        }))
+        .unwrap();
+
+        let mut executor = Executor::builder(config).build().unwrap();
+        let handle = executor.cancellation_handle();
+
+        let address = calldata::Address::from([0; calldata::ADDRESS_SIZE]);
+        let execution_data = domain::ExecutionData {
+            calldata: Vec::new(),
+            message: domain::MessageData {
+                contract_address: address,
+                sender_address: address,
+                origin_address: address,
+                chain_id: "0".into(),
+                value: None,
+                is_init: false,
+                datetime: chrono::DateTime::UNIX_EPOCH,
+            },
+            host_data: r#"{ "node_address": "", "tx_id": "" }"#.into(),
+            code: None,
+        };
+
+        // host backend is not set, so execution fails after its canceller is registered
+        assert!(executor.execute(execution_data).is_err());
+        assert!(executor.canceller.lock().unwrap().is_none());
+        handle.cancel();
+
+        drop(executor);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
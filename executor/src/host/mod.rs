@@ -77,6 +77,22 @@ fn write_slice(sock: &mut dyn Sock, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
+/// Encodes result as kind byte followed by calldata map, see `spec/03-vm/01-result`
+fn encode_full_result(res: &rt::vm::FullResult) -> Result<Vec<u8>> {
+    let mut as_value = calldata::to_value(res)?;
+    if let calldata::Value::Map(map) = &mut as_value {
+        map.insert(
+            "format_version".to_owned(),
+            calldata::Value::Number(public_abi::RESULT_FORMAT_VERSION.into()),
+        );
+    }
+
+    let mut encoded = Vec::from([res.kind as u8]);
+    calldata::encode_to(&mut encoded, &as_value);
+
+    Ok(encoded)
+}
+
 fn read_host_error(sock: &mut dyn Sock) -> Result<host_fns::Errors> {
     let mut has_some = [0; 1];
     sock.read_exact(&mut has_some)?;
@@ -239,26 +255,19 @@ impl Host {
         let mut sock = self.lock_sock();
 
         let data = match res {
-            Ok(d) => {
-                let mut encoded = Vec::from([d.kind as u8]);
-                let as_value = calldata::to_value(d)?;
-                calldata::encode_to(&mut encoded, &as_value);
-
-                encoded
-            }
+            Ok(d) => encode_full_result(d)?,
             Err(e) => {
-                let mut encoded = Vec::from([ResultCode::InternalError as u8]);
                 let fake_res = rt::vm::FullResult {
                     kind: public_abi::ResultCode::InternalError,
                     data: calldata::Value::Str(format!("{e:?}")),
                     fingerprint: None,
                     storage_changes: Vec::new(),
                     events: Vec::new(),
+                    messages: Vec::new(),
+                    output: Vec::new(),
                 };
-                let as_value = calldata::to_value(&fake_res)?;
-                calldata::encode_to(&mut encoded, &as_value);
 
-                encoded
+                encode_full_result(&fake_res)?
             }
         };
 
@@ -1,5 +1,6 @@
 pub mod caching;
 pub mod config;
+pub mod executor;
 pub mod host;
 pub mod modules;
 pub mod rt;
@@ -11,6 +12,7 @@ pub mod public_abi;
 pub use genvm_common::calldata;
 use genvm_common::*;
 
+pub use executor::{ExecutionOutcome, Executor, Permissions};
 pub use host::{Host, SlotID};
 
 use anyhow::Result;
@@ -35,7 +37,7 @@ pub struct Metrics {
 
 pub fn create_supervisor(
     config: &config::Config,
-    mut host: Host,
+    host: Host,
     host_data: genvm_modules_interfaces::HostData,
     shared_data: sync::DArc<rt::SharedData>,
     message: &domain::MessageData,
@@ -44,8 +46,28 @@ pub fn create_supervisor(
     config.validate()?;
     // -------------------------------
 
+    let caches = rt::supervisor::Caches::new(
+        config,
+        shared_data.debug_mode,
+        shared_data.profile_interval.is_some(),
+    )?;
+
+    create_supervisor_with_caches(config, caches, host, host_data, shared_data, message)
+}
+
+/// Creates supervisor that reuses engines and runner caches, which must be created with the same config
+pub fn create_supervisor_with_caches(
+    config: &config::Config,
+    caches: Arc<rt::supervisor::Caches>,
+    mut host: Host,
+    host_data: genvm_modules_interfaces::HostData,
+    shared_data: sync::DArc<rt::SharedData>,
+    message: &domain::MessageData,
+) -> Result<Arc<rt::supervisor::Supervisor>> {
     let metrics = shared_data.gep(|x| &x.metrics);
 
+    let random_seed = rt::random::root_seed(&host_data)?;
+
     let modules = modules::All {
         web: Arc::new(modules::Module::new(
             "web".into(),
@@ -81,9 +103,10 @@ pub fn create_supervisor(
             non_det: rt::memlimiter::Limiter::new("nondet"),
         },
         locked_slots,
+        random_seed,
     };
 
-    rt::supervisor::Supervisor::start(config, ctor, host)
+    rt::supervisor::Supervisor::start_with_caches(config, caches, ctor, host)
 }
 
 fn log_vm_error(e: &anyhow::Error) {
@@ -97,7 +120,7 @@ fn log_vm_error(e: &anyhow::Error) {
 pub async fn run_with_impl(
     entry_data: domain::ExecutionData,
     supervisor: Arc<rt::supervisor::Supervisor>,
-    permissions: &str,
+    permissions: &Permissions,
 ) -> anyhow::Result<rt::vm::FullResult> {
     // --- START EXECUTION TIMER ---
     let start_instant = Instant::now();
@@ -129,11 +152,11 @@ pub async fn run_with_impl(
         conf: wasi::base::Config {
             needs_error_fingerprint: true,
             is_deterministic: true,
-            can_read_storage: permissions.contains("r"),
-            can_write_storage: permissions.contains("w"),
-            can_send_messages: permissions.contains("s"),
-            can_call_others: permissions.contains("c"),
-            can_spawn_nondet: permissions.contains("n"),
+            can_read_storage: permissions.read_storage,
+            can_write_storage: permissions.write_storage,
+            can_send_messages: permissions.send_messages,
+            can_call_others: permissions.call_others,
+            can_spawn_nondet: permissions.spawn_nondet,
             state_mode: crate::public_abi::StorageType::Default,
         },
         message_data: ExtendedMessage {
@@ -156,6 +179,12 @@ pub async fn run_with_impl(
 
         storage: topmost_storage,
         events: Vec::new(),
+        cancellation: supervisor.shared_data.cancellation.clone(),
+        foreign_storages: std::collections::BTreeMap::new(),
+        messages: Vec::new(),
+        buffer_messages: false,
+        random_seed: supervisor.random_seed,
+        depth: 0,
     };
 
     let limiter = supervisor
@@ -188,6 +217,8 @@ pub async fn run_with_impl(
                     },
                     storage_changes: Vec::new(),
                     events: Vec::new(),
+                    messages: Vec::new(),
+                    output: Vec::new(),
                 }),
             };
         }
@@ -221,15 +252,20 @@ pub async fn run_with_impl(
             rt::vm::RunOk::UserError(buf) => calldata::Value::Str(buf),
             rt::vm::RunOk::VMError(msg, _) => calldata::Value::Str(msg),
         },
-        storage_changes: run_result.vm_data.storage.make_delta(),
+        storage_changes: std::iter::once(&run_result.vm_data.storage)
+            .chain(run_result.vm_data.foreign_storages.values())
+            .map(|storage| (storage.address, storage.make_delta()))
+            .collect(),
         events: run_result.vm_data.events,
+        messages: run_result.vm_data.messages,
+        output: Vec::new(),
     })
 }
 
 pub async fn run_with(
     entry_data: domain::ExecutionData,
     supervisor: Arc<rt::supervisor::Supervisor>,
-    permissions: &str,
+    permissions: &Permissions,
 ) -> anyhow::Result<(rt::vm::FullResult, Option<u32>)> {
     let res = run_with_impl(entry_data, supervisor.clone(), permissions).await;
 
@@ -265,6 +301,8 @@ pub async fn run_with(
                     data: calldata::Value::Str(public_abi::VmError::Timeout.value().into()),
                     storage_changes: Vec::new(),
                     events: Vec::new(),
+                    messages: Vec::new(),
+                    output: Vec::new(),
                 },
                 None,
             )),
@@ -273,9 +311,19 @@ pub async fn run_with(
         merged_result
     };
 
-    let res = res.inspect_err(|e| {
-        log_error!(error:ah = &e; "internal error");
-    });
+    let res = res
+        .map(|(mut r, disag)| {
+            r.output = supervisor.captured_output();
+            (r, disag)
+        })
+        .inspect_err(|e| {
+            log_error!(error:ah = &e; "internal error");
+
+            let output = supervisor.captured_output();
+            if !output.is_empty() {
+                log_info!(output:serde = output; "captured output of failed execution");
+            }
+        });
 
     if let Ok((_, Some(disag))) = &res {
         let mut host = supervisor.host.lock().await;
@@ -312,12 +360,24 @@ pub async fn run_with(
         web: Option<calldata::Value>,
         llm: Option<calldata::Value>,
         gvm: &'a crate::Metrics,
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        spans: &'a [rt::spans::VMSpans],
+    }
+
+    let spans = supervisor.take_spans();
+    if spans.has_spans() {
+        log_debug!(spans:serde = spans.spans(), dropped = spans.dropped(); "trace spans");
     }
 
     let all_metrics = AllMetrics {
         web: web_metrics,
         llm: llm_metrics,
         gvm: &supervisor.shared_data.metrics,
+        spans: if spans.has_spans() {
+            spans.spans()
+        } else {
+            &[]
+        },
     };
 
     let all_metrics = calldata::to_value(&all_metrics)
@@ -10,8 +10,12 @@ mod exe;
 #[derive(clap::Subcommand, Debug)]
 enum Commands {
     Run(exe::run::Args),
+    Daemon(exe::daemon::Args),
     Precompile(exe::precompile::Args),
     ParseVersionPattern(exe::parse_version::Args),
+    Runners(exe::runners::Args),
+    CheckContract(exe::check_contract::Args),
+    Symbolize(exe::symbolize::Args),
 }
 
 #[derive(clap::Parser)]
@@ -55,8 +59,12 @@ fn main_impl() -> Result<()> {
 
     match args.command {
         Commands::Run(args) => exe::run::handle(args, config),
+        Commands::Daemon(args) => exe::daemon::handle(args, config),
         Commands::Precompile(args) => exe::precompile::handle(args, config),
         Commands::ParseVersionPattern(args) => exe::parse_version::handle(args, config),
+        Commands::Runners(args) => exe::runners::handle(args, config),
+        Commands::CheckContract(args) => exe::check_contract::handle(args, config),
+        Commands::Symbolize(args) => exe::symbolize::handle(args, config),
     }
 }
 
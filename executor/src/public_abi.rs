@@ -196,6 +196,9 @@ pub enum VmError {
     VersionTooBig,
     Oom,
     InvalidContract,
+    SubLimitExceeded,
+    CallDepthExceeded,
+    ReentrancyForbidden,
 }
 
 impl VmError {
@@ -207,6 +210,9 @@ impl VmError {
             VmError::VersionTooBig => "version_too_big",
             VmError::Oom => "OOM",
             VmError::InvalidContract => "invalid_contract",
+            VmError::SubLimitExceeded => "sub_limit_exceeded",
+            VmError::CallDepthExceeded => "call_depth_exceeded",
+            VmError::ReentrancyForbidden => "reentrancy_forbidden",
         }
     }
     pub fn str_snake_case(self) -> &'static str {
@@ -217,6 +223,9 @@ impl VmError {
             VmError::VersionTooBig => "version_too_big",
             VmError::Oom => "oom",
             VmError::InvalidContract => "invalid_contract",
+            VmError::SubLimitExceeded => "sub_limit_exceeded",
+            VmError::CallDepthExceeded => "call_depth_exceeded",
+            VmError::ReentrancyForbidden => "reentrancy_forbidden",
         }
     }
 }
@@ -232,10 +241,18 @@ impl TryFrom<&str> for VmError {
             "version_too_big" => Ok(VmError::VersionTooBig),
             "OOM" => Ok(VmError::Oom),
             "invalid_contract" => Ok(VmError::InvalidContract),
+            "sub_limit_exceeded" => Ok(VmError::SubLimitExceeded),
+            "call_depth_exceeded" => Ok(VmError::CallDepthExceeded),
+            "reentrancy_forbidden" => Ok(VmError::ReentrancyForbidden),
             _ => Err(()),
         }
     }
 }
 pub const EVENT_MAX_TOPICS: u32 = 4;
 pub const ABSENT_VERSION: &'static str = "v0.1.0";
+pub const DET_SIMD_MIN_VERSION: &'static str = "v0.2.5";
+pub const RANDOM_SEED_MIN_VERSION: &'static str = "v0.2.5";
+pub const MAX_VM_DEPTH: u32 = 16;
+pub const REENTRANCY_FORBIDDEN_MIN_VERSION: &'static str = "v0.2.5";
+pub const RESULT_FORMAT_VERSION: u32 = 2;
 pub const CODE_SLOT_OFFSET: u32 = 1;
@@ -0,0 +1,603 @@
+//! Debug Adapter Protocol server, available only in debug mode, see `--dap-port` of `genvm run`
+//!
+//! Breakpoints are set on kinds of events instead of source lines. Kinds are
+//! [`gl_call::MESSAGE_KINDS`], which stop before the message is dispatched,
+//! and [`STORAGE_READ`], [`STORAGE_WRITE`], [`NONDET_START`], [`NONDET_END`].
+//! They can be set both as function breakpoints and as exception filters.
+//!
+//! Each VM is a thread, stopped thread has a single frame named after the event,
+//! with the decoded event exposed as variables. Stepping resumes a thread till its next event
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+
+use genvm_common::*;
+
+use crate::{public_abi, rt, wasi::gl_call};
+
+pub const STORAGE_READ: &str = "StorageRead";
+pub const STORAGE_WRITE: &str = "StorageWrite";
+pub const NONDET_START: &str = "NondetStart";
+pub const NONDET_END: &str = "NondetEnd";
+
+/// How often [`Debugger::attach`] checks for cancellation while waiting for the client
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+fn all_kinds() -> impl Iterator<Item = &'static str> {
+    gl_call::MESSAGE_KINDS.into_iter().chain([
+        STORAGE_READ,
+        STORAGE_WRITE,
+        NONDET_START,
+        NONDET_END,
+    ])
+}
+
+/// Converts calldata into json that is readable in debugger, bytes and big numbers become strings
+pub fn calldata_to_json(value: &calldata::Value) -> serde_json::Value {
+    match value {
+        calldata::Value::Null => serde_json::Value::Null,
+        calldata::Value::Address(addr) => format!("{addr}").into(),
+        calldata::Value::Bool(b) => (*b).into(),
+        calldata::Value::Str(s) => s.as_str().into(),
+        calldata::Value::Bytes(b) => format!("0x{}", hex::encode(b)).into(),
+        calldata::Value::Number(n) => match i64::try_from(n) {
+            Ok(n) => n.into(),
+            Err(_) => n.to_string().into(),
+        },
+        calldata::Value::Map(m) => m
+            .iter()
+            .map(|(k, v)| (k.clone(), calldata_to_json(v)))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+        calldata::Value::Array(a) => a.iter().map(calldata_to_json).collect(),
+    }
+}
+
+/// Kind of gl_call request, which is the name of [`gl_call::Message`] variant
+pub fn gl_call_kind(request: &calldata::Value) -> &str {
+    match request {
+        calldata::Value::Map(m) if m.len() == 1 => m.keys().next().unwrap(),
+        _ => "<invalid>",
+    }
+}
+
+struct Stop {
+    kind: String,
+    data: serde_json::Value,
+    resume: Arc<tokio::sync::Notify>,
+}
+
+struct ThreadState {
+    name: String,
+    /// reason to stop on the next event regardless of breakpoints, set by stepping and pausing
+    stop_next: Option<&'static str>,
+    stopped: Option<Stop>,
+}
+
+#[derive(Default)]
+struct State {
+    client: Option<std::net::TcpStream>,
+    configured: bool,
+    seq: i64,
+
+    function_breakpoints: BTreeSet<String>,
+    filter_breakpoints: BTreeSet<String>,
+
+    threads: BTreeMap<i64, ThreadState>,
+    next_thread: i64,
+    /// values of variable references, reference is index + 1
+    variables: Vec<serde_json::Value>,
+}
+
+impl State {
+    fn send(&mut self, mut message: serde_json::Value) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+
+        let body = message.to_string();
+        let res = client
+            .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+            .and_then(|_| client.write_all(body.as_bytes()));
+
+        if let Err(e) = res {
+            log_warn!(error:err = e; "failed to write to debug adapter client");
+        }
+    }
+
+    fn send_event(&mut self, event: &str, body: serde_json::Value) {
+        self.send(serde_json::json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+
+    fn resume(&mut self, thread_id: i64) {
+        if let Some(stop) = self
+            .threads
+            .get_mut(&thread_id)
+            .and_then(|t| t.stopped.take())
+        {
+            stop.resume.notify_one();
+        }
+
+        if self.threads.values().all(|t| t.stopped.is_none()) {
+            self.variables.clear();
+        }
+    }
+
+    fn resume_all(&mut self) {
+        let ids: Vec<i64> = self.threads.keys().cloned().collect();
+        for id in ids {
+            self.resume(id);
+        }
+    }
+
+    fn add_variable(&mut self, value: serde_json::Value) -> usize {
+        self.variables.push(value);
+        self.variables.len()
+    }
+
+    fn variables(&mut self, reference: usize) -> Vec<serde_json::Value> {
+        let Some(value) = self.variables.get(reference.wrapping_sub(1)).cloned() else {
+            return Vec::new();
+        };
+
+        let children: Vec<(String, serde_json::Value)> = match value {
+            serde_json::Value::Object(m) => m.into_iter().collect(),
+            serde_json::Value::Array(a) => a
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        children
+            .into_iter()
+            .map(|(name, value)| {
+                let (shown, reference) = match &value {
+                    serde_json::Value::Object(m) if !m.is_empty() => {
+                        (format!("{{{} fields}}", m.len()), self.add_variable(value))
+                    }
+                    serde_json::Value::Array(a) if !a.is_empty() => {
+                        (format!("[{} items]", a.len()), self.add_variable(value))
+                    }
+                    value => (value.to_string(), 0),
+                };
+
+                serde_json::json!({
+                    "name": name,
+                    "value": shown,
+                    "variablesReference": reference,
+                })
+            })
+            .collect()
+    }
+}
+
+pub struct Debugger {
+    listener: std::net::TcpListener,
+    state: Mutex<State>,
+    configured: Condvar,
+}
+
+/// VM registered in the debugger, it is unregistered on drop
+pub struct Thread {
+    id: i64,
+    debugger: Arc<Debugger>,
+}
+
+impl Debugger {
+    /// Listens on local `port`, client is accepted by [`Debugger::attach`]
+    pub fn bind(port: u16) -> anyhow::Result<Arc<Self>> {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Arc::new(Self {
+            listener,
+            state: Mutex::new(State {
+                next_thread: 1,
+                ..Default::default()
+            }),
+            configured: Condvar::new(),
+        }))
+    }
+
+    /// Waits till client connects and finishes configuration, does nothing if it already did.
+    /// Gives up with timeout error when `cancellation` fires
+    pub fn attach(self: &Arc<Self>, cancellation: &cancellation::Token) -> anyhow::Result<()> {
+        let cancelled = || -> anyhow::Error {
+            rt::errors::VMError(public_abi::VmError::Timeout.value().into(), None).into()
+        };
+
+        if self.state.lock().unwrap().client.is_none() {
+            log_info!(address:? = self.listener.local_addr()?; "waiting for debug adapter client");
+
+            let (stream, peer) = loop {
+                match self.listener.accept() {
+                    Ok(accepted) => break accepted,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        if cancellation.is_cancelled() {
+                            return Err(cancelled());
+                        }
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            stream.set_nonblocking(false)?;
+            log_info!(peer:? = peer; "debug adapter client connected");
+
+            let mut state = self.state.lock().unwrap();
+            state.client = Some(stream.try_clone()?);
+            state.configured = false;
+
+            let zelf = self.clone();
+            std::thread::spawn(move || zelf.serve(stream));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        while state.client.is_some() && !state.configured {
+            if cancellation.is_cancelled() {
+                return Err(cancelled());
+            }
+            state = self
+                .configured
+                .wait_timeout(state, POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+
+        Ok(())
+    }
+
+    pub fn register_vm(self: &Arc<Self>, name: String) -> Thread {
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.next_thread;
+        state.next_thread += 1;
+        state.threads.insert(
+            id,
+            ThreadState {
+                name,
+                stop_next: None,
+                stopped: None,
+            },
+        );
+        state.send_event(
+            "thread",
+            serde_json::json!({"reason": "started", "threadId": id}),
+        );
+
+        Thread {
+            id,
+            debugger: self.clone(),
+        }
+    }
+
+    fn serve(&self, stream: std::net::TcpStream) {
+        let mut reader = std::io::BufReader::new(stream);
+
+        loop {
+            match read_message(&mut reader) {
+                Ok(Some(request)) => {
+                    if !self.handle(request) {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log_warn!(error:ah = e; "failed to read debug adapter message");
+                    break;
+                }
+            }
+        }
+
+        log_info!("debug adapter client disconnected");
+
+        let mut state = self.state.lock().unwrap();
+        state.client = None;
+        state.configured = false;
+        state.function_breakpoints.clear();
+        state.filter_breakpoints.clear();
+        for thread in state.threads.values_mut() {
+            thread.stop_next = None;
+        }
+        state.resume_all();
+        self.configured.notify_all();
+    }
+
+    /// Returns whenever connection must be kept
+    fn handle(&self, request: serde_json::Value) -> bool {
+        let command = request["command"].as_str().unwrap_or_default().to_owned();
+        let args = &request["arguments"];
+
+        let mut state = self.state.lock().unwrap();
+
+        let body: Result<serde_json::Value, String> = match command.as_str() {
+            "initialize" => Ok(serde_json::json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "exceptionBreakpointFilters": all_kinds()
+                    .map(|kind| serde_json::json!({"filter": kind, "label": kind, "default": false}))
+                    .collect::<Vec<_>>(),
+            })),
+            "attach" | "launch" => Ok(serde_json::Value::Null),
+            "configurationDone" => {
+                state.configured = true;
+                self.configured.notify_all();
+                Ok(serde_json::Value::Null)
+            }
+            "setFunctionBreakpoints" => {
+                let names: Vec<String> = args["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|bp| bp["name"].as_str().map(|x| x.to_owned()))
+                    .collect();
+
+                let breakpoints: Vec<serde_json::Value> = names
+                    .iter()
+                    .map(|name| {
+                        let verified = all_kinds().any(|kind| kind == name);
+                        serde_json::json!({
+                            "verified": verified,
+                            "message": if verified { "" } else { "unknown event kind" },
+                        })
+                    })
+                    .collect();
+
+                state.function_breakpoints = names.into_iter().collect();
+                Ok(serde_json::json!({"breakpoints": breakpoints}))
+            }
+            "setExceptionBreakpoints" => {
+                state.filter_breakpoints = args["filters"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|x| x.as_str().map(|x| x.to_owned()))
+                    .collect();
+                Ok(serde_json::Value::Null)
+            }
+            "setBreakpoints" => {
+                let count = args["breakpoints"].as_array().map_or(0, |x| x.len());
+                let breakpoints: Vec<serde_json::Value> = (0..count)
+                    .map(|_| serde_json::json!({"verified": false, "message": "only event kinds are supported, use function breakpoints"}))
+                    .collect();
+                Ok(serde_json::json!({"breakpoints": breakpoints}))
+            }
+            "threads" => {
+                let threads: Vec<serde_json::Value> = state
+                    .threads
+                    .iter()
+                    .map(|(id, t)| serde_json::json!({"id": id, "name": t.name}))
+                    .collect();
+                Ok(serde_json::json!({"threads": threads}))
+            }
+            "stackTrace" => {
+                let thread_id = args["threadId"].as_i64().unwrap_or_default();
+                let frames: Vec<serde_json::Value> = state
+                    .threads
+                    .get(&thread_id)
+                    .and_then(|t| t.stopped.as_ref())
+                    .map(|stop| serde_json::json!({"id": thread_id, "name": stop.kind, "line": 0, "column": 0}))
+                    .into_iter()
+                    .collect();
+                Ok(serde_json::json!({"stackFrames": frames, "totalFrames": frames.len()}))
+            }
+            "scopes" => {
+                let thread_id = args["frameId"].as_i64().unwrap_or_default();
+                let data = state
+                    .threads
+                    .get(&thread_id)
+                    .and_then(|t| t.stopped.as_ref())
+                    .map(|stop| stop.data.clone());
+
+                match data {
+                    None => Err("thread is not stopped".to_owned()),
+                    Some(data) => {
+                        let reference = state.add_variable(serde_json::json!({ "event": data }));
+                        Ok(serde_json::json!({"scopes": [
+                            {"name": "Event", "variablesReference": reference, "expensive": false}
+                        ]}))
+                    }
+                }
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_u64().unwrap_or_default();
+                let variables = state.variables(reference as usize);
+                Ok(serde_json::json!({"variables": variables}))
+            }
+            "continue" => {
+                state.resume_all();
+                Ok(serde_json::json!({"allThreadsContinued": true}))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let thread_id = args["threadId"].as_i64().unwrap_or_default();
+                if let Some(thread) = state.threads.get_mut(&thread_id) {
+                    thread.stop_next = Some("step");
+                }
+                state.resume(thread_id);
+                Ok(serde_json::Value::Null)
+            }
+            "pause" => {
+                let thread_id = args["threadId"].as_i64().unwrap_or_default();
+                if let Some(thread) = state.threads.get_mut(&thread_id) {
+                    thread.stop_next = Some("pause");
+                }
+                Ok(serde_json::Value::Null)
+            }
+            "disconnect" => Ok(serde_json::Value::Null),
+            command => Err(format!("unsupported command {command}")),
+        };
+
+        let (success, body, message) = match body {
+            Ok(body) => (true, body, None),
+            Err(message) => (false, serde_json::Value::Null, Some(message)),
+        };
+
+        state.send(serde_json::json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": success,
+            "message": message,
+            "body": body,
+        }));
+
+        if command == "initialize" {
+            state.send_event("initialized", serde_json::Value::Null);
+        }
+
+        command != "disconnect"
+    }
+}
+
+impl Thread {
+    /// Stops the VM before event of `kind` if client requested so, `data` is shown as variables
+    pub async fn hook(
+        &self,
+        kind: &str,
+        data: impl FnOnce() -> serde_json::Value,
+        cancellation: &cancellation::Token,
+    ) {
+        let resume = {
+            let mut state = self.debugger.state.lock().unwrap();
+            if state.client.is_none() {
+                return;
+            }
+
+            let is_breakpoint = state.function_breakpoints.contains(kind)
+                || state.filter_breakpoints.contains(kind);
+
+            let Some(thread) = state.threads.get_mut(&self.id) else {
+                return;
+            };
+
+            let reason = match thread.stop_next.take() {
+                Some(reason) => reason,
+                None if is_breakpoint => "breakpoint",
+                None => return,
+            };
+
+            let resume = Arc::new(tokio::sync::Notify::new());
+            thread.stopped = Some(Stop {
+                kind: kind.to_owned(),
+                data: data(),
+                resume: resume.clone(),
+            });
+
+            state.send_event(
+                "stopped",
+                serde_json::json!({
+                    "reason": reason,
+                    "description": kind,
+                    "text": kind,
+                    "threadId": self.id,
+                    "allThreadsStopped": false,
+                }),
+            );
+
+            resume
+        };
+
+        tokio::select! {
+            _ = resume.notified() => {}
+            _ = cancellation.chan.closed() => {
+                self.debugger.state.lock().unwrap().resume(self.id);
+            }
+        }
+    }
+
+    /// Shows text of `Trace` messages in the debug console
+    pub fn output(&self, text: &str) {
+        let mut state = self.debugger.state.lock().unwrap();
+        state.send_event(
+            "output",
+            serde_json::json!({"category": "console", "output": format!("{text}\n")}),
+        );
+    }
+}
+
+impl Drop for Thread {
+    fn drop(&mut self) {
+        let mut state = self.debugger.state.lock().unwrap();
+        state.resume(self.id);
+        state.threads.remove(&self.id);
+        state.send_event(
+            "thread",
+            serde_json::json!({"reason": "exited", "threadId": self.id}),
+        );
+    }
+}
+
+/// Reads a message framed with `Content-Length` header, returns `None` on end of stream
+fn read_message(reader: &mut impl BufRead) -> anyhow::Result<Option<serde_json::Value>> {
+    let mut content_length = None;
+
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+
+    let content_length =
+        content_length.ok_or_else(|| anyhow::anyhow!("message without Content-Length"))?;
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let mut input: &[u8] =
+            b"Content-Length: 13\r\n\r\n{\"seq\": 1}   Content-Length: 2\r\n\r\n{}";
+
+        let first = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(first["seq"], 1);
+        assert_eq!(
+            read_message(&mut input).unwrap(),
+            Some(serde_json::json!({}))
+        );
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn kinds() {
+        let request = calldata::Value::Map(BTreeMap::from([(
+            "ExecPrompt".to_owned(),
+            calldata::Value::Bytes(vec![1, 2]),
+        )]));
+
+        assert_eq!(gl_call_kind(&request), "ExecPrompt");
+        assert_eq!(
+            calldata_to_json(&request),
+            serde_json::json!({"ExecPrompt": "0x0102"})
+        );
+        assert!(all_kinds().any(|kind| kind == "ExecPrompt"));
+    }
+}
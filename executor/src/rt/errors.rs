@@ -25,6 +25,14 @@ impl VMError {
         VMError(str, cause)
     }
 
+    /// limit requested by the parent VM was exhausted, `what` is one of `memory`, `storage`, `timeout`
+    pub fn sub_limit(what: &str, cause: Option<anyhow::Error>) -> Self {
+        let mut str = public_abi::VmError::SubLimitExceeded.value().to_owned();
+        str.push(' ');
+        str.push_str(what);
+        VMError(str, cause)
+    }
+
     pub fn wrap(message: String, cause: anyhow::Error) -> Self {
         match cause.downcast::<VMError>() {
             Err(cause) => Self(message, Some(cause)),
@@ -44,8 +52,17 @@ pub fn unwrap_vm_errors(err: anyhow::Error) -> anyhow::Result<rt::vm::RunOk> {
             Err(e) => Err(e),
         },
         |e: anyhow::Error| {
-            e.downcast::<wasmtime::Trap>()
-                .map(|v| rt::vm::RunOk::VMError(format!("wasm_trap {v:?}"), Some(v.into())))
+            let backtrace = e
+                .downcast_ref::<wasmtime::WasmBacktrace>()
+                .map(|bt| bt.to_string());
+            e.downcast::<wasmtime::Trap>().map(|v| {
+                let cause = anyhow::Error::from(v);
+                let cause = match backtrace {
+                    Some(bt) => cause.context(bt),
+                    None => cause,
+                };
+                rt::vm::RunOk::VMError(format!("wasm_trap {v:?}"), Some(cause))
+            })
         },
         |e: anyhow::Error| {
             e.downcast::<rt::errors::VMError>()
@@ -69,8 +86,21 @@ pub fn unwrap_vm_errors(err: anyhow::Error) -> anyhow::Result<rt::vm::RunOk> {
     res
 }
 
+/// Source location of the innermost inlined function of the frame, available only with DWARF
+fn frame_location(frame: &wasmtime::FrameInfo) -> Option<String> {
+    let symbol = frame.symbols().first()?;
+    let file = symbol.file()?;
+
+    Some(match symbol.line() {
+        Some(line) => format!("{file}:{line}"),
+        None => file.to_owned(),
+    })
+}
+
+/// `debug_mode` adds function names and source locations to frames
 pub fn unwrap_vm_errors_fingerprint(
     err: anyhow::Error,
+    debug_mode: bool,
 ) -> anyhow::Result<(rt::vm::RunOk, Fingerprint)> {
     let mut fingerprint = Fingerprint {
         frames: Vec::new(),
@@ -81,9 +111,19 @@ pub fn unwrap_vm_errors_fingerprint(
         let frames = bt
             .frames()
             .iter()
-            .map(|f| Frame {
-                module_name: f.module().name().unwrap_or("").to_string(),
-                func: f.func_index(),
+            .map(|f| {
+                let (func_name, location) = if debug_mode {
+                    (f.func_name().map(|x| x.to_owned()), frame_location(f))
+                } else {
+                    (None, None)
+                };
+
+                Frame {
+                    module_name: f.module().name().unwrap_or("").to_string(),
+                    func: f.func_index(),
+                    func_name,
+                    location,
+                }
             })
             .collect();
 
@@ -113,10 +153,16 @@ impl std::fmt::Display for UserError {
     }
 }
 
-#[derive(Debug, Clone, serde::Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, Eq)]
 pub struct Frame {
     pub module_name: String,
     pub func: u32,
+    /// from name section, set only in debug mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub func_name: Option<String>,
+    /// `file:line` from DWARF, set only in debug mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -128,3 +174,91 @@ pub struct Fingerprint {
 
     pub module_instances: BTreeMap<String, wasmtime::ModuleFingerprint>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// traps in `inner` called from exported `outer` of module named `contract`
+    fn trap() -> anyhow::Error {
+        let engines = rt::supervisor::create_engines(|conf| {
+            conf.async_support(false);
+            Ok(())
+        })
+        .unwrap();
+
+        let wasm = wat::parse_str(
+            r#"(module $contract
+                (func $inner unreachable)
+                (func $outer (export "run") call $inner))"#,
+        )
+        .unwrap();
+        let module = wasmtime::Module::new(&engines.det, &wasm).unwrap();
+        let mut store = wasmtime::Store::new(
+            &engines.det,
+            (),
+            wasmtime::GenVMCtx {
+                should_capture_fp: std::sync::Arc::new(false.into()),
+                should_quit: std::sync::Arc::new(0.into()),
+            },
+        );
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let run = instance
+            .get_typed_func::<(), ()>(&mut store, "run")
+            .unwrap();
+
+        run.call(&mut store, ()).unwrap_err()
+    }
+
+    #[test]
+    fn fingerprint_frames() {
+        let (res, fingerprint) = unwrap_vm_errors_fingerprint(trap(), false).unwrap();
+        assert!(matches!(res, rt::vm::RunOk::VMError(ref msg, _) if msg.starts_with("wasm_trap")));
+        assert_eq!(
+            fingerprint.frames,
+            vec![
+                Frame {
+                    module_name: "contract".to_owned(),
+                    func: 0,
+                    func_name: None,
+                    location: None,
+                },
+                Frame {
+                    module_name: "contract".to_owned(),
+                    func: 1,
+                    func_name: None,
+                    location: None,
+                },
+            ]
+        );
+
+        let (_, fingerprint) = unwrap_vm_errors_fingerprint(trap(), true).unwrap();
+        let names: Vec<_> = fingerprint
+            .frames
+            .iter()
+            .map(|f| (f.func, f.func_name.as_deref()))
+            .collect();
+        assert_eq!(names, vec![(0, Some("inner")), (1, Some("outer"))]);
+    }
+
+    #[test]
+    fn trap_cause_has_backtrace() {
+        let Ok(rt::vm::RunOk::VMError(_, Some(cause))) = unwrap_vm_errors(trap()) else {
+            panic!("trap is not a vm error");
+        };
+        let cause = format!("{cause:#}");
+        assert!(cause.contains("inner"), "{cause}");
+        assert!(cause.contains("outer"), "{cause}");
+    }
+
+    #[test]
+    fn frame_without_names_deserializes() {
+        let frame: Frame = serde_json::from_str(r#"{"module_name":"contract","func":3}"#).unwrap();
+        assert_eq!(frame.func_name, None);
+        assert_eq!(frame.location, None);
+        assert_eq!(
+            serde_json::to_string(&frame).unwrap(),
+            r#"{"module_name":"contract","func":3}"#
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use genvm_common::*;
+
+use crate::{rt, wasi};
+
+/// Limits that contract may request for a nested VM (`Sandbox`, `CallContract`, `RunNondet`).
+/// They can only tighten the limits of the parent, never extend them
+#[derive(Clone, Copy, Default, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubVMLimits {
+    /// bytes of memory available to the VM and its children
+    #[serde(default)]
+    pub memory: Option<u32>,
+    /// amount of storage pages (and event blobs) VM can write
+    #[serde(default)]
+    pub storage_pages: Option<u64>,
+    /// allowed only for non-deterministic VMs, as result would depend on the host speed
+    #[serde(default)]
+    pub wall_time_ms: Option<u64>,
+    /// reserved until fuel metering is implemented, requesting it is an error
+    #[serde(default)]
+    pub fuel: Option<u64>,
+}
+
+/// Keeps child cancellation token alive, stops its watcher on drop
+pub struct Applied {
+    pub limiter: rt::memlimiter::Limiter,
+    parent_cancellation: Arc<cancellation::Token>,
+    own_cancellation: Option<Arc<cancellation::Token>>,
+    _canceller: Option<cancellation::CancellationDrop<Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl SubVMLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none()
+            && self.storage_pages.is_none()
+            && self.wall_time_ms.is_none()
+            && self.fuel.is_none()
+    }
+
+    /// Rejects limits that can't be enforced for a VM of given mode
+    pub fn validate(&self, is_deterministic: bool) -> anyhow::Result<()> {
+        if self.fuel.is_some() {
+            anyhow::bail!("fuel limit is not supported");
+        }
+        if is_deterministic && self.wall_time_ms.is_some() {
+            anyhow::bail!("wall time limit is not allowed for deterministic VMs");
+        }
+
+        Ok(())
+    }
+
+    /// Narrows resources of `vm` that is about to be spawned, limits must be [`SubVMLimits::validate`]d
+    pub fn apply(
+        &self,
+        limiter: rt::memlimiter::Limiter,
+        vm: &mut wasi::genlayer_sdk::SingleVMData,
+    ) -> Applied {
+        debug_assert!(self.validate(vm.conf.is_deterministic).is_ok());
+
+        let limiter = match self.memory {
+            Some(memory) => limiter.derived_with_budget(memory),
+            None => limiter,
+        };
+
+        if let Some(pages) = self.storage_pages {
+            let storage_limiter = vm.storage.limiter().derived(pages);
+            vm.storage.set_limiter(storage_limiter);
+        }
+
+        let parent_cancellation = vm.cancellation.clone();
+
+        let (own_cancellation, canceller) = match self.wall_time_ms {
+            None => (None, None),
+            Some(ms) => {
+                let (token, canceller) = cancellation::make_child(
+                    &parent_cancellation,
+                    Some(std::time::Duration::from_millis(ms)),
+                );
+                let canceller: Box<dyn Fn() + Send + Sync> = Box::new(canceller);
+
+                vm.cancellation = token.clone();
+
+                (
+                    Some(token),
+                    Some(cancellation::CancellationDrop(canceller)),
+                )
+            }
+        };
+
+        Applied {
+            limiter,
+            parent_cancellation,
+            own_cancellation,
+            _canceller: canceller,
+        }
+    }
+}
+
+impl Applied {
+    /// whenever VM was stopped by its own wall time limit and not by the parent
+    pub fn is_timed_out(&self) -> bool {
+        match &self.own_cancellation {
+            None => false,
+            Some(own) => own.is_cancelled() && !self.parent_cancellation.is_cancelled(),
+        }
+    }
+
+    /// resolves when own wall time limit is exceeded, never resolves if there is no such limit
+    pub async fn timed_out(&self) {
+        match &self.own_cancellation {
+            None => std::future::pending().await,
+            Some(own) => own.chan.closed().await,
+        }
+    }
+
+    /// replaces generic errors with [`rt::errors::VMError::sub_limit`] if they were caused by sub-VM limits
+    pub fn patch_result(
+        &self,
+        res: anyhow::Result<rt::vm::RunOk>,
+    ) -> anyhow::Result<rt::vm::RunOk> {
+        if self.is_timed_out() {
+            let cause = match res {
+                Ok(rt::vm::RunOk::VMError(msg, cause)) => cause.map(|c| c.context(msg)),
+                Ok(_) => None,
+                Err(e) => Some(e),
+            };
+            return Ok(rt::vm::RunOk::VMError(
+                rt::errors::VMError::sub_limit("timeout", None).0,
+                cause,
+            ));
+        }
+
+        match res {
+            Ok(rt::vm::RunOk::VMError(msg, cause))
+                if self.limiter.sub_limit_hit()
+                    && msg.starts_with(crate::public_abi::VmError::Oom.value()) =>
+            {
+                Ok(rt::vm::RunOk::VMError(
+                    rt::errors::VMError::sub_limit("memory", None).0,
+                    cause.map(|c| c.context(msg)),
+                ))
+            }
+            res => res,
+        }
+    }
+}
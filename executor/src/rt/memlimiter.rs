@@ -1,11 +1,20 @@
 use genvm_common::*;
-use std::sync::{atomic::AtomicU32, Arc};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32},
+    Arc,
+};
 
 use crate::{public_abi, rt};
 
+/// Largest linear memory limiter can account for, in whole wasm pages
+pub const MAX_MEMORY_SIZE: u64 = (u32::MAX as u64 / 65536) * 65536;
+
 struct LimiterInnerData {
     remaining_memory: Arc<AtomicU32>,
     least_remaining_memory: Arc<AtomicU32>,
+    /// budgets of sub-VMs this limiter is nested in, outermost first
+    sub_budgets: Box<[Arc<AtomicU32>]>,
+    sub_limit_hit: Arc<AtomicBool>,
 }
 
 struct LimiterInner {
@@ -37,6 +46,39 @@ impl LimiterInner {
             .remaining_memory
             .fetch_add(delta, std::sync::atomic::Ordering::SeqCst);
         assert!(previous.checked_add(delta).is_some());
+
+        self.release_sub_budgets(&self.data.sub_budgets, delta);
+    }
+
+    fn release_sub_budgets(&self, budgets: &[Arc<AtomicU32>], delta: u32) {
+        for budget in budgets {
+            budget.fetch_add(delta, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    fn consume_sub_budgets(&self, delta: u32) -> bool {
+        let budgets = &self.data.sub_budgets;
+
+        for (i, budget) in budgets.iter().enumerate() {
+            let res = budget.fetch_update(
+                std::sync::atomic::Ordering::SeqCst,
+                std::sync::atomic::Ordering::SeqCst,
+                |current| current.checked_sub(delta),
+            );
+
+            if res.is_err() {
+                self.release_sub_budgets(&budgets[..i], delta);
+                self.data
+                    .sub_limit_hit
+                    .store(true, std::sync::atomic::Ordering::SeqCst);
+
+                log_debug!(delta = delta, id = self.id, depth = i; "sub-VM memory budget exhausted");
+
+                return false;
+            }
+        }
+
+        true
     }
 }
 
@@ -55,6 +97,8 @@ impl Limiter {
             data: Arc::new(LimiterInnerData {
                 remaining_memory: Arc::new(AtomicU32::new(u32::MAX)),
                 least_remaining_memory: Arc::new(AtomicU32::new(u32::MAX)),
+                sub_budgets: Box::new([]),
+                sub_limit_hit: Arc::new(AtomicBool::new(false)),
             }),
         }))
     }
@@ -66,10 +110,46 @@ impl Limiter {
             data: Arc::new(LimiterInnerData {
                 remaining_memory: self.0.data.remaining_memory.clone(),
                 least_remaining_memory: self.0.data.least_remaining_memory.clone(),
+                sub_budgets: self.0.data.sub_budgets.clone(),
+                sub_limit_hit: self.0.data.sub_limit_hit.clone(),
+            }),
+        }))
+    }
+
+    /// Same as [`Limiter::derived`], but additionally caps total consumption of
+    /// the new limiter (and all limiters derived from it) by `budget` bytes
+    pub fn derived_with_budget(&self, budget: u32) -> Self {
+        let mut sub_budgets = Vec::from(&self.0.data.sub_budgets[..]);
+        sub_budgets.push(Arc::new(AtomicU32::new(budget)));
+
+        Self(Arc::new(LimiterInner {
+            id: self.0.id,
+            consumed_memory: AtomicU32::new(0),
+            data: Arc::new(LimiterInnerData {
+                remaining_memory: self.0.data.remaining_memory.clone(),
+                least_remaining_memory: self.0.data.least_remaining_memory.clone(),
+                sub_budgets: sub_budgets.into_boxed_slice(),
+                sub_limit_hit: Arc::new(AtomicBool::new(false)),
             }),
         }))
     }
 
+    /// whenever allocation failed due to sub-VM budget rather than the global one
+    pub fn sub_limit_hit(&self) -> bool {
+        self.0
+            .data
+            .sub_limit_hit
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    fn exhausted_error(&self) -> rt::errors::VMError {
+        if self.sub_limit_hit() {
+            rt::errors::VMError::sub_limit("memory", None)
+        } else {
+            rt::errors::VMError::oom(None)
+        }
+    }
+
     pub fn consume_mul(&self, delta: u32, multiplier: u32) -> bool {
         let delta = match delta.checked_mul(multiplier) {
             Some(delta) => delta,
@@ -86,6 +166,16 @@ impl Limiter {
             .load(std::sync::atomic::Ordering::SeqCst)
     }
 
+    /// Largest amount that can be consumed right now, accounting for sub-VM budgets
+    pub fn get_available_memory(&self) -> u32 {
+        self.0
+            .data
+            .sub_budgets
+            .iter()
+            .map(|x| x.load(std::sync::atomic::Ordering::SeqCst))
+            .fold(self.get_remaining_memory(), u32::min)
+    }
+
     pub fn release(&self, delta: u32) {
         self.0.release_no_consumed(delta);
         self.0
@@ -102,8 +192,13 @@ impl Limiter {
 
         log_debug!(delta = delta, remaining_at_op_start = remaining, id = self.0.id; "consume");
 
+        if !self.0.consume_sub_budgets(delta) {
+            return false;
+        }
+
         loop {
             if delta > remaining {
+                self.0.release_sub_budgets(&self.0.data.sub_budgets, delta);
                 return false;
             }
 
@@ -148,7 +243,7 @@ impl wasmtime::ResourceLimiter for Limiter {
         let success = self.consume(delta);
 
         if current == 0 && !success {
-            Err(rt::errors::VMError::oom(None).into())
+            Err(self.exhausted_error().into())
         } else {
             Ok(success)
         }
@@ -170,7 +265,7 @@ impl wasmtime::ResourceLimiter for Limiter {
         let success = self.consume_mul(delta, public_abi::MemoryLimiterConsts::TableEntry.value());
 
         if current == 0 && !success {
-            Err(rt::errors::VMError::oom(None).into())
+            Err(self.exhausted_error().into())
         } else {
             Ok(success)
         }
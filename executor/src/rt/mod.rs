@@ -1,15 +1,57 @@
+pub mod debugger;
 pub mod errors;
+pub mod limits;
 pub mod memlimiter;
+pub mod profiling;
+pub mod random;
+pub mod spans;
 pub mod supervisor;
 pub mod vm;
 
 use std::sync::Arc;
 
+#[derive(Default, Debug, serde::Serialize)]
+pub struct CacheMetrics {
+    hits: genvm_common::stats::metric::Count,
+    misses: genvm_common::stats::metric::Count,
+    evictions: genvm_common::stats::metric::Count,
+}
+
+impl CacheMetrics {
+    pub fn record<T>(&self, lookup: &genvm_common::sync::LruLookup<T>) {
+        if lookup.hit {
+            self.hits.increment();
+        } else {
+            self.misses.increment();
+        }
+
+        for _ in 0..lookup.evicted {
+            self.evictions.increment();
+        }
+    }
+}
+
 #[derive(Default, Debug, serde::Serialize)]
 pub struct Metrics {
     precompile_hits: genvm_common::stats::metric::Count,
+    /// precompiled modules that failed integrity check or deserialization
+    precompile_rejected: genvm_common::stats::metric::Count,
     compiled_modules: genvm_common::stats::metric::Count,
     compilation_time: genvm_common::stats::metric::Time,
+    snapshot_hits: genvm_common::stats::metric::Count,
+    snapshots_created: genvm_common::stats::metric::Count,
+    /// VMs that were allocated from the instance pool
+    pooled_vms: genvm_common::stats::metric::Count,
+    /// most VMs of a single engine observed in the pool at the same time, including other executions
+    pool_peak_vms: genvm_common::stats::metric::Max,
+    /// VMs that had to wait for a pool slot
+    pool_exhausted: genvm_common::stats::metric::Count,
+    archive_cache: CacheMetrics,
+    module_cache: CacheMetrics,
+    /// time non-deterministic VMs spent sleeping in `poll_oneoff`
+    pub(crate) guest_sleep: genvm_common::stats::metric::Time,
+    /// time by which deterministic VMs advanced their virtual clocks in `poll_oneoff`
+    pub(crate) guest_virtual_sleep: genvm_common::stats::metric::Time,
 }
 
 pub struct DetNondet<T> {
@@ -43,6 +85,10 @@ pub struct SharedData {
     pub debug_mode: bool,
     pub metrics: crate::Metrics,
     pub storage_pages_limit: std::sync::atomic::AtomicU64,
+    /// sampling interval of guest profiler, see [`profiling`]
+    pub profile_interval: Option<std::time::Duration>,
+    /// set only in debug mode, see [`debugger`]
+    pub debugger: Option<Arc<debugger::Debugger>>,
 }
 
 pub fn parse_host_data(
@@ -0,0 +1,224 @@
+//! Sampling of guest stacks in debug mode, see `--profile` of `genvm run`
+//!
+//! Engines are created with epoch interruption, a background thread increments epochs
+//! each interval and every VM records its stack in the epoch deadline callback.
+//! Frames are named after module name sections, which are produced by `genvm-wasm-add-mod-name`
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32},
+    Arc, Mutex,
+};
+
+use genvm_common::*;
+
+use crate::{public_abi, rt};
+
+pub const DEFAULT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// Profile of a single VM in Firefox profiler format
+#[derive(Debug, Clone)]
+pub struct VMProfile {
+    pub contract: calldata::Address,
+    pub entry_kind: public_abi::EntryKind,
+    pub is_deterministic: bool,
+    /// index of the VM in order of profiling start
+    pub index: u32,
+    pub json: Vec<u8>,
+}
+
+/// Profiler of a single VM, lives in store data
+pub struct Sampler {
+    profiler: wasmtime::GuestProfiler,
+    last_sample: std::time::Instant,
+    contract: calldata::Address,
+    entry_kind: public_abi::EntryKind,
+    is_deterministic: bool,
+    index: u32,
+}
+
+impl Sampler {
+    pub fn sample(&mut self, store: impl wasmtime::AsContext) {
+        let now = std::time::Instant::now();
+        self.profiler.sample(store, now - self.last_sample);
+        self.last_sample = now;
+    }
+}
+
+/// Sets `stop` flag on drop, which terminates the epoch ticking thread
+struct Ticker(Arc<AtomicBool>);
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Profiler of a single execution, owned by [`rt::supervisor::Supervisor`]
+pub struct Profiler {
+    interval: std::time::Duration,
+    next_index: AtomicU32,
+    profiles: Mutex<Vec<VMProfile>>,
+    _ticker: Ticker,
+}
+
+impl Profiler {
+    /// `engines` must be created with epoch interruption
+    pub fn start(engines: &rt::DetNondet<wasmtime::Engine>, interval: std::time::Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let engines = rt::DetNondet {
+            det: engines.det.clone(),
+            non_det: engines.non_det.clone(),
+        };
+        let stop_copy = stop.clone();
+        std::thread::spawn(move || {
+            while !stop_copy.load(std::sync::atomic::Ordering::SeqCst) {
+                std::thread::sleep(interval);
+                engines.det.increment_epoch();
+                engines.non_det.increment_epoch();
+            }
+        });
+
+        Self {
+            interval,
+            next_index: AtomicU32::new(0),
+            profiles: Mutex::new(Vec::new()),
+            _ticker: Ticker(stop),
+        }
+    }
+
+    /// Creates sampler of VM that has all `modules` linked
+    pub fn start_vm(
+        &self,
+        contract: calldata::Address,
+        entry_kind: public_abi::EntryKind,
+        is_deterministic: bool,
+        modules: Vec<(String, wasmtime::Module)>,
+    ) -> Sampler {
+        let mode = if is_deterministic { "det" } else { "nondet" };
+        let name = format!("{contract} {} {mode}", entry_kind.str_snake_case());
+
+        Sampler {
+            profiler: wasmtime::GuestProfiler::new(&name, self.interval, modules),
+            last_sample: std::time::Instant::now(),
+            contract,
+            entry_kind,
+            is_deterministic,
+            index: self
+                .next_index
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst),
+        }
+    }
+
+    pub fn finish_vm(&self, sampler: Sampler) {
+        let mut json = Vec::new();
+        if let Err(e) = sampler.profiler.finish(&mut json) {
+            log_warn!(error:ah = e, contract:? = sampler.contract; "failed to finish profile");
+            return;
+        }
+
+        self.profiles.lock().unwrap().push(VMProfile {
+            contract: sampler.contract,
+            entry_kind: sampler.entry_kind,
+            is_deterministic: sampler.is_deterministic,
+            index: sampler.index,
+            json,
+        });
+    }
+
+    /// Profiles of finished VMs, ordered by [`VMProfile::index`]
+    pub fn take_profiles(&self) -> Vec<VMProfile> {
+        let mut profiles = std::mem::take(&mut *self.profiles.lock().unwrap());
+        profiles.sort_by_key(|p| p.index);
+        profiles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: u32 = 3;
+
+    /// runs an endless loop until it is sampled [`SAMPLES`] times
+    fn run_sampled(module: &wasmtime::Module, sampler: Sampler) -> Sampler {
+        let mut store = wasmtime::Store::new(
+            module.engine(),
+            (Some(sampler), 0u32),
+            wasmtime::GenVMCtx {
+                should_capture_fp: Arc::new(false.into()),
+                should_quit: Arc::new(0.into()),
+            },
+        );
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|mut ctx| {
+            let mut sampler = ctx.data_mut().0.take().unwrap();
+            sampler.sample(&ctx);
+            ctx.data_mut().0 = Some(sampler);
+            ctx.data_mut().1 += 1;
+            if ctx.data().1 >= SAMPLES {
+                anyhow::bail!("enough samples");
+            }
+            Ok(wasmtime::UpdateDeadline::Continue(1))
+        });
+
+        let instance = wasmtime::Instance::new(&mut store, module, &[]).unwrap();
+        let spin = instance
+            .get_typed_func::<(), ()>(&mut store, "spin")
+            .unwrap();
+        assert!(spin.call(&mut store, ()).is_err());
+        assert_eq!(store.data().1, SAMPLES);
+
+        store.data_mut().0.take().unwrap()
+    }
+
+    #[test]
+    fn samples_named_frames() {
+        let engines = rt::supervisor::create_engines(|conf| {
+            conf.async_support(false).epoch_interruption(true);
+            Ok(())
+        })
+        .unwrap();
+        let profiler = Profiler::start(&engines, DEFAULT_INTERVAL);
+
+        let wasm = wat::parse_str(
+            r#"(module $spinner
+                (func $busy_loop (export "spin")
+                    (loop $l (br $l))))"#,
+        )
+        .unwrap();
+        let module = wasmtime::Module::new(&engines.det, &wasm).unwrap();
+
+        let first = profiler.start_vm(
+            calldata::Address::from([1; calldata::ADDRESS_SIZE]),
+            public_abi::EntryKind::Main,
+            true,
+            vec![("spinner".to_owned(), module.clone())],
+        );
+        let second = profiler.start_vm(
+            calldata::Address::from([2; calldata::ADDRESS_SIZE]),
+            public_abi::EntryKind::Sandbox,
+            true,
+            vec![("spinner".to_owned(), module.clone())],
+        );
+
+        // finished in reverse order, profiles are still ordered by start
+        let second = run_sampled(&module, second);
+        profiler.finish_vm(second);
+        let first = run_sampled(&module, first);
+        profiler.finish_vm(first);
+
+        let profiles = profiler.take_profiles();
+        assert_eq!(
+            profiles.iter().map(|p| p.index).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(profiles[1].entry_kind, public_abi::EntryKind::Sandbox);
+        for profile in &profiles {
+            let json = String::from_utf8(profile.json.clone()).unwrap();
+            assert!(json.contains("busy_loop"), "{json}");
+        }
+
+        assert!(profiler.take_profiles().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+//! Derivation of seeds for deterministic `random_get`, see `random_get` section of wasip1 specification
+//!
+//! All inputs come from the host and from the order of calls within deterministic VMs,
+//! so every validator derives exactly the same seeds.
+//! Contracts below [`public_abi::RANDOM_SEED_MIN_VERSION`] always use the legacy constant seed,
+//! so that re-execution of old transactions is not affected by the host providing a block hash
+
+use sha2::Digest as _;
+
+use genvm_common::*;
+
+use crate::public_abi;
+
+pub type Seed = [u8; 32];
+
+const ROOT_DOMAIN: &[u8] = b"genvm-random-v1";
+const CHILD_DOMAIN: &[u8] = b"genvm-random-child-v1";
+const COMMITMENT_DOMAIN: &[u8] = b"genvm-random-commitment-v1";
+
+const LEGACY_MT19937_SEED: [u32; 2] = [u32::from_le_bytes(*b"GenL"), u32::from_le_bytes(*b"ayer")];
+
+/// Key of [`genvm_modules_interfaces::HostData`] that holds block hash
+pub const BLOCK_HASH_KEY: &str = "block_hash";
+
+fn update_prefixed(hasher: &mut sha2::Sha256, data: &[u8]) {
+    hasher.update((data.len() as u32).to_le_bytes());
+    hasher.update(data);
+}
+
+/// Returns seed of the topmost VM, or `None` if host did not provide a block hash,
+/// in which case legacy constant seed is used
+pub fn root_seed(host_data: &genvm_modules_interfaces::HostData) -> anyhow::Result<Option<Seed>> {
+    let block_hash = match host_data.rest.get(BLOCK_HASH_KEY) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(serde_json::Value::String(s)) => s,
+        Some(_) => anyhow::bail!("host data `{BLOCK_HASH_KEY}` must be a string"),
+    };
+
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(ROOT_DOMAIN);
+    update_prefixed(&mut hasher, host_data.tx_id.as_bytes());
+    update_prefixed(&mut hasher, block_hash.as_bytes());
+
+    Ok(Some(hasher.finalize().into()))
+}
+
+/// Derives seed of a deterministic VM spawned by the VM with `parent` seed
+///
+/// `call_no` is the index of this VM among deterministic VMs spawned by the parent,
+/// which together with the chain of parents identifies the call stack
+pub fn child_seed(
+    parent: &Seed,
+    call_no: u32,
+    address: calldata::Address,
+    entry_kind: public_abi::EntryKind,
+) -> Seed {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(CHILD_DOMAIN);
+    hasher.update(parent);
+    hasher.update(call_no.to_le_bytes());
+    hasher.update(address.raw());
+    hasher.update([entry_kind.value()]);
+
+    hasher.finalize().into()
+}
+
+/// Value that contract can publish to prove which seed was used, without revealing it
+pub fn commitment(seed: &Seed) -> [u8; 32] {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(COMMITMENT_DOMAIN);
+    hasher.update(seed);
+
+    hasher.finalize().into()
+}
+
+/// Seed of mt19937: 8 little-endian words of the seed
+pub fn mt19937_seed(seed: &Seed) -> [u32; 8] {
+    std::array::from_fn(|i| u32::from_le_bytes(seed[i * 4..i * 4 + 4].try_into().unwrap()))
+}
+
+/// Generator of deterministic `random_get`, `None` stands for the legacy constant seed
+pub fn rng(seed: Option<&Seed>) -> mt19937::MT19937 {
+    match seed {
+        Some(seed) => mt19937::MT19937::new_with_slice_seed(&mt19937_seed(seed)),
+        None => mt19937::MT19937::new_with_slice_seed(&LEGACY_MT19937_SEED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_data(block_hash: Option<&str>) -> genvm_modules_interfaces::HostData {
+        let mut rest = serde_json::Map::new();
+        if let Some(block_hash) = block_hash {
+            rest.insert(BLOCK_HASH_KEY.into(), block_hash.into());
+        }
+        genvm_modules_interfaces::HostData {
+            node_address: "0x".into(),
+            tx_id: "0x01".into(),
+            rest,
+        }
+    }
+
+    #[test]
+    fn legacy_without_block_hash() {
+        assert_eq!(root_seed(&host_data(None)).unwrap(), None);
+    }
+
+    #[test]
+    fn known_vectors() {
+        let root = root_seed(&host_data(Some("0x02"))).unwrap().unwrap();
+        assert_eq!(
+            hex::encode(root),
+            "5ac611fda9fe56db72dc5a1fba563d2e08af3e8037aa7b5d04406d8948df17d7"
+        );
+
+        let child = child_seed(
+            &root,
+            1,
+            calldata::Address::from([3; 20]),
+            public_abi::EntryKind::Main,
+        );
+        assert_eq!(
+            hex::encode(child),
+            "731aae9ba68de3f72330a178f750decbe05663f7c3a5878a888374d499eb4e67"
+        );
+        assert_ne!(
+            child,
+            child_seed(
+                &root,
+                2,
+                calldata::Address::from([3; 20]),
+                public_abi::EntryKind::Main,
+            )
+        );
+
+        assert_eq!(
+            hex::encode(commitment(&root)),
+            "9cc062b1d53f4568723a84a0af261470f17b8e1a24ca577bcc272879709e05a8"
+        );
+    }
+
+    #[test]
+    fn random_get_streams() {
+        use rand_core::RngCore as _;
+
+        let mut legacy = rng(None);
+        assert_eq!(legacy.next_u32(), 0xb8bc73fd);
+        assert_eq!(legacy.next_u32(), 0x78b639b2);
+
+        let root = root_seed(&host_data(Some("0x02"))).unwrap().unwrap();
+        let mut derived = rng(Some(&root));
+        assert_eq!(derived.next_u32(), 0x871427f4);
+        assert_eq!(derived.next_u32(), 0x2e8d27ad);
+    }
+}
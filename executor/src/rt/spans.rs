@@ -0,0 +1,285 @@
+//! Contract-level call tree built from `Trace.SpanBegin` and `Trace.SpanEnd`
+//!
+//! Spans with the same name under the same parent are merged, so that loops produce a single node.
+//! Durations are measured by the host and never returned to the guest,
+//! which keeps deterministic execution independent of timing
+
+use std::time::{Duration, Instant};
+
+use genvm_common::*;
+
+use crate::public_abi;
+
+/// Nodes above this limit are not created, their time and calls are attributed to the parent
+const MAX_NODES: usize = 4096;
+const MAX_NAME_LEN: usize = 256;
+/// Limit of nodes kept by [`Recorded`] for all VMs of an execution, trees of VMs above it are dropped
+const MAX_RECORDED_NODES: usize = 4 * MAX_NODES;
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Span {
+    pub name: String,
+    /// how many times span was entered
+    pub count: u64,
+    pub duration_us: u64,
+    /// gl_calls made in this span, excluding its children and `Trace` messages
+    pub gl_calls: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Span>,
+}
+
+impl Span {
+    fn node_count(&self) -> usize {
+        1 + self.children.iter().map(Span::node_count).sum::<usize>()
+    }
+}
+
+/// Call tree of a single VM, its root spans the whole execution
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VMSpans {
+    pub contract: calldata::Address,
+    pub entry_kind: public_abi::EntryKind,
+    pub is_deterministic: bool,
+    pub root: Span,
+}
+
+/// Call trees of finished VMs, in order of finishing
+#[derive(Default)]
+pub struct Recorded {
+    spans: Vec<VMSpans>,
+    nodes: usize,
+    dropped: usize,
+}
+
+impl Recorded {
+    pub fn push(&mut self, spans: VMSpans) {
+        let nodes = spans.root.node_count();
+        if self.nodes + nodes > MAX_RECORDED_NODES {
+            if self.dropped == 0 {
+                log_warn!(limit = MAX_RECORDED_NODES; "trace span limit reached, dropping call trees");
+            }
+            self.dropped += 1;
+            return;
+        }
+
+        self.nodes += nodes;
+        self.spans.push(spans);
+    }
+
+    /// Whenever any VM marked a span
+    pub fn has_spans(&self) -> bool {
+        self.spans.iter().any(|x| !x.root.children.is_empty())
+    }
+
+    pub fn spans(&self) -> &[VMSpans] {
+        &self.spans
+    }
+
+    /// Amount of VMs whose call trees were dropped due to [`MAX_RECORDED_NODES`]
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+struct Node {
+    name: String,
+    count: u64,
+    duration: Duration,
+    gl_calls: u64,
+    children: Vec<usize>,
+}
+
+pub struct Tree {
+    nodes: Vec<Node>,
+    /// open spans, root is always at the bottom; `None` start marks span that was not created due to [`MAX_NODES`]
+    stack: Vec<(usize, Option<Instant>)>,
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                name: String::new(),
+                count: 1,
+                duration: Duration::ZERO,
+                gl_calls: 0,
+                children: Vec::new(),
+            }],
+            stack: vec![(0, Some(Instant::now()))],
+        }
+    }
+
+    fn top(&self) -> usize {
+        self.stack.last().unwrap().0
+    }
+
+    pub fn begin(&mut self, mut name: String) {
+        if name.len() > MAX_NAME_LEN {
+            let mut end = MAX_NAME_LEN;
+            while !name.is_char_boundary(end) {
+                end -= 1;
+            }
+            name.truncate(end);
+        }
+
+        let parent = self.top();
+        let existing = self.nodes[parent]
+            .children
+            .iter()
+            .cloned()
+            .find(|&child| self.nodes[child].name == name);
+
+        let node = match existing {
+            Some(node) => node,
+            None if self.nodes.len() >= MAX_NODES => {
+                self.stack.push((parent, None));
+                return;
+            }
+            None => {
+                self.nodes.push(Node {
+                    name,
+                    count: 0,
+                    duration: Duration::ZERO,
+                    gl_calls: 0,
+                    children: Vec::new(),
+                });
+                let node = self.nodes.len() - 1;
+                self.nodes[parent].children.push(node);
+                node
+            }
+        };
+
+        self.nodes[node].count += 1;
+        self.stack.push((node, Some(Instant::now())));
+    }
+
+    /// Unbalanced end is ignored, as root can't be closed by the guest
+    pub fn end(&mut self) {
+        if self.stack.len() <= 1 {
+            log_warn!("trace span end without begin");
+            return;
+        }
+
+        self.pop();
+    }
+
+    fn pop(&mut self) {
+        if let Some((node, Some(start))) = self.stack.pop() {
+            self.nodes[node].duration += start.elapsed();
+        }
+    }
+
+    pub fn record_gl_call(&mut self) {
+        let top = self.top();
+        self.nodes[top].gl_calls += 1;
+    }
+
+    /// Closes all open spans, root is named `name`
+    pub fn finish(mut self, name: String) -> Span {
+        while !self.stack.is_empty() {
+            self.pop();
+        }
+        self.nodes[0].name = name;
+
+        self.make_span(0)
+    }
+
+    fn make_span(&self, node: usize) -> Span {
+        let data = &self.nodes[node];
+        Span {
+            name: data.name.clone(),
+            count: data.count,
+            duration_us: data.duration.as_micros() as u64,
+            gl_calls: data.gl_calls,
+            children: data.children.iter().map(|&x| self.make_span(x)).collect(),
+        }
+    }
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_siblings() {
+        let mut tree = Tree::new();
+
+        tree.record_gl_call();
+        for _ in 0..3 {
+            tree.begin("loop".into());
+            tree.record_gl_call();
+            tree.begin("inner".into());
+            tree.end();
+            tree.end();
+        }
+        tree.end();
+        tree.begin("unclosed".into());
+
+        let root = tree.finish("root".into());
+        assert_eq!(root.name, "root");
+        assert_eq!(root.gl_calls, 1);
+        assert_eq!(root.children.len(), 2);
+
+        let looped = &root.children[0];
+        assert_eq!(
+            (looped.name.as_str(), looped.count, looped.gl_calls),
+            ("loop", 3, 3)
+        );
+        assert_eq!(looped.children.len(), 1);
+        assert_eq!(looped.children[0].count, 3);
+
+        assert_eq!(root.children[1].name, "unclosed");
+    }
+
+    #[test]
+    fn node_limit() {
+        let mut tree = Tree::new();
+
+        for i in 0..MAX_NODES + 10 {
+            tree.begin(format!("span {i}"));
+            tree.record_gl_call();
+            tree.end();
+        }
+
+        let root = tree.finish("root".into());
+        assert_eq!(root.children.len(), MAX_NODES - 1);
+        assert_eq!(root.gl_calls, 11);
+    }
+
+    #[test]
+    fn recorded_limit() {
+        let vm_spans = |children: usize| {
+            let mut tree = Tree::new();
+            for i in 0..children {
+                tree.begin(format!("span {i}"));
+                tree.end();
+            }
+            VMSpans {
+                contract: calldata::Address::zero(),
+                entry_kind: public_abi::EntryKind::Main,
+                is_deterministic: true,
+                root: tree.finish("root".into()),
+            }
+        };
+
+        let mut recorded = Recorded::default();
+        recorded.push(vm_spans(0));
+        assert!(!recorded.has_spans());
+
+        for _ in 0..4 {
+            recorded.push(vm_spans(MAX_NODES - 1));
+        }
+        assert!(recorded.has_spans());
+        assert_eq!((recorded.spans().len(), recorded.dropped()), (4, 1));
+
+        // smaller trees still fit
+        recorded.push(vm_spans(0));
+        assert_eq!((recorded.spans().len(), recorded.dropped()), (5, 1));
+    }
+}
@@ -1,15 +1,36 @@
 use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr as _;
 
 use crate::{caching, public_abi, rt, runners};
 
+use super::snapshot;
+
 use anyhow::Context;
 use genvm_common::*;
 use symbol_table::GlobalSymbol;
 
+/// Size of the compiled code, used to budget the modules cache
+fn compiled_size(modules: &rt::DetNondet<wasmtime::Module>) -> u64 {
+    [&modules.det, &modules.non_det]
+        .into_iter()
+        .map(|m| {
+            let range = m.image_range();
+            (range.end as usize - range.start as usize) as u64
+        })
+        .sum()
+}
+
 pub struct Ctx<'a, 'b> {
     pub env: BTreeMap<String, String>,
     pub visited: HashSet<symbol_table::GlobalSymbol>,
+    /// instances added with [`runners::InitAction::LinkWasm`] and whenever their state can be snapshotted
+    pub linked: Vec<(wasmtime::Instance, bool)>,
+    /// all modules instantiated in the VM, named after their name section or path, see [`rt::profiling`]
+    pub modules: Vec<(String, wasmtime::Module)>,
+    /// actions that can affect runner initialization, they are part of the snapshot key
+    pub trail: Vec<String>,
     pub contract_id: symbol_table::GlobalSymbol,
+    pub contract_arch: sync::DArc<runners::ArchiveCache>,
     pub supervisor: &'a rt::supervisor::Supervisor,
     pub vm: &'b mut rt::vm::VMBase,
 }
@@ -30,29 +51,45 @@ impl Ctx<'_, '_> {
 
         let limiter = &self.vm.store.data_mut().limits;
 
-        let new_arch = self
+        let lookup = self
             .supervisor
+            .caches
             .runner_cache
             .get_or_create(
                 uid,
                 || async {
-                    let mut path = self.supervisor.runner_cache.runners_path().to_owned();
-                    runners::append_runner_subpath(runner_id, runner_hash, &mut path);
-                    path.set_extension("tar");
-                    if !path.exists() {
+                    let runner_cache = &self.supervisor.caches.runner_cache;
+                    let Some((path, format)) = runners::find_runner_archive(
+                        runner_cache.runners_path(),
+                        runner_id,
+                        runner_hash,
+                    ) else {
                         anyhow::bail!("runner {} not found", uid);
-                    }
+                    };
 
-                    let data = util::mmap_file(&path)
-                        .with_context(|| format!("creating new archive for {uid}"))?;
-                    let data = util::SharedBytes::new(data);
+                    // archive is charged after it is read, so it can't be larger than what is left
+                    let max_size = limiter.get_available_memory();
+                    let data = tokio::task::spawn_blocking({
+                        let path = path.clone();
+                        move || runners::read_runner_archive(&path, format, max_size)
+                    })
+                    .await?
+                    .with_context(|| format!("creating new archive for {uid}"))?;
+                    runner_cache.verify(uid, runner_hash, &path, data.as_ref())?;
                     runners::Archive::from_ustar(data)
                 },
                 limiter,
             )
             .await?;
 
-        Ok((uid, new_arch))
+        self.supervisor
+            .shared_data
+            .metrics
+            .supervisor
+            .archive_cache
+            .record(&lookup);
+
+        Ok((uid, lookup.value))
     }
 
     fn full_check_runner_uid(
@@ -72,7 +109,7 @@ impl Ctx<'_, '_> {
             if !self.supervisor.shared_data.debug_mode {
                 log_warn!(":test/ :latest runner used in non-debug mode, this is not allowed");
             }
-            self.supervisor.runner_cache.get_latest(runner_id)
+            self.supervisor.caches.runner_cache.get_latest(runner_id)
         } else {
             Some(GlobalSymbol::new(runner_hash))
         };
@@ -83,6 +120,7 @@ impl Ctx<'_, '_> {
 
         if !self
             .supervisor
+            .caches
             .runner_cache
             .has_in_all(runner_id, runner_hash)
         {
@@ -105,44 +143,227 @@ impl Ctx<'_, '_> {
             return Ok(None);
         };
 
-        let special_name = caching::path_in_zip_to_hash(path);
-        let Some(cache_dir) = &self.supervisor.wasm_mod_cache.cache_dir else {
+        let Some(cache_dir) = &self.supervisor.caches.wasm_mod_cache.cache_dir else {
             return Ok(None);
         };
 
-        let mut cache_dir = cache_dir.to_owned();
-        cache_dir.push(caching::PRECOMPILE_DIR_NAME);
-        runners::append_runner_subpath(id, hash, &mut cache_dir);
-        cache_dir.push(special_name);
+        let caches = &self.supervisor.caches;
+
+        let mut dir = cache_dir.to_owned();
+        dir.push(caching::PRECOMPILE_DIR_NAME);
+        runners::append_runner_subpath(id, hash, &mut dir);
+
+        let det_mod = caching::precompiled_module_path(
+            &dir,
+            path,
+            &caches.engine_hashes.det,
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det,
+        );
+        let non_det_mod = caching::precompiled_module_path(
+            &dir,
+            path,
+            &caches.engine_hashes.non_det,
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det,
+        );
+
+        let loaded = (|| -> anyhow::Result<Option<rt::DetNondet<wasmtime::Module>>> {
+            let Some(det_data) = caching::read_precompiled(&det_mod, &caches.engine_hashes.det)?
+            else {
+                return Ok(None);
+            };
+            let Some(non_det_data) =
+                caching::read_precompiled(&non_det_mod, &caches.engine_hashes.non_det)?
+            else {
+                return Ok(None);
+            };
+
+            // sidecar guarantees that these are intact artifacts of `genvm precompile`
+            // compiled by an engine with the same configuration
+            Ok(Some(rt::DetNondet {
+                det: unsafe { wasmtime::Module::deserialize(&caches.engines.det, det_data) }?,
+                non_det: unsafe {
+                    wasmtime::Module::deserialize(&caches.engines.non_det, non_det_data)
+                }?,
+            }))
+        })();
+
+        match loaded {
+            Ok(Some(loaded)) => {
+                self.supervisor
+                    .shared_data
+                    .metrics
+                    .supervisor
+                    .precompile_hits
+                    .increment();
+
+                Ok(Some(loaded))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                log_warn!(error:ah = e, runner = current.as_str(), path = path; "precompiled module rejected, falling back to compilation");
+
+                self.supervisor
+                    .shared_data
+                    .metrics
+                    .supervisor
+                    .precompile_rejected
+                    .increment();
 
-        let det_mod = cache_dir.with_extension(caching::DET_NON_DET_PRECOMPILED_SUFFIX.det);
+                Ok(None)
+            }
+        }
+    }
 
-        if !det_mod.exists() {
+    fn trail_name(&self, current: symbol_table::GlobalSymbol) -> &str {
+        if current == self.contract_id {
+            "<contract>"
+        } else {
+            current.as_str()
+        }
+    }
+
+    /// Returns snapshot path and key, if snapshot can be used for this module
+    fn snapshot_location(
+        &self,
+        current: symbol_table::GlobalSymbol,
+        path: &str,
+        wasm: &[u8],
+        module: &wasmtime::Module,
+    ) -> anyhow::Result<Option<(std::path::PathBuf, String)>> {
+        if current == self.contract_id || self.linked.iter().any(|(_, capturable)| !capturable) {
             return Ok(None);
         }
 
-        cache_dir.set_extension(caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det);
-        let non_det_mod = cache_dir;
+        let Some((id, hash)) = runners::verify_runner(current.as_str()) else {
+            return Ok(None);
+        };
+
+        let Some(cache_dir) = &self.supervisor.caches.wasm_mod_cache.cache_dir else {
+            return Ok(None);
+        };
 
-        if !det_mod.exists() {
+        if !snapshot::is_module_capturable(module, wasm)? {
+            log_debug!(runner = current.as_str(), path = path; "module state can't be captured, snapshot is not used");
             return Ok(None);
         }
 
-        self.supervisor
-            .shared_data
-            .metrics
-            .supervisor
-            .precompile_hits
-            .increment();
-
-        Ok(Some(rt::DetNondet {
-            det: unsafe {
-                wasmtime::Module::deserialize_file(&self.supervisor.engines.det, &det_mod)
-            }?,
-            non_det: unsafe {
-                wasmtime::Module::deserialize_file(&self.supervisor.engines.non_det, &non_det_mod)
-            }?,
-        }))
+        let is_det = self.vm.config_copy.is_deterministic;
+
+        let mut key = self.supervisor.caches.engine_hashes.get(is_det).clone();
+        for entry in &self.trail {
+            key.push('\n');
+            key.push_str(entry);
+        }
+        for (k, v) in &self.env {
+            key.push_str(&format!("\nenv {k}={v}"));
+        }
+
+        let suffix = if is_det {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.det
+        } else {
+            caching::DET_NON_DET_PRECOMPILED_SUFFIX.non_det
+        };
+
+        let mut snapshot_path = cache_dir.to_owned();
+        snapshot_path.push(caching::PRECOMPILE_DIR_NAME);
+        runners::append_runner_subpath(id, hash, &mut snapshot_path);
+        snapshot_path.push(format!(
+            "{}.{}.{}.snap",
+            caching::path_in_zip_to_hash(path),
+            caching::path_in_zip_to_hash(&key),
+            suffix
+        ));
+
+        Ok(Some((snapshot_path, key)))
+    }
+
+    /// Calls [`snapshot::INIT_EXPORT`] or restores state it produced, see [`snapshot`]
+    async fn init_or_restore(
+        &mut self,
+        current: symbol_table::GlobalSymbol,
+        path: &str,
+        wasm: &[u8],
+        module: &wasmtime::Module,
+        instance: wasmtime::Instance,
+    ) -> anyhow::Result<()> {
+        let Ok(init) = instance.get_typed_func::<(), ()>(&mut self.vm.store, snapshot::INIT_EXPORT)
+        else {
+            return Ok(());
+        };
+
+        let location = self.snapshot_location(current, path, wasm, module)?;
+
+        let mut instances: Vec<wasmtime::Instance> = self.linked.iter().map(|x| x.0).collect();
+        instances.push(instance);
+
+        if let Some((snapshot_path, key)) = &location {
+            match snapshot::Snapshot::load(snapshot_path, key) {
+                Ok(Some(snap)) => {
+                    if snap.restore(&mut self.vm.store, &instances)? {
+                        log_debug!(runner = current.as_str(), path = path, snapshot:? = snapshot_path; "restored from snapshot");
+
+                        self.supervisor
+                            .shared_data
+                            .metrics
+                            .supervisor
+                            .snapshot_hits
+                            .increment();
+
+                        return Ok(());
+                    }
+
+                    log_warn!(snapshot:? = snapshot_path; "snapshot does not match instances");
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    log_warn!(error:ah = e, snapshot:? = snapshot_path; "failed to load snapshot");
+                }
+            }
+        }
+
+        log_info!(runner = current.as_str(), path = path; "calling snapshot initialization");
+        self.vm
+            .store
+            .data_mut()
+            .genlayer_ctx_mut()
+            .begin_initialization();
+        let res = init.call_async(&mut self.vm.store, ()).await;
+        let finished = self
+            .vm
+            .store
+            .data_mut()
+            .genlayer_ctx_mut()
+            .end_initialization();
+        res?;
+        finished?;
+
+        let Some((snapshot_path, key)) = location else {
+            return Ok(());
+        };
+
+        let res = snapshot::Snapshot::capture(&mut self.vm.store, &instances).and_then(|snap| {
+            let Some(snap) = snap else {
+                log_debug!(runner = current.as_str(), path = path; "globals of unsupported type, snapshot is not created");
+                return Ok(());
+            };
+
+            snap.save(&snapshot_path, &key)?;
+
+            self.supervisor
+                .shared_data
+                .metrics
+                .supervisor
+                .snapshots_created
+                .increment();
+
+            Ok(())
+        });
+
+        if let Err(e) = res {
+            log_warn!(error:ah = e, snapshot:? = snapshot_path; "failed to create snapshot");
+        }
+
+        Ok(())
     }
 
     async fn link_wasm(
@@ -157,11 +378,16 @@ impl Ctx<'_, '_> {
 
         let wasm_key = symbol_table::GlobalSymbol::from(wasm_key);
 
-        let ret_mod = self
-            .supervisor
-            .wasm_mod_cache
-            .wasm_modules_cache
-            .get_or_create(wasm_key, || async {
+        let is_contract = current == self.contract_id;
+
+        let cache = if is_contract {
+            &self.supervisor.contracts_wasm_cache
+        } else {
+            &self.supervisor.caches.wasm_mod_cache.wasm_modules_cache
+        };
+
+        let lookup = cache
+            .get_or_create(wasm_key, compiled_size, || async {
                 if let Some(loaded) = self.load_modules(current, path)? {
                     return Ok(loaded);
                 }
@@ -173,9 +399,56 @@ impl Ctx<'_, '_> {
             })
             .await?;
 
+        if !is_contract {
+            self.supervisor
+                .shared_data
+                .metrics
+                .supervisor
+                .module_cache
+                .record(&lookup);
+        }
+
+        let ret_mod = lookup.value;
+
+        self.check_det_gated_features(wasm_key, is_contract, contents.as_ref())?;
+
         Ok(ret_mod)
     }
 
+    /// Deterministic VMs of contracts below [`public_abi::DET_SIMD_MIN_VERSION`] can't use SIMD
+    fn check_det_gated_features(
+        &self,
+        wasm_key: symbol_table::GlobalSymbol,
+        is_contract: bool,
+        wasm: &[u8],
+    ) -> anyhow::Result<()> {
+        if !self.vm.config_copy.is_deterministic {
+            return Ok(());
+        }
+
+        let min_version = version::Version::from_str(public_abi::DET_SIMD_MIN_VERSION)?;
+        if self.contract_arch.get_version()? >= min_version {
+            return Ok(());
+        }
+
+        let gated = if is_contract {
+            &self.supervisor.contracts_det_gated
+        } else {
+            &self.supervisor.caches.wasm_mod_cache.det_gated_modules
+        };
+
+        if gated.get(&wasm_key).is_some_and(|uses| !*uses) {
+            return Ok(());
+        }
+
+        let res = self
+            .supervisor
+            .validate_det_gated_features(wasm, wasm_key.as_str());
+        gated.insert(wasm_key, res.is_err());
+
+        res
+    }
+
     pub async fn apply(
         &mut self,
         action: &runners::InitAction,
@@ -192,6 +465,10 @@ impl Ctx<'_, '_> {
 
         match action {
             InitAction::MapFile { to, file } => {
+                self.trail
+                    .push(format!("map {}:{file} {to}", self.trail_name(current)));
+                let from_contract = current == self.contract_id;
+
                 if file.ends_with("/") {
                     let is_root = file.as_ref() == "/";
 
@@ -247,7 +524,7 @@ impl Ctx<'_, '_> {
                             .data_mut()
                             .genlayer_ctx_mut()
                             .preview1
-                            .map_file(&name_in_fs, file_contents.clone())?;
+                            .map_file(&name_in_fs, file_contents.clone(), from_contract)?;
                     }
                 } else {
                     let limiter = &self.vm.store.data_mut().limits;
@@ -263,7 +540,7 @@ impl Ctx<'_, '_> {
                         .data_mut()
                         .genlayer_ctx_mut()
                         .preview1
-                        .map_file(to, current_runner_arch.get_file(file)?)?;
+                        .map_file(to, current_runner_arch.get_file(file)?, from_contract)?;
                 }
                 Ok(None)
             }
@@ -277,6 +554,8 @@ impl Ctx<'_, '_> {
                 Ok(None)
             }
             InitAction::SetArgs(args) => {
+                self.trail.push(format!("args {args:?}"));
+
                 self.vm
                     .store
                     .data_mut()
@@ -286,9 +565,12 @@ impl Ctx<'_, '_> {
                 Ok(None)
             }
             InitAction::LinkWasm(path) => {
+                self.trail
+                    .push(format!("link {}:{path}", self.trail_name(current)));
+
                 let contents = current_runner_arch.get_file(path)?;
 
-                let module = self.link_wasm(contents, current, path).await?;
+                let module = self.link_wasm(contents.clone(), current, path).await?;
 
                 let module = module.into_gep(|x| x.get(self.vm.config_copy.is_deterministic));
 
@@ -319,6 +601,15 @@ impl Ctx<'_, '_> {
                         func.call_async(&mut self.vm.store, ()).await?;
                     }
                 }
+
+                let capturable = current != self.contract_id
+                    && snapshot::is_module_capturable(&module, contents.as_ref())?;
+                self.linked.push((instance, capturable));
+                self.modules.push((
+                    module.name().unwrap_or(&path[..]).to_owned(),
+                    wasmtime::Module::clone(&module),
+                ));
+
                 Ok(None)
             }
             InitAction::StartWasm(path) => {
@@ -334,16 +625,24 @@ impl Ctx<'_, '_> {
                     .preview1
                     .set_env(&env)?;
                 let contents = current_runner_arch.get_file(path)?;
-                let module = self.link_wasm(contents, current, path).await?;
+                let module = self.link_wasm(contents.clone(), current, path).await?;
 
                 let module = module.into_gep(|x| x.get(self.vm.config_copy.is_deterministic));
 
-                Ok(Some(
-                    self.vm
-                        .linker
-                        .instantiate_async(&mut self.vm.store, &module)
-                        .await?,
-                ))
+                let instance = self
+                    .vm
+                    .linker
+                    .instantiate_async(&mut self.vm.store, &module)
+                    .await?;
+
+                self.init_or_restore(current, path, contents.as_ref(), &module, instance)
+                    .await?;
+                self.modules.push((
+                    module.name().unwrap_or(&path[..]).to_owned(),
+                    wasmtime::Module::clone(&module),
+                ));
+
+                Ok(Some(instance))
             }
             InitAction::When { cond, action } => {
                 if (*cond == runners::WasmMode::Det) != self.vm.config_copy.is_deterministic {
@@ -351,6 +650,16 @@ impl Ctx<'_, '_> {
                 }
                 Box::pin(self.apply(action, current, current_runner_arch)).await
             }
+            InitAction::WhenVersion { min, max, action } => {
+                let version = self.contract_arch.get_version()?;
+                if !runners::version_in_bounds(version, *min, *max) {
+                    return Ok(None);
+                }
+                Box::pin(self.apply(action, current, current_runner_arch)).await
+            }
+            InitAction::Fail { message } => {
+                Err(anyhow::anyhow!("runner {} failed: {}", current, message))
+            }
             InitAction::Seq(vec) => {
                 for act in vec {
                     if self.supervisor.shared_data.cancellation.is_cancelled() {
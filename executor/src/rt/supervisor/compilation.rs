@@ -1,36 +1,60 @@
 use anyhow::Context;
 
-use crate::rt;
+use crate::{public_abi, rt};
 use genvm_common::*;
 
+/// Features that deterministic modules can use only if contract version is at least [`public_abi::DET_SIMD_MIN_VERSION`]
+pub const DET_GATED_FEATURES: wasmparser::WasmFeatures =
+    wasmparser::WasmFeatures::SIMD.union(wasmparser::WasmFeatures::RELAXED_SIMD);
+
+/// Features that wasmparser must accept for modules compiled by `engine`
+///
+/// Floats are validated here and restricted by the engine itself
+pub fn validation_features(engine: &wasmtime::Engine) -> wasmparser::WasmFeatures {
+    use wasmparser::WasmFeatures;
+
+    let add_features = WasmFeatures::REFERENCE_TYPES.bits() | WasmFeatures::FLOATS.bits();
+
+    let features = engine.config().get_features().bits() | add_features;
+
+    WasmFeatures::from_bits(features).unwrap()
+}
+
 impl super::Supervisor {
-    pub fn validate_wasm(&self, wasm: &[u8]) -> anyhow::Result<()> {
-        use wasmparser::*;
+    fn validation_features(&self, is_det: bool) -> wasmparser::WasmFeatures {
+        validation_features(self.caches.engines.get(is_det))
+    }
 
-        let add_features = WasmFeatures::REFERENCE_TYPES.bits() | WasmFeatures::FLOATS.bits();
+    pub fn validate_wasm(&self, wasm: &[u8], debug_path: &str) -> anyhow::Result<()> {
+        wasmparser::Validator::new_with_features(self.validation_features(true))
+            .validate_all(wasm)
+            .with_context(|| {
+                format!("{debug_path}: module uses features forbidden in deterministic mode")
+            })?;
+        wasmparser::Validator::new_with_features(self.validation_features(false))
+            .validate_all(wasm)
+            .with_context(|| {
+                format!("{debug_path}: module uses features forbidden in non-deterministic mode")
+            })?;
 
-        let det_features = self.engines.det.config().get_features().bits() | add_features;
+        Ok(())
+    }
 
-        let non_det_features = self.engines.non_det.config().get_features().bits() | add_features;
+    /// Checks that module which passed [`Self::validate_wasm`] can be used by deterministic VM of a contract
+    /// below [`public_abi::DET_SIMD_MIN_VERSION`]
+    pub fn validate_det_gated_features(&self, wasm: &[u8], debug_path: &str) -> anyhow::Result<()> {
+        let features = self
+            .validation_features(true)
+            .difference(DET_GATED_FEATURES);
 
-        let mut det_validator = wasmparser::Validator::new_with_features(
-            WasmFeatures::from_bits(det_features).unwrap(),
-        );
-        let mut non_det_validator = wasmparser::Validator::new_with_features(
-            WasmFeatures::from_bits(non_det_features).unwrap(),
-        );
-        det_validator.validate_all(wasm).with_context(|| {
-            format!(
-                "validating {}",
-                &String::from_utf8_lossy(&wasm[..10.min(wasm.len())])
-            )
-        })?;
-        non_det_validator.validate_all(wasm).with_context(|| {
-            format!(
-                "validating {}",
-                &String::from_utf8_lossy(&wasm[..10.min(wasm.len())])
-            )
-        })?;
+        wasmparser::Validator::new_with_features(features)
+            .validate_all(wasm)
+            .with_context(|| {
+                format!(
+                    "{debug_path}: module uses SIMD, which is allowed in deterministic mode only for contracts of version {} or newer",
+                    public_abi::DET_SIMD_MIN_VERSION
+                )
+            })?;
 
         Ok(())
     }
@@ -51,17 +75,17 @@ impl super::Supervisor {
                 .gep(|x| &x.metrics.supervisor.compilation_time),
         );
 
-        self.validate_wasm(wasm)?;
+        self.validate_wasm(wasm, debug_path)?;
 
         let start_time = std::time::Instant::now();
-        let module_det = wasmtime::CodeBuilder::new(&self.engines.det)
+        let module_det = wasmtime::CodeBuilder::new(&self.caches.engines.det)
             .wasm_binary(
                 std::borrow::Cow::Borrowed(wasm),
                 Some(std::path::Path::new(debug_path)),
             )?
             .compile_module()?;
 
-        let module_non_det = wasmtime::CodeBuilder::new(&self.engines.non_det)
+        let module_non_det = wasmtime::CodeBuilder::new(&self.caches.engines.non_det)
             .wasm_binary(
                 std::borrow::Cow::Borrowed(wasm),
                 Some(std::path::Path::new(debug_path)),
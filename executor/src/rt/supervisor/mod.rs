@@ -1,28 +1,36 @@
 use std::{
     collections::{BTreeMap, HashSet},
+    str::FromStr as _,
     sync::{atomic::AtomicU32, Arc},
 };
 
 use genvm_common::*;
 
 use crate::{
-    config, host, public_abi,
+    caching, config, host, public_abi,
     rt::{self, memlimiter, DetNondet},
     runners, wasi,
 };
 
 mod actions;
 mod compilation;
+mod snapshot;
+
+pub use compilation::{validation_features, DET_GATED_FEATURES};
 
 struct WasmModuleCache {
     cache_dir: Option<std::path::PathBuf>,
-    wasm_modules_cache: sync::CacheMap<DetNondet<wasmtime::Module>>,
+    wasm_modules_cache: sync::LruCacheMap<DetNondet<wasmtime::Module>>,
+    /// whenever module uses features that are gated by contract version in deterministic mode,
+    /// see [`compilation::DET_GATED_FEATURES`]
+    det_gated_modules: dashmap::DashMap<symbol_table::GlobalSymbol, bool>,
 }
 
 pub struct NonDetVMTask {
     pub task: wasi::genlayer_sdk::SingleVMData,
     pub call_no: u32,
     pub tasks_done: Arc<tokio::sync::Notify>,
+    pub limits: rt::limits::SubVMLimits,
 }
 
 impl NonDetVMTask {
@@ -48,6 +56,13 @@ struct NondetQueue {
     encountered_error: crossbeam::atomic::AtomicCell<Option<anyhow::Error>>,
 }
 
+struct CapturedVM {
+    contract: calldata::Address,
+    entry_kind: public_abi::EntryKind,
+    is_deterministic: bool,
+    output: Arc<std::sync::Mutex<wasi::vfs::CapturedOutput>>,
+}
+
 pub struct Ctor {
     pub shared_data: sync::DArc<rt::SharedData>,
 
@@ -55,6 +70,8 @@ pub struct Ctor {
 
     pub limiter: rt::DetNondet<rt::memlimiter::Limiter>,
     pub locked_slots: host::LockedSlotsSet,
+    /// seed of the topmost VM, see [`rt::random::root_seed`]
+    pub random_seed: Option<rt::random::Seed>,
 }
 
 pub struct Supervisor {
@@ -62,16 +79,219 @@ pub struct Supervisor {
     pub modules: crate::modules::All,
     pub limiter: rt::DetNondet<rt::memlimiter::Limiter>,
     pub locked_slots: host::LockedSlotsSet,
+    pub random_seed: Option<rt::random::Seed>,
 
     pub nondet_call_no: AtomicU32,
     pub balances: dashmap::DashMap<calldata::Address, primitive_types::U256>,
 
     queue: NondetQueue,
+    caches: Arc<Caches>,
+    /// archives of contracts are cached only for a single execution, as code can change between transactions
+    contracts_cache: sync::LruCacheMap<runners::ArchiveCache>,
+    contracts_wasm_cache: sync::LruCacheMap<DetNondet<wasmtime::Module>>,
+    /// same as [`WasmModuleCache::det_gated_modules`] for contracts
+    contracts_det_gated: dashmap::DashMap<symbol_table::GlobalSymbol, bool>,
+    /// stdout and stderr of all spawned VMs, filled only in debug mode
+    captured_output: std::sync::Mutex<Vec<CapturedVM>>,
+    /// bytes of output that can still be captured, see [`wasi::vfs::CAPTURED_OUTPUT_TOTAL_LIMIT`]
+    captured_output_remaining: Arc<std::sync::atomic::AtomicUsize>,
+    /// set only if [`rt::SharedData::profile_interval`] is
+    pub(crate) profiler: Option<rt::profiling::Profiler>,
+    /// call trees of finished VMs, see [`rt::spans`]
+    spans: std::sync::Mutex<rt::spans::Recorded>,
+
+    pub(crate) host: Arc<tokio::sync::Mutex<host::Host>>,
+}
+
+/// State that doesn't depend on a transaction and can be reused between executions
+pub struct Caches {
     runner_cache: runners::cache::Reader,
     wasm_mod_cache: WasmModuleCache,
 
     pub(crate) engines: rt::DetNondet<wasmtime::Engine>,
-    pub(crate) host: Arc<tokio::sync::Mutex<host::Host>>,
+    /// see [`caching::engine_config_hash`]
+    pub(crate) engine_hashes: rt::DetNondet<String>,
+    /// engines are created for profiling, see [`rt::profiling`]
+    epoch_interruption: bool,
+
+    pool_size: Option<u32>,
+    /// free pool slots of each engine
+    pool_slots: rt::DetNondet<Arc<tokio::sync::Semaphore>>,
+}
+
+/// Pool slot held by a VM, it is released when VM store is dropped
+pub struct PoolSlot {
+    _permit: tokio::sync::OwnedSemaphorePermit,
+}
+
+fn configure_allocation(base_conf: &mut wasmtime::Config, conf: &config::Engine) {
+    base_conf.memory_init_cow(conf.memory_init_cow);
+
+    let Some(pooling) = &conf.pooling else {
+        return;
+    };
+
+    let total_instances = pooling.total_vms.saturating_mul(pooling.instances_per_vm);
+
+    let mut pool = wasmtime::PoolingAllocationConfig::default();
+    pool.total_stores(pooling.total_vms)
+        .total_core_instances(total_instances)
+        .total_memories(total_instances)
+        .total_tables(total_instances)
+        // memory can't grow past what limiter can account for
+        .max_memory_size(memlimiter::MAX_MEMORY_SIZE as usize)
+        .table_elements(pooling.table_elements as usize);
+
+    base_conf.allocation_strategy(wasmtime::InstanceAllocationStrategy::Pooling(pool));
+}
+
+impl Caches {
+    /// Waits until a slot of the pool is free, fails with timeout if VM is cancelled meanwhile
+    async fn take_pool_slot(
+        &self,
+        is_det: bool,
+        metrics: &rt::Metrics,
+        cancellation: &cancellation::Token,
+    ) -> anyhow::Result<Option<PoolSlot>> {
+        let Some(pool_size) = self.pool_size else {
+            return Ok(None);
+        };
+
+        let slots = self.pool_slots.get(is_det);
+
+        let permit = match slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log_warn!(pool_size = pool_size; "instance pool exhausted, waiting for a slot");
+                metrics.pool_exhausted.increment();
+
+                tokio::select! {
+                    permit = slots.clone().acquire_owned() => permit?,
+                    _ = cancellation.chan.closed() => {
+                        return Err(rt::errors::VMError(
+                            public_abi::VmError::Timeout.value().into(),
+                            None,
+                        )
+                        .into());
+                    }
+                }
+            }
+        };
+
+        metrics.pooled_vms.increment();
+        metrics
+            .pool_peak_vms
+            .record((pool_size as usize - slots.available_permits()) as u64);
+
+        Ok(Some(PoolSlot { _permit: permit }))
+    }
+
+    /// `profiling` enables epoch interruption, which is required by [`rt::profiling`],
+    /// `debug_mode` enables detailed backtraces
+    pub fn new(
+        config: &config::Config,
+        debug_mode: bool,
+        profiling: bool,
+    ) -> anyhow::Result<Arc<Self>> {
+        let my_cache_dir = runners::cache::get_cache_dir(&config.cache_dir).ok();
+
+        let engines = create_runtime_engines(
+            config,
+            my_cache_dir.as_deref(),
+            EngineOptions {
+                profiling,
+                backtrace_details: debug_mode,
+            },
+        )?;
+
+        let engine_hashes = rt::DetNondet {
+            det: caching::engine_config_hash(&engines.det),
+            non_det: caching::engine_config_hash(&engines.non_det),
+        };
+
+        let pool_size = config.engine.pooling.as_ref().map(|x| x.total_vms);
+
+        Ok(Arc::new(Self {
+            runner_cache: runners::cache::Reader::new(
+                std::path::Path::new(&config.runners_dir),
+                std::path::Path::new(&config.registry_dir),
+                debug_mode,
+                config.cache_limits.runner_archives,
+                &config.runner_verification,
+            )?,
+            wasm_mod_cache: WasmModuleCache {
+                cache_dir: my_cache_dir,
+                wasm_modules_cache: sync::LruCacheMap::new(config.cache_limits.wasm_modules),
+                det_gated_modules: dashmap::DashMap::new(),
+            },
+            engines,
+            engine_hashes,
+            epoch_interruption: profiling,
+            pool_size,
+            pool_slots: rt::DetNondet {
+                det: Arc::new(tokio::sync::Semaphore::new(
+                    pool_size.unwrap_or_default() as usize
+                )),
+                non_det: Arc::new(tokio::sync::Semaphore::new(
+                    pool_size.unwrap_or_default() as usize
+                )),
+            },
+        }))
+    }
+}
+
+/// Debugging facilities of runtime engines, all of them are disabled by default
+///
+/// Enabling any of them changes engine hashes, so that precompiled artifacts are not used
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EngineOptions {
+    /// epoch interruption, which is required by [`rt::profiling`]
+    pub profiling: bool,
+    /// function names and DWARF source locations in backtraces of both engines
+    pub backtrace_details: bool,
+}
+
+/// Engines used for execution, `genvm precompile` uses their hashes as keys
+pub fn create_runtime_engines(
+    config: &config::Config,
+    cache_dir: Option<&std::path::Path>,
+    options: EngineOptions,
+) -> anyhow::Result<rt::DetNondet<wasmtime::Engine>> {
+    create_engines(|base_conf| {
+        configure_allocation(base_conf, &config.engine);
+        base_conf.epoch_interruption(options.profiling);
+
+        if options.backtrace_details {
+            base_conf
+                .wasm_backtrace(true)
+                .wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Enable);
+        }
+
+        match cache_dir {
+            None => {
+                base_conf.disable_cache();
+            }
+            Some(cache_dir) => {
+                let mut cache_dir = cache_dir.to_owned();
+                cache_dir.push("wasmtime");
+
+                let cache_conf: wasmtime_cache::CacheConfig =
+                    serde_json::from_value(serde_json::Value::Object(
+                        [
+                            ("enabled".into(), serde_json::Value::Bool(true)),
+                            (
+                                "directory".into(),
+                                cache_dir.into_os_string().into_string().unwrap().into(),
+                            ),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    ))?;
+                base_conf.cache_config_set(cache_conf)?;
+            }
+        }
+        Ok(())
+    })
 }
 
 pub fn create_engines(
@@ -81,6 +301,7 @@ pub fn create_engines(
 
     base_conf
         .debug_info(true)
+        .wasm_backtrace(false)
         .wasm_backtrace_details(wasmtime::WasmBacktraceDetails::Disable)
         .async_support(true)
         .consume_fuel(false)
@@ -89,9 +310,9 @@ pub fn create_engines(
     base_conf
         .wasm_tail_call(true)
         .wasm_bulk_memory(true)
-        .wasm_simd(false)
+        .wasm_simd(true)
         .relaxed_simd_deterministic(true)
-        .wasm_relaxed_simd(false);
+        .wasm_relaxed_simd(true);
 
     use wasmparser::WasmFeatures;
 
@@ -112,7 +333,9 @@ pub fn create_engines(
         .wasm_backtrace(true);
 
     let mut non_det_conf = base_conf.clone();
-    non_det_conf.wasm_floats_enabled(true).wasm_backtrace(false);
+    non_det_conf
+        .wasm_floats_enabled(true)
+        .cranelift_nan_canonicalization(true);
 
     let det_engine = wasmtime::Engine::new(&det_conf)?;
     let non_det_engine = wasmtime::Engine::new(&non_det_conf)?;
@@ -171,6 +394,43 @@ pub async fn submit_nondet_vm_task(zelf: &Arc<Supervisor>, task: NonDetVMTask) {
 }
 
 impl Supervisor {
+    /// Output of VMs spawned so far, see [`rt::vm::FullResult::output`]
+    pub fn captured_output(&self) -> Vec<rt::vm::VMOutput> {
+        self.captured_output
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|vm| {
+                let output = vm.output.lock().unwrap();
+                rt::vm::VMOutput {
+                    contract: vm.contract,
+                    entry_kind: vm.entry_kind,
+                    is_deterministic: vm.is_deterministic,
+                    stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                    truncated: output.truncated,
+                }
+            })
+            .collect()
+    }
+
+    /// Profiles of VMs finished so far, see [`rt::profiling`]
+    pub fn take_profiles(&self) -> Vec<rt::profiling::VMProfile> {
+        self.profiler
+            .as_ref()
+            .map(|p| p.take_profiles())
+            .unwrap_or_default()
+    }
+
+    pub fn record_spans(&self, spans: rt::spans::VMSpans) {
+        self.spans.lock().unwrap().push(spans);
+    }
+
+    /// Call trees of VMs finished so far
+    pub fn take_spans(&self) -> rt::spans::Recorded {
+        std::mem::take(&mut *self.spans.lock().unwrap())
+    }
+
     pub fn get_storage_limiter(&self) -> rt::vm::storage::Limiter {
         rt::vm::storage::Limiter::new(self.shared_data.gep(|x| &x.storage_pages_limit))
     }
@@ -180,44 +440,38 @@ impl Supervisor {
         ctor: Ctor,
         host: host::Host,
     ) -> anyhow::Result<Arc<Self>> {
-        let my_cache_dir = runners::cache::get_cache_dir(&config.cache_dir).ok();
+        let caches = Caches::new(
+            config,
+            ctor.shared_data.debug_mode,
+            ctor.shared_data.profile_interval.is_some(),
+        )?;
 
-        let engines = create_engines(|base_conf| {
-            match &my_cache_dir {
-                None => {
-                    base_conf.disable_cache();
-                }
-                Some(cache_dir) => {
-                    let mut cache_dir = cache_dir.to_owned();
-                    cache_dir.push("wasmtime");
-
-                    let cache_conf: wasmtime_cache::CacheConfig =
-                        serde_json::from_value(serde_json::Value::Object(
-                            [
-                                ("enabled".into(), serde_json::Value::Bool(true)),
-                                (
-                                    "directory".into(),
-                                    cache_dir.into_os_string().into_string().unwrap().into(),
-                                ),
-                            ]
-                            .into_iter()
-                            .collect(),
-                        ))?;
-                    base_conf.cache_config_set(cache_conf)?;
-                }
-            }
-            Ok(())
-        })?;
+        Self::start_with_caches(config, caches, ctor, host)
+    }
 
+    /// Same as [`Supervisor::start`], but reuses caches and engines of previous executions
+    pub fn start_with_caches(
+        config: &config::Config,
+        caches: Arc<Caches>,
+        ctor: Ctor,
+        host: host::Host,
+    ) -> anyhow::Result<Arc<Self>> {
         let (sender, receiver) = tokio_mpmc::channel(100);
 
-        let debug_mode = ctor.shared_data.debug_mode;
+        let profiler = match ctor.shared_data.profile_interval {
+            None => None,
+            Some(_) if !caches.epoch_interruption => {
+                anyhow::bail!("profiling requires caches created with profiling enabled")
+            }
+            Some(interval) => Some(rt::profiling::Profiler::start(&caches.engines, interval)),
+        };
 
         let zelf = Arc::new(Self {
             shared_data: ctor.shared_data,
             modules: ctor.modules,
             limiter: ctor.limiter,
             locked_slots: ctor.locked_slots,
+            random_seed: ctor.random_seed,
             nondet_call_no: AtomicU32::new(0),
             balances: dashmap::DashMap::new(),
             queue: NondetQueue {
@@ -228,17 +482,17 @@ impl Supervisor {
                 vm_countdown: genvm_common::sync::Waiter::new(),
                 tasks_loop_done: Arc::new(tokio::sync::RwLock::new(())),
             },
-            runner_cache: runners::cache::Reader::new(
-                std::path::Path::new(&config.runners_dir),
-                std::path::Path::new(&config.registry_dir),
-                debug_mode,
-            )?,
-            wasm_mod_cache: WasmModuleCache {
-                cache_dir: my_cache_dir,
-                wasm_modules_cache: sync::CacheMap::new(),
-            },
+            caches,
+            contracts_cache: sync::LruCacheMap::new(None),
+            contracts_wasm_cache: sync::LruCacheMap::new(None),
+            contracts_det_gated: dashmap::DashMap::new(),
+            captured_output: std::sync::Mutex::new(Vec::new()),
+            captured_output_remaining: Arc::new(std::sync::atomic::AtomicUsize::new(
+                wasi::vfs::CAPTURED_OUTPUT_TOTAL_LIMIT,
+            )),
+            profiler,
+            spans: std::sync::Mutex::new(rt::spans::Recorded::default()),
             host: Arc::new(tokio::sync::Mutex::new(host)),
-            engines,
         });
 
         let read_permit = zelf.queue.tasks_loop_done.clone().try_read_owned().unwrap();
@@ -259,25 +513,77 @@ pub async fn spawn(
     limiter: rt::memlimiter::Limiter,
 ) -> anyhow::Result<rt::vm::VM<()>> {
     let config_copy = vm.conf;
+    let should_quit = vm.cancellation.should_quit.clone();
+
+    let pool_slot = zelf
+        .caches
+        .take_pool_slot(
+            vm.conf.is_deterministic,
+            &zelf.shared_data.metrics.supervisor,
+            &vm.cancellation,
+        )
+        .await?;
 
-    let engine = zelf.engines.get(vm.conf.is_deterministic);
+    let engine = zelf.caches.engines.get(vm.conf.is_deterministic);
 
     let should_capture_fp = std::sync::Arc::new(vm.conf.is_deterministic.into());
 
+    let debug_thread = zelf.shared_data.debugger.as_ref().map(|debugger| {
+        let mode = if vm.conf.is_deterministic {
+            "det"
+        } else {
+            "nondet"
+        };
+        debugger.register_vm(format!(
+            "{} {} {mode}",
+            vm.message_data.contract_address,
+            vm.message_data.entry_kind.str_snake_case()
+        ))
+    });
+
+    let output = if zelf.shared_data.debug_mode {
+        let output = Arc::new(std::sync::Mutex::new(wasi::vfs::CapturedOutput::new(
+            zelf.captured_output_remaining.clone(),
+        )));
+        zelf.captured_output.lock().unwrap().push(CapturedVM {
+            contract: vm.message_data.contract_address,
+            entry_kind: vm.message_data.entry_kind,
+            is_deterministic: vm.conf.is_deterministic,
+            output: output.clone(),
+        });
+        Some(output)
+    } else {
+        None
+    };
+
     let mut store = wasmtime::Store::new(
         engine,
         rt::vm::WasmtimeStoreData {
             limits: limiter.clone(),
-            genlayer_ctx: wasi::Context::new(vm, limiter)?,
+            genlayer_ctx: wasi::Context::new(vm, limiter, output)?,
             supervisor: zelf.clone(),
+            pool_slot,
+            profiler: None,
         },
         wasmtime::GenVMCtx {
             should_capture_fp,
-            should_quit: zelf.shared_data.cancellation.should_quit.clone(),
+            should_quit,
         },
     );
 
     store.limiter(|ctx| &mut ctx.limits);
+    store.data_mut().genlayer_ctx.genlayer_sdk.debug_thread = debug_thread;
+
+    if zelf.caches.epoch_interruption {
+        store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(|mut ctx| {
+            if let Some(mut sampler) = ctx.data_mut().profiler.take() {
+                sampler.sample(&ctx);
+                ctx.data_mut().profiler = Some(sampler);
+            }
+            Ok(wasmtime::UpdateDeadline::Continue(1))
+        });
+    }
 
     let mut linker = wasmtime::Linker::new(engine);
 
@@ -302,53 +608,94 @@ pub async fn apply_contract_actions(
     zelf: &std::sync::Arc<Supervisor>,
     mut vm: rt::vm::VM<()>,
 ) -> anyhow::Result<rt::vm::VM<wasmtime::Instance>> {
-    let contract_address = vm
+    let message_data = &vm
         .vm_base
         .store
         .data()
         .genlayer_ctx
         .genlayer_sdk
         .data
-        .message_data
-        .contract_address;
+        .message_data;
+    let contract_address = message_data.contract_address;
+    let is_reentrant_write = message_data.stack.contains(&contract_address)
+        && vm
+            .vm_base
+            .store
+            .data()
+            .genlayer_ctx
+            .genlayer_sdk
+            .data
+            .conf
+            .can_write_storage;
 
     let contract_id = runners::get_runner_of_contract(contract_address);
 
     let limiter = vm.vm_base.store.data_mut().limits.clone();
 
-    let arch = zelf
-        .runner_cache
-        .get_or_create(
-            contract_id,
-            || async {
-                let code = vm
-                    .vm_base
-                    .store
-                    .data()
-                    .genlayer_ctx
-                    .genlayer_sdk
-                    .data
-                    .storage
-                    .read_code(&limiter)
-                    .await?;
-
-                runners::parse(util::SharedBytes::new(code))
-            },
-            &limiter,
-        )
-        .await
-        .map_err(|e| {
-            rt::errors::VMError::wrap(public_abi::VmError::InvalidContract.value().to_owned(), e)
-        })?;
+    let arch = runners::cache::get_or_create_in(
+        &zelf.contracts_cache,
+        contract_id,
+        || async {
+            let code = vm
+                .vm_base
+                .store
+                .data()
+                .genlayer_ctx
+                .genlayer_sdk
+                .data
+                .storage
+                .read_code(&limiter)
+                .await?;
+
+            runners::parse(util::SharedBytes::new(code)).map(|(arch, _)| arch)
+        },
+        &limiter,
+    )
+    .await
+    .map_err(|e| {
+        rt::errors::VMError::wrap(public_abi::VmError::InvalidContract.value().to_owned(), e)
+    })?
+    .value;
 
     let actions = arch.get_actions().await.map_err(|e| {
         rt::errors::VMError::wrap(public_abi::VmError::InvalidContract.value().to_owned(), e)
     })?;
 
+    // seeds derived from host entropy would change results of re-executed old transactions
+    let contract_version = arch.get_version().map_err(|e| {
+        rt::errors::VMError::wrap(public_abi::VmError::InvalidContract.value().to_owned(), e)
+    })?;
+    if contract_version < version::Version::from_str(public_abi::RANDOM_SEED_MIN_VERSION)? {
+        vm.vm_base
+            .store
+            .data_mut()
+            .genlayer_ctx_mut()
+            .use_legacy_random_seed();
+    }
+
+    // callee decides, so that contracts deployed before reentrancy was forbidden keep working,
+    // reentrant calls that can't write are always allowed
+    if is_reentrant_write
+        && contract_version
+            >= version::Version::from_str(public_abi::REENTRANCY_FORBIDDEN_MIN_VERSION)?
+    {
+        log_warn!(address:? = contract_address; "reentrant call denied");
+
+        return Err(rt::errors::VMError(
+            public_abi::VmError::ReentrancyForbidden.value().to_owned(),
+            None,
+        )
+        .into());
+    }
+
     let mut ctx = actions::Ctx {
         env: BTreeMap::new(),
         visited: HashSet::new(),
+        linked: Vec::new(),
+        modules: Vec::new(),
+        trail: Vec::new(),
         contract_id,
+        contract_arch: arch.clone(),
         supervisor: zelf,
         vm: &mut vm.vm_base,
     };
@@ -369,12 +716,56 @@ pub async fn apply_contract_actions(
         }
     };
 
+    if let Some(profiler) = &zelf.profiler {
+        let modules = std::mem::take(&mut ctx.modules);
+        let data = vm.vm_base.store.data_mut();
+        let message_data = &data.genlayer_ctx.genlayer_sdk.data.message_data;
+
+        data.profiler = Some(profiler.start_vm(
+            message_data.contract_address,
+            message_data.entry_kind,
+            vm.vm_base.config_copy.is_deterministic,
+            modules,
+        ));
+    }
+
     Ok(rt::vm::VM {
         vm_base: vm.vm_base,
         data: inst,
     })
 }
 
+/// Spawns and runs VM narrowed by `limits`, see [`rt::limits::SubVMLimits`]
+///
+/// VM data is returned only if VM ran till completion
+pub async fn run_limited(
+    zelf: &std::sync::Arc<Supervisor>,
+    mut vm: wasi::genlayer_sdk::SingleVMData,
+    limiter: rt::memlimiter::Limiter,
+    limits: &rt::limits::SubVMLimits,
+) -> anyhow::Result<(rt::vm::RunOk, Option<wasi::genlayer_sdk::SingleVMData>)> {
+    let applied = limits.apply(limiter, &mut vm);
+
+    let run = async {
+        let vm = spawn(zelf, vm, applied.limiter.clone()).await?;
+        let vm = apply_contract_actions(zelf, vm).await?;
+        vm.run().await
+    };
+
+    let res = tokio::select! {
+        res = run => res.map(|x| (x.run_ok, Some(x.vm_data))),
+        _ = applied.timed_out() => {
+            log_debug!(limits:? = limits; "sub-VM wall time limit exceeded");
+            Err(rt::errors::VMError::sub_limit("timeout", None).into())
+        }
+    };
+
+    match res {
+        Ok((run_ok, vm_data)) => applied.patch_result(Ok(run_ok)).map(|x| (x, vm_data)),
+        Err(e) => applied.patch_result(Err(e)).map(|x| (x, None)),
+    }
+}
+
 async fn run_single_nondet(
     zelf: &std::sync::Arc<Supervisor>,
     task: NonDetVMTask,
@@ -391,9 +782,9 @@ async fn run_single_nondet_inner(
     task: NonDetVMTask,
     limiter: memlimiter::Limiter,
 ) -> anyhow::Result<rt::vm::RunOk> {
-    let vm = spawn(zelf, task.task, limiter).await?;
-    let vm = apply_contract_actions(zelf, vm).await?;
-    vm.run().await.map(|x| x.run_ok)
+    run_limited(zelf, task.task, limiter, &task.limits)
+        .await
+        .map(|x| x.0)
 }
 
 async fn nondet_vm_processor(
@@ -468,3 +859,44 @@ async fn nondet_vm_processor(
     std::mem::drop(read_permit);
     log_debug!(count = count; "nondet worker done");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_det_simd_nan_is_canonical() {
+        let engines = create_engines(|conf| {
+            conf.async_support(false);
+            Ok(())
+        })
+        .unwrap();
+
+        // signaling NaN with a payload, which hardware keeps when quieting it
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "nan") (result i32)
+                    (v128.const i32x4 0x7fa00001 0 0 0)
+                    (v128.const f32x4 1 1 1 1)
+                    f32x4.add
+                    i32x4.extract_lane 0))"#,
+        )
+        .unwrap();
+
+        let module = wasmtime::Module::new(&engines.non_det, &wasm).unwrap();
+        let mut store = wasmtime::Store::new(
+            &engines.non_det,
+            (),
+            wasmtime::GenVMCtx {
+                should_capture_fp: Arc::new(false.into()),
+                should_quit: Arc::new(0.into()),
+            },
+        );
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let nan = instance
+            .get_typed_func::<(), i32>(&mut store, "nan")
+            .unwrap();
+
+        assert_eq!(nan.call(&mut store, ()).unwrap() as u32, 0x7fc00000);
+    }
+}
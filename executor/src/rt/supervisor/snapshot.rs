@@ -0,0 +1,469 @@
+//! Pre-initialized runner snapshots, in the style of [Wizer](https://github.com/bytecodealliance/wizer)
+//!
+//! If a module started by a runner exports [`INIT_EXPORT`], this function is called before `_start`.
+//! After it returns, exported memories and mutable globals of the module and of all modules linked
+//! before it are stored next to precompiled modules. Later instantiations restore this state instead of calling the function.
+//!
+//! Snapshot is taken only if all such state is visible: modules must not import memories, tables or mutable globals,
+//! and all defined ones must be exported. Initialization function must not modify tables
+//! and must not leave any host-visible state (open file descriptors, consumed random bytes, ...),
+//! otherwise execution with and without a snapshot would differ. Host calls that produce such state trap
+//! during initialization, files mapped from runners can still be read, see [`crate::wasi::Context::begin_initialization`].
+//!
+//! Snapshots are written with a checksum sidecar, corrupted ones are ignored and initialization is called again
+
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+
+use crate::caching;
+
+pub const INIT_EXPORT: &str = "wizer.initialize";
+
+const MAGIC: &[u8; 8] = b"GVMSNAP1";
+const PAGE_SIZE: usize = 65536;
+
+/// Checks that all mutable state of the module is reachable through its exports
+pub fn is_module_capturable(module: &wasmtime::Module, wasm: &[u8]) -> anyhow::Result<bool> {
+    let mut imported_globals = 0;
+
+    for import in module.imports() {
+        match import.ty() {
+            wasmtime::ExternType::Memory(_) | wasmtime::ExternType::Table(_) => return Ok(false),
+            wasmtime::ExternType::Global(ty) => {
+                if ty.mutability() == wasmtime::Mutability::Var {
+                    return Ok(false);
+                }
+                imported_globals += 1;
+            }
+            _ => {}
+        }
+    }
+
+    let mut mutable_globals = BTreeSet::new();
+    let mut exported_globals = BTreeSet::new();
+    let mut defined_memories = 0;
+    let mut exported_memories = BTreeSet::new();
+
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            wasmparser::Payload::GlobalSection(reader) => {
+                for (i, global) in reader.into_iter().enumerate() {
+                    if global?.ty.mutable {
+                        mutable_globals.insert(imported_globals + i as u32);
+                    }
+                }
+            }
+            wasmparser::Payload::MemorySection(reader) => {
+                defined_memories += reader.count();
+            }
+            wasmparser::Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    match export.kind {
+                        wasmparser::ExternalKind::Global => {
+                            exported_globals.insert(export.index);
+                        }
+                        wasmparser::ExternalKind::Memory => {
+                            exported_memories.insert(export.index);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(mutable_globals.is_subset(&exported_globals)
+        && exported_memories.len() == defined_memories as usize)
+}
+
+struct MemoryState {
+    pages: u64,
+    /// pages that are not filled with zeroes
+    data: Vec<(u32, Box<[u8]>)>,
+}
+
+#[derive(Default)]
+struct InstanceState {
+    memories: Vec<(String, MemoryState)>,
+    globals: Vec<(String, wasmtime::Val)>,
+}
+
+pub struct Snapshot {
+    instances: Vec<InstanceState>,
+}
+
+impl Snapshot {
+    /// Returns `None` if some exported mutable global has a type that can't be stored
+    pub fn capture<T>(
+        store: &mut wasmtime::Store<T>,
+        instances: &[wasmtime::Instance],
+    ) -> anyhow::Result<Option<Self>> {
+        let mut res = Vec::new();
+
+        for instance in instances {
+            let exports: Vec<(String, wasmtime::Extern)> = instance
+                .exports(&mut *store)
+                .map(|e| (e.name().to_owned(), e.into_extern()))
+                .collect();
+
+            let mut state = InstanceState::default();
+
+            for (name, ext) in exports {
+                match ext {
+                    wasmtime::Extern::Memory(mem) => {
+                        let data = mem
+                            .data(&*store)
+                            .chunks(PAGE_SIZE)
+                            .enumerate()
+                            .filter(|(_, page)| page.iter().any(|x| *x != 0))
+                            .map(|(i, page)| (i as u32, Box::from(page)))
+                            .collect();
+
+                        state.memories.push((
+                            name,
+                            MemoryState {
+                                pages: mem.size(&*store),
+                                data,
+                            },
+                        ));
+                    }
+                    wasmtime::Extern::Global(global) => {
+                        if global.ty(&*store).mutability() != wasmtime::Mutability::Var {
+                            continue;
+                        }
+
+                        let val = global.get(&mut *store);
+                        match val {
+                            wasmtime::Val::I32(_)
+                            | wasmtime::Val::I64(_)
+                            | wasmtime::Val::F32(_)
+                            | wasmtime::Val::F64(_) => state.globals.push((name, val)),
+                            _ => return Ok(None),
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            res.push(state);
+        }
+
+        Ok(Some(Self { instances: res }))
+    }
+
+    /// Returns `false` and leaves instances intact if snapshot does not match them
+    pub fn restore<T>(
+        &self,
+        store: &mut wasmtime::Store<T>,
+        instances: &[wasmtime::Instance],
+    ) -> anyhow::Result<bool> {
+        if instances.len() != self.instances.len() {
+            return Ok(false);
+        }
+
+        let mut memories = Vec::new();
+        let mut globals = Vec::new();
+
+        for (instance, state) in instances.iter().zip(&self.instances) {
+            for (name, mem_state) in &state.memories {
+                let Some(mem) = instance.get_memory(&mut *store, name) else {
+                    return Ok(false);
+                };
+                if mem.size(&*store) > mem_state.pages {
+                    return Ok(false);
+                }
+                memories.push((mem, mem_state));
+            }
+
+            for (name, val) in &state.globals {
+                let Some(global) = instance.get_global(&mut *store, name) else {
+                    return Ok(false);
+                };
+                if global.ty(&*store).mutability() != wasmtime::Mutability::Var {
+                    return Ok(false);
+                }
+                globals.push((global, val));
+            }
+        }
+
+        for (mem, mem_state) in memories {
+            let cur_pages = mem.size(&*store);
+            if cur_pages < mem_state.pages {
+                mem.grow(&mut *store, mem_state.pages - cur_pages)?;
+            }
+
+            let data = mem.data_mut(&mut *store);
+            data.fill(0);
+
+            for (page, contents) in &mem_state.data {
+                let off = *page as usize * PAGE_SIZE;
+                data.get_mut(off..off + contents.len())
+                    .ok_or_else(|| anyhow::anyhow!("snapshot page out of bounds"))?
+                    .copy_from_slice(contents);
+            }
+        }
+
+        for (global, val) in globals {
+            global.set(&mut *store, val.clone())?;
+        }
+
+        Ok(true)
+    }
+
+    pub fn save(&self, path: &std::path::Path, key: &str) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        put_str(&mut buf, key);
+
+        put_u32(&mut buf, self.instances.len() as u32);
+        for state in &self.instances {
+            put_u32(&mut buf, state.memories.len() as u32);
+            for (name, mem) in &state.memories {
+                put_str(&mut buf, name);
+                buf.extend_from_slice(&mem.pages.to_le_bytes());
+                put_u32(&mut buf, mem.data.len() as u32);
+                for (page, contents) in &mem.data {
+                    put_u32(&mut buf, *page);
+                    put_u32(&mut buf, contents.len() as u32);
+                    buf.extend_from_slice(contents);
+                }
+            }
+
+            put_u32(&mut buf, state.globals.len() as u32);
+            for (name, val) in &state.globals {
+                put_str(&mut buf, name);
+                let (tag, bits): (u8, u64) = match val {
+                    wasmtime::Val::I32(v) => (0, *v as u32 as u64),
+                    wasmtime::Val::I64(v) => (1, *v as u64),
+                    wasmtime::Val::F32(v) => (2, *v as u64),
+                    wasmtime::Val::F64(v) => (3, *v),
+                    _ => unreachable!(),
+                };
+                buf.push(tag);
+                buf.extend_from_slice(&bits.to_le_bytes());
+            }
+        }
+
+        std::fs::create_dir_all(path.parent().unwrap())
+            .with_context(|| format!("creating directory for {path:?}"))?;
+
+        caching::write_with_checksum(path, &buf)
+    }
+
+    /// Returns `None` if snapshot does not exist or was created for another key, fails if it is corrupted
+    pub fn load(path: &std::path::Path, key: &str) -> anyhow::Result<Option<Self>> {
+        let Some(data) = caching::read_verified(path)? else {
+            return Ok(None);
+        };
+
+        let mut reader = Reader(data.as_ref());
+
+        if reader.take(MAGIC.len())? != MAGIC || reader.str()? != key {
+            return Ok(None);
+        }
+
+        let mut instances = Vec::new();
+        for _ in 0..reader.u32()? {
+            let mut state = InstanceState::default();
+
+            for _ in 0..reader.u32()? {
+                let name = reader.str()?;
+                let pages = reader.u64()?;
+                let mut data = Vec::new();
+                for _ in 0..reader.u32()? {
+                    let page = reader.u32()?;
+                    let len = reader.u32()? as usize;
+                    data.push((page, Box::from(reader.take(len)?)));
+                }
+                state.memories.push((name, MemoryState { pages, data }));
+            }
+
+            for _ in 0..reader.u32()? {
+                let name = reader.str()?;
+                let tag = reader.take(1)?[0];
+                let bits = reader.u64()?;
+                let val = match tag {
+                    0 => wasmtime::Val::I32(bits as u32 as i32),
+                    1 => wasmtime::Val::I64(bits as i64),
+                    2 => wasmtime::Val::F32(bits as u32),
+                    3 => wasmtime::Val::F64(bits),
+                    _ => anyhow::bail!("invalid global tag {tag}"),
+                };
+                state.globals.push((name, val));
+            }
+
+            instances.push(state);
+        }
+
+        Ok(Some(Self { instances }))
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) {
+    put_u32(buf, s.len() as u32);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < len {
+            anyhow::bail!("unexpected end of snapshot");
+        }
+        let (res, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(res)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn str(&mut self) -> anyhow::Result<String> {
+        let len = self.u32()? as usize;
+        Ok(std::str::from_utf8(self.take(len)?)?.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Path in a directory of its own, which is removed by the test
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir()
+            .join(format!("genvm-snapshot-{name}-{}", std::process::id()))
+            .join("module.snap")
+    }
+
+    fn sample() -> Snapshot {
+        Snapshot {
+            instances: vec![
+                InstanceState {
+                    memories: vec![(
+                        "memory".to_owned(),
+                        MemoryState {
+                            pages: 3,
+                            data: vec![
+                                (0, Box::from(&[1_u8, 2, 3][..])),
+                                (2, Box::from(&[4_u8][..])),
+                            ],
+                        },
+                    )],
+                    globals: vec![
+                        ("a".to_owned(), wasmtime::Val::I32(-5)),
+                        ("b".to_owned(), wasmtime::Val::I64(i64::MIN)),
+                        ("c".to_owned(), wasmtime::Val::F32(0x7fc00001)),
+                        ("d".to_owned(), wasmtime::Val::F64(1.5_f64.to_bits())),
+                    ],
+                },
+                InstanceState::default(),
+            ],
+        }
+    }
+
+    fn describe(snap: &Snapshot) -> String {
+        let mut res = String::new();
+        for state in &snap.instances {
+            for (name, mem) in &state.memories {
+                res.push_str(&format!("{name} {} {:?}\n", mem.pages, mem.data));
+            }
+            for (name, val) in &state.globals {
+                res.push_str(&format!("{name} {val:?}\n"));
+            }
+            res.push_str("---\n");
+        }
+        res
+    }
+
+    fn capturable(wat: &str) -> bool {
+        let engines = crate::rt::supervisor::create_engines(|conf| {
+            conf.async_support(false);
+            Ok(())
+        })
+        .unwrap();
+        let wasm = wat::parse_str(wat).unwrap();
+        let module = wasmtime::Module::new(&engines.non_det, &wasm).unwrap();
+
+        is_module_capturable(&module, &wasm).unwrap()
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        let path = temp_path("roundtrip");
+        let snap = sample();
+
+        snap.save(&path, "engine\ntrail").unwrap();
+        let loaded = Snapshot::load(&path, "engine\ntrail").unwrap().unwrap();
+        assert_eq!(describe(&loaded), describe(&snap));
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn other_engine_hash() {
+        let path = temp_path("engine");
+
+        sample().save(&path, "engine-a\ntrail").unwrap();
+        assert!(Snapshot::load(&path, "engine-b\ntrail").unwrap().is_none());
+        assert!(
+            Snapshot::load(&path.with_file_name("absent.snap"), "engine-a\ntrail")
+                .unwrap()
+                .is_none()
+        );
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn corrupted_or_truncated() {
+        let path = temp_path("corrupted");
+
+        sample().save(&path, "key").unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&path, &data).unwrap();
+        assert!(Snapshot::load(&path, "key").is_err());
+
+        // checksum is valid, but contents end early
+        caching::write_with_checksum(&path, &data[..data.len() - 4]).unwrap();
+        assert!(Snapshot::load(&path, "key").is_err());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn capturable_modules() {
+        assert!(capturable(
+            r#"(module
+                (memory (export "memory") 1)
+                (global (export "g") (mut i32) (i32.const 0))
+                (global i32 (i32.const 1)))"#
+        ));
+
+        assert!(!capturable(
+            r#"(module (import "env" "memory" (memory 1)))"#
+        ));
+        assert!(!capturable(
+            r#"(module (import "env" "table" (table 1 funcref)))"#
+        ));
+        assert!(!capturable(
+            r#"(module (import "env" "g" (global (mut i32))))"#
+        ));
+        assert!(!capturable(r#"(module (memory 1))"#));
+        assert!(!capturable(r#"(module (global (mut i32) (i32.const 0)))"#));
+    }
+}
@@ -18,13 +18,50 @@ pub struct RunResult {
     pub vm_data: wasi::genlayer_sdk::SingleVMData,
 }
 
+/// Message sent by a contract that was called with write access.
+/// Such messages are reported in [`FullResult`] instead of being sent to the host right away
+#[derive(Debug, Clone, serde::Serialize)]
+pub enum PendingMessage {
+    Post {
+        sender: calldata::Address,
+        address: calldata::Address,
+        #[serde(with = "serde_bytes")]
+        calldata: Vec<u8>,
+        data: String,
+    },
+    Deploy {
+        sender: calldata::Address,
+        #[serde(with = "serde_bytes")]
+        calldata: Vec<u8>,
+        #[serde(with = "serde_bytes")]
+        code: Vec<u8>,
+        data: String,
+    },
+}
+
+/// Captured stdout and stderr of a single VM, see [`crate::wasi::vfs::CapturedOutput`]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VMOutput {
+    pub contract: calldata::Address,
+    pub entry_kind: public_abi::EntryKind,
+    pub is_deterministic: bool,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct FullResult {
     pub kind: public_abi::ResultCode,
     pub data: calldata::Value,
     pub fingerprint: Option<rt::errors::Fingerprint>,
-    pub storage_changes: Vec<storage::Delta>,
+    /// changes of each contract storage, entrypoint contract goes first
+    pub storage_changes: Vec<(calldata::Address, Vec<storage::Delta>)>,
     pub events: Vec<Vec<bytes::Bytes>>,
+    pub messages: Vec<PendingMessage>,
+    /// output of all VMs in order of their creation, collected only in debug mode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub output: Vec<VMOutput>,
 }
 
 impl RunOk {
@@ -79,6 +116,9 @@ pub struct WasmtimeStoreData {
     pub(super) genlayer_ctx: wasi::Context,
     pub(super) limits: rt::memlimiter::Limiter,
     pub(super) supervisor: std::sync::Arc<rt::supervisor::Supervisor>,
+    pub(super) pool_slot: Option<rt::supervisor::PoolSlot>,
+    /// set only while VM is profiled, see [`rt::profiling`]
+    pub(super) profiler: Option<rt::profiling::Sampler>,
 }
 
 impl WasmtimeStoreData {
@@ -124,9 +164,24 @@ impl VM<wasmtime::Instance> {
             }
         };
 
+        if !self.vm_base.config_copy.is_deterministic {
+            self.debug_hook(rt::debugger::NONDET_START, |data| {
+                let message = &data.message_data;
+                serde_json::json!({
+                    "contract": format!("{}", message.contract_address),
+                    "entry_kind": message.entry_kind.str_snake_case(),
+                    "entry_data": format!("0x{}", hex::encode(&message.entry_data)),
+                    "entry_stage_data": rt::debugger::calldata_to_json(&message.entry_stage_data),
+                })
+            })
+            .await;
+        }
+
         log_debug!("execution start");
         let time_start = std::time::Instant::now();
         let res = func.call_async(&mut self.vm_base.store, ()).await;
+        self.finish_profile();
+        self.finish_spans();
         log_debug!(
             elapsed:? = self.vm_base.store.data().genlayer_ctx.genlayer_sdk.start_time.elapsed(),
             wasm_start_elapsed:? = time_start.elapsed();
@@ -136,7 +191,9 @@ impl VM<wasmtime::Instance> {
             Ok(()) => Ok((rt::vm::RunOk::empty_return(), None)),
             Err(e) => {
                 if self.vm_base.config_copy.needs_error_fingerprint {
-                    rt::errors::unwrap_vm_errors_fingerprint(e).map(|(a, b)| (a, Some(b)))
+                    let debug_mode = self.vm_base.store.data().supervisor.shared_data.debug_mode;
+                    rt::errors::unwrap_vm_errors_fingerprint(e, debug_mode)
+                        .map(|(a, b)| (a, Some(b)))
                 } else {
                     rt::errors::unwrap_vm_errors(e).map(|a| (a, None))
                 }
@@ -184,6 +241,20 @@ impl VM<wasmtime::Instance> {
         };
 
         let res = res?;
+
+        if !self.vm_base.config_copy.is_deterministic {
+            self.debug_hook(rt::debugger::NONDET_END, |_| match &res.0 {
+                RunOk::Return(data) => serde_json::json!({
+                    "return": calldata::decode(data)
+                        .map(|v| rt::debugger::calldata_to_json(&v))
+                        .unwrap_or_else(|_| format!("0x{}", hex::encode(data)).into()),
+                }),
+                RunOk::UserError(msg) => serde_json::json!({ "user_error": msg }),
+                RunOk::VMError(msg, _) => serde_json::json!({ "vm_error": msg }),
+            })
+            .await;
+        }
+
         Ok(RunResult {
             run_ok: res.0,
             fingerprint: res.1,
@@ -199,6 +270,58 @@ impl VM<wasmtime::Instance> {
 }
 
 impl<T> VM<T> {
+    /// Stops at event of `kind` if debugger requested so, `data` is called only if debugger is enabled
+    async fn debug_hook(
+        &mut self,
+        kind: &str,
+        data: impl FnOnce(&wasi::genlayer_sdk::SingleVMData) -> serde_json::Value,
+    ) {
+        let sdk = &mut self.vm_base.store.data_mut().genlayer_ctx.genlayer_sdk;
+        let Some(thread) = sdk.debug_thread.take() else {
+            return;
+        };
+
+        let data = data(&sdk.data);
+        let cancellation = sdk.data.cancellation.clone();
+        thread.hook(kind, || data, &cancellation).await;
+
+        self.vm_base
+            .store
+            .data_mut()
+            .genlayer_ctx
+            .genlayer_sdk
+            .debug_thread = Some(thread);
+    }
+
+    fn finish_profile(&mut self) {
+        let data = self.vm_base.store.data_mut();
+        if let (Some(profiler), Some(sampler)) = (&data.supervisor.profiler, data.profiler.take()) {
+            profiler.finish_vm(sampler);
+        }
+    }
+
+    fn finish_spans(&mut self) {
+        let is_deterministic = self.vm_base.config_copy.is_deterministic;
+        let data = self.vm_base.store.data_mut();
+        let ctx = &mut data.genlayer_ctx.genlayer_sdk;
+        let message_data = &ctx.data.message_data;
+
+        let mode = if is_deterministic { "det" } else { "nondet" };
+        let name = format!(
+            "{} {} {mode}",
+            message_data.contract_address,
+            message_data.entry_kind.str_snake_case()
+        );
+
+        let spans = rt::spans::VMSpans {
+            contract: message_data.contract_address,
+            entry_kind: message_data.entry_kind,
+            is_deterministic,
+            root: std::mem::take(&mut ctx.spans).finish(name),
+        };
+        data.supervisor.record_spans(spans);
+    }
+
     pub fn map(mut self, f: impl FnOnce(&mut VMBase, T) -> T) -> VM<T> {
         VM {
             data: f(&mut self.vm_base, self.data),
@@ -52,27 +52,83 @@ pub trait HostStorageLocking {
 }
 
 #[derive(Clone, Debug)]
-pub struct Limiter(sync::DArc<std::sync::atomic::AtomicU64>);
+pub struct Limiter(
+    sync::DArc<std::sync::atomic::AtomicU64>,
+    /// budgets of sub-VMs, outermost first
+    Box<[std::sync::Arc<std::sync::atomic::AtomicU64>]>,
+);
+
+fn try_consume_pages(counter: &std::sync::atomic::AtomicU64, amount: u64) -> bool {
+    counter
+        .fetch_update(
+            std::sync::atomic::Ordering::SeqCst,
+            std::sync::atomic::Ordering::SeqCst,
+            |current| {
+                if amount > current {
+                    None
+                } else {
+                    Some(current - amount)
+                }
+            },
+        )
+        .is_ok()
+}
 
 impl Limiter {
     pub fn new(storage_pages_limit: sync::DArc<std::sync::atomic::AtomicU64>) -> Self {
-        Self(storage_pages_limit)
+        Self(storage_pages_limit, Box::new([]))
     }
 
-    pub fn consume(&self, amount: u64) -> anyhow::Result<()> {
+    /// creates limiter that can additionally consume at most `pages`
+    pub fn derived(&self, pages: u64) -> Self {
+        let mut budgets = Vec::from(&self.1[..]);
+        budgets.push(std::sync::Arc::new(std::sync::atomic::AtomicU64::new(pages)));
+
+        Self(self.0.clone(), budgets.into_boxed_slice())
+    }
+
+    /// creates limiter that counts pages consumed through it and limiters derived from it,
+    /// so that they can be returned with [`Limiter::release_tracked`]
+    pub fn tracked(&self) -> Self {
+        self.derived(u64::MAX)
+    }
+
+    /// returns pages consumed through limiter created by [`Limiter::tracked`] to all budgets
+    pub fn release_tracked(&self) {
+        let Some(tracker) = self.1.last() else {
+            return;
+        };
+
+        let amount = u64::MAX - tracker.load(std::sync::atomic::Ordering::SeqCst);
+        if amount == 0 {
+            return;
+        }
+
+        for budget in &self.1[..] {
+            budget.fetch_add(amount, std::sync::atomic::Ordering::SeqCst);
+        }
         self.0
-            .fetch_update(
-                std::sync::atomic::Ordering::SeqCst,
-                std::sync::atomic::Ordering::SeqCst,
-                |current| {
-                    if amount > current {
-                        None
-                    } else {
-                        Some(current - amount)
-                    }
-                },
-            )
-            .map_err(|_| rt::errors::VMError::oos(None))?;
+            .fetch_add(amount, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn consume(&self, amount: u64) -> anyhow::Result<()> {
+        for (i, budget) in self.1.iter().enumerate() {
+            if !try_consume_pages(budget, amount) {
+                for budget in &self.1[..i] {
+                    budget.fetch_add(amount, std::sync::atomic::Ordering::SeqCst);
+                }
+
+                return Err(rt::errors::VMError::sub_limit("storage", None).into());
+            }
+        }
+
+        if !try_consume_pages(&self.0, amount) {
+            for budget in &self.1[..] {
+                budget.fetch_add(amount, std::sync::atomic::Ordering::SeqCst);
+            }
+
+            return Err(rt::errors::VMError::oos(None).into());
+        }
 
         Ok(())
     }
@@ -128,6 +184,15 @@ impl<HS: Send + Sync> Storage<HS> {
         self.pages.read_page_override(key)
     }
 
+    pub fn limiter(&self) -> &Limiter {
+        &self.pages.1
+    }
+
+    /// replaces limiter for all future writes, already written pages are not recounted
+    pub fn set_limiter(&mut self, limiter: Limiter) {
+        self.pages.1 = limiter;
+    }
+
     #[inline(always)]
     pub fn write_page(&mut self, key: PageID, value: [u8; 32]) -> anyhow::Result<()> {
         self.pages.write_page(key, value)
@@ -405,4 +470,22 @@ mod tests {
         assert!(right > left);
         assert!(right.to_bytes() > left.to_bytes());
     }
+
+    #[test]
+    fn tracked_release() {
+        let total = sync::DArc::new(std::sync::atomic::AtomicU64::new(100));
+        let parent = Limiter::new(total.clone()).derived(50);
+
+        let tracked = parent.tracked();
+        tracked.consume(10).unwrap();
+        tracked.derived(5).consume(5).unwrap();
+        assert_eq!(total.load(std::sync::atomic::Ordering::SeqCst), 85);
+
+        tracked.release_tracked();
+        assert_eq!(total.load(std::sync::atomic::Ordering::SeqCst), 100);
+        assert_eq!(parent.1[0].load(std::sync::atomic::Ordering::SeqCst), 50);
+
+        parent.consume(50).unwrap();
+        assert!(parent.consume(1).is_err());
+    }
 }
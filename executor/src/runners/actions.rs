@@ -8,6 +8,17 @@ pub enum WasmMode {
     Nondet,
 }
 
+fn deserialize_version<'de, D>(d: D) -> Result<Option<version::Version>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize as _;
+
+    Option::<String>::deserialize(d)?
+        .map(|x| std::str::FromStr::from_str(&x).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
 #[derive(Clone, Debug, serde::Deserialize)]
 pub enum InitAction {
     MapFile {
@@ -29,6 +40,17 @@ pub enum InitAction {
         cond: WasmMode,
         action: Box<InitAction>,
     },
+    /// executes action if version of the contract is within `[min, max]`
+    WhenVersion {
+        #[serde(default, deserialize_with = "deserialize_version")]
+        min: Option<version::Version>,
+        #[serde(default, deserialize_with = "deserialize_version")]
+        max: Option<version::Version>,
+        action: Box<InitAction>,
+    },
+    Fail {
+        message: String,
+    },
     Seq(Vec<InitAction>),
 
     With {
@@ -37,3 +59,12 @@ pub enum InitAction {
         action: Box<InitAction>,
     },
 }
+
+/// Checks `version` against bounds of [`InitAction::WhenVersion`], both of them are inclusive
+pub fn version_in_bounds(
+    version: version::Version,
+    min: Option<version::Version>,
+    max: Option<version::Version>,
+) -> bool {
+    !(min.is_some_and(|min| version < min) || max.is_some_and(|max| version > max))
+}
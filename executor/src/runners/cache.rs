@@ -4,7 +4,8 @@ use super::*;
 use genvm_common::*;
 
 pub struct Reader {
-    cache: sync::CacheMap<ArchiveCache>,
+    cache: sync::LruCacheMap<ArchiveCache>,
+    verifier: verify::Verifier,
     runners_data_path: std::path::PathBuf,
 
     all: BTreeMap<symbol_table::GlobalSymbol, Vec<symbol_table::GlobalSymbol>>,
@@ -16,6 +17,8 @@ impl Reader {
         path: &std::path::Path,
         registry_path: &std::path::Path,
         debug_mode: bool,
+        budget: Option<u64>,
+        verification: &crate::config::RunnerVerification,
     ) -> anyhow::Result<Self> {
         let runners_path = std::path::PathBuf::from(path);
         if !runners_path.exists() {
@@ -40,7 +43,8 @@ impl Reader {
         };
 
         Ok(Self {
-            cache: sync::CacheMap::new(),
+            cache: sync::LruCacheMap::new(budget),
+            verifier: verify::Verifier::new(verification)?,
             runners_data_path: runners_path.clone(),
             all,
             latest,
@@ -66,37 +70,67 @@ impl Reader {
         &self.runners_data_path
     }
 
+    /// Checks that archive read from `path` has hash from its id, checks are cached by file
+    pub fn verify(
+        &self,
+        uid: symbol_table::GlobalSymbol,
+        hash: &str,
+        path: &std::path::Path,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.verifier.verify(uid, hash, path, data).map_err(|e| {
+            anyhow::Error::from(rt::errors::VMError::wrap(
+                format!(
+                    "{} runner_verification",
+                    public_abi::VmError::InvalidContract.value()
+                ),
+                e,
+            ))
+        })
+    }
+
     pub async fn get_or_create<F>(
         &self,
         name: symbol_table::GlobalSymbol,
         arch_provider: impl FnOnce() -> F,
         limiter: &rt::memlimiter::Limiter,
-    ) -> anyhow::Result<sync::DArc<ArchiveCache>>
+    ) -> anyhow::Result<sync::LruLookup<ArchiveCache>>
     where
         F: std::future::Future<Output = anyhow::Result<Archive>>,
     {
-        let called = std::sync::atomic::AtomicBool::new(false);
+        get_or_create_in(&self.cache, name, arch_provider, limiter).await
+    }
+}
 
-        let res = self
-            .cache
-            .get_or_create(name, || async {
-                called.store(true, std::sync::atomic::Ordering::SeqCst);
+/// Returns cached archive, memory is charged to `limiter` even if archive was already cached
+pub async fn get_or_create_in<F>(
+    cache: &sync::LruCacheMap<ArchiveCache>,
+    name: symbol_table::GlobalSymbol,
+    arch_provider: impl FnOnce() -> F,
+    limiter: &rt::memlimiter::Limiter,
+) -> anyhow::Result<sync::LruLookup<ArchiveCache>>
+where
+    F: std::future::Future<Output = anyhow::Result<Archive>>,
+{
+    let res = cache
+        .get_or_create(
+            name,
+            |arch| arch.files.total_size as u64,
+            || async {
                 let arch = arch_provider().await?;
                 if !limiter.consume(arch.total_size) {
                     return Err(anyhow::Error::from(rt::errors::VMError::oom(None)));
                 }
                 Ok(ArchiveCache::new(name, arch))
-            })
-            .await?;
+            },
+        )
+        .await?;
 
-        if !called.load(std::sync::atomic::Ordering::SeqCst)
-            && !limiter.consume(res.files.total_size)
-        {
-            return Err(anyhow::Error::from(rt::errors::VMError::oom(None)));
-        }
-
-        Ok(res)
+    if res.hit && !limiter.consume(res.value.files.total_size) {
+        return Err(anyhow::Error::from(rt::errors::VMError::oom(None)));
     }
+
+    Ok(res)
 }
 
 pub fn get_cache_dir(base_path: &str) -> anyhow::Result<std::path::PathBuf> {
@@ -0,0 +1,1019 @@
+//! Static checks of contract packages, that detect at deploy time problems
+//! which otherwise surface only when contract is linked or executed
+//!
+//! Runner chain is walked for both modes the same way [`InitAction`]s are applied,
+//! but modules are only parsed and never compiled or instantiated
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr as _;
+
+use genvm_common::*;
+
+use super::*;
+
+const HOST_MODULES: [&str; 2] = [
+    include_str!("../wasi/witx/wasi_snapshot_preview1.witx"),
+    include_str!("../wasi/witx/genlayer_sdk.witx"),
+];
+
+/// Functions provided by the host, by module name
+fn host_functions() -> &'static BTreeMap<String, BTreeSet<String>> {
+    static FUNCTIONS: std::sync::LazyLock<BTreeMap<String, BTreeSet<String>>> =
+        std::sync::LazyLock::new(|| {
+            HOST_MODULES
+                .iter()
+                .map(|witx| {
+                    let name = witx
+                        .split_once("(module $")
+                        .and_then(|(_, rest)| rest.split_whitespace().next())
+                        .unwrap_or_default();
+
+                    let functions = witx
+                        .split("(export \"")
+                        .skip(1)
+                        .filter_map(|x| x.split_once('"').map(|x| x.0.to_owned()))
+                        .collect();
+
+                    (name.to_owned(), functions)
+                })
+                .collect()
+        });
+
+    &FUNCTIONS
+}
+
+/// Environment that is needed to resolve and check runners of a contract
+pub struct Env<'a> {
+    pub runners: &'a cache::Reader,
+    pub engines: &'a rt::DetNondet<wasmtime::Engine>,
+    /// larger code is reported with [`Problem::OversizedCode`]
+    pub max_code_size: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    OversizedCode {
+        size: u64,
+        limit: u64,
+    },
+    /// code does not specify version, [`public_abi::ABSENT_VERSION`] is used
+    MissingVersion,
+    InvalidVersion {
+        error: String,
+    },
+    /// contract can't be parsed by [`parse`], no other checks are done
+    InvalidPackage {
+        error: String,
+    },
+    UnresolvedRunner {
+        mode: WasmMode,
+        runner: String,
+        error: String,
+    },
+    MissingFile {
+        mode: WasmMode,
+        runner: String,
+        file: String,
+    },
+    /// runner chain reached [`InitAction::Fail`]
+    RunnerFails {
+        mode: WasmMode,
+        runner: String,
+        message: String,
+    },
+    /// runner chain does not reach [`InitAction::StartWasm`]
+    NoStart {
+        mode: WasmMode,
+    },
+    InvalidModule {
+        mode: WasmMode,
+        runner: String,
+        path: String,
+        error: String,
+    },
+    ForbiddenFeature {
+        mode: WasmMode,
+        runner: String,
+        path: String,
+        feature: &'static str,
+        error: String,
+    },
+    UnresolvedImport {
+        mode: WasmMode,
+        runner: String,
+        path: String,
+        module: String,
+        name: String,
+    },
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Module {
+    pub mode: WasmMode,
+    pub runner: String,
+    pub path: String,
+    /// name under which exports of the module are visible to the following ones
+    pub name: Option<String>,
+    /// function names from name section by function index, used by `genvm symbolize`
+    #[serde(skip)]
+    pub functions: BTreeMap<u32, String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Report {
+    pub code_size: u64,
+    pub version: Option<String>,
+    /// resolved runners in order of first use, contract itself is not included
+    pub runners: Vec<String>,
+    /// linked and started modules in order of linking
+    pub modules: Vec<Module>,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct ModuleSummary {
+    name: Option<String>,
+    imports: Vec<(String, String)>,
+    exports: BTreeSet<String>,
+    functions: BTreeMap<u32, String>,
+    /// function index and operator of the first floating point operation that is forbidden in deterministic mode
+    forbidden_float: Option<(u32, String)>,
+    forbidden_float_count: u64,
+}
+
+/// Floating point operations that trap in deterministic mode. Loads, stores, constants and reinterpretations
+/// only move bits and are allowed. Vector operations on `f32x4` and `f64x2` lanes, including conversions
+/// to and from integer lanes, are forbidden as well, see `spec/02-execution-environment/01-wasm`
+fn det_forbidden_float_op(op: &wasmparser::Operator) -> bool {
+    use wasmparser::Operator as O;
+
+    matches!(
+        op,
+        O::F32Eq
+            | O::F32Ne
+            | O::F32Lt
+            | O::F32Gt
+            | O::F32Le
+            | O::F32Ge
+            | O::F64Eq
+            | O::F64Ne
+            | O::F64Lt
+            | O::F64Gt
+            | O::F64Le
+            | O::F64Ge
+            | O::F32Abs
+            | O::F32Neg
+            | O::F32Ceil
+            | O::F32Floor
+            | O::F32Trunc
+            | O::F32Nearest
+            | O::F32Sqrt
+            | O::F32Add
+            | O::F32Sub
+            | O::F32Mul
+            | O::F32Div
+            | O::F32Min
+            | O::F32Max
+            | O::F32Copysign
+            | O::F64Abs
+            | O::F64Neg
+            | O::F64Ceil
+            | O::F64Floor
+            | O::F64Trunc
+            | O::F64Nearest
+            | O::F64Sqrt
+            | O::F64Add
+            | O::F64Sub
+            | O::F64Mul
+            | O::F64Div
+            | O::F64Min
+            | O::F64Max
+            | O::F64Copysign
+            | O::I32TruncF32S
+            | O::I32TruncF32U
+            | O::I32TruncF64S
+            | O::I32TruncF64U
+            | O::I64TruncF32S
+            | O::I64TruncF32U
+            | O::I64TruncF64S
+            | O::I64TruncF64U
+            | O::F32ConvertI32S
+            | O::F32ConvertI32U
+            | O::F32ConvertI64S
+            | O::F32ConvertI64U
+            | O::F32DemoteF64
+            | O::F64ConvertI32S
+            | O::F64ConvertI32U
+            | O::F64ConvertI64S
+            | O::F64ConvertI64U
+            | O::F64PromoteF32
+            | O::I32TruncSatF32S
+            | O::I32TruncSatF32U
+            | O::I32TruncSatF64S
+            | O::I32TruncSatF64U
+            | O::I64TruncSatF32S
+            | O::I64TruncSatF32U
+            | O::I64TruncSatF64S
+            | O::I64TruncSatF64U
+            | O::F32x4Splat
+            | O::F64x2Splat
+            | O::F32x4ExtractLane { .. }
+            | O::F32x4ReplaceLane { .. }
+            | O::F64x2ExtractLane { .. }
+            | O::F64x2ReplaceLane { .. }
+            | O::F32x4Eq
+            | O::F32x4Ne
+            | O::F32x4Lt
+            | O::F32x4Gt
+            | O::F32x4Le
+            | O::F32x4Ge
+            | O::F64x2Eq
+            | O::F64x2Ne
+            | O::F64x2Lt
+            | O::F64x2Gt
+            | O::F64x2Le
+            | O::F64x2Ge
+            | O::F32x4Ceil
+            | O::F32x4Floor
+            | O::F32x4Trunc
+            | O::F32x4Nearest
+            | O::F32x4Abs
+            | O::F32x4Neg
+            | O::F32x4Sqrt
+            | O::F32x4Add
+            | O::F32x4Sub
+            | O::F32x4Mul
+            | O::F32x4Div
+            | O::F32x4Min
+            | O::F32x4Max
+            | O::F32x4PMin
+            | O::F32x4PMax
+            | O::F64x2Ceil
+            | O::F64x2Floor
+            | O::F64x2Trunc
+            | O::F64x2Nearest
+            | O::F64x2Abs
+            | O::F64x2Neg
+            | O::F64x2Sqrt
+            | O::F64x2Add
+            | O::F64x2Sub
+            | O::F64x2Mul
+            | O::F64x2Div
+            | O::F64x2Min
+            | O::F64x2Max
+            | O::F64x2PMin
+            | O::F64x2PMax
+            | O::I32x4TruncSatF32x4S
+            | O::I32x4TruncSatF32x4U
+            | O::F32x4ConvertI32x4S
+            | O::F32x4ConvertI32x4U
+            | O::I32x4TruncSatF64x2SZero
+            | O::I32x4TruncSatF64x2UZero
+            | O::F64x2ConvertLowI32x4S
+            | O::F64x2ConvertLowI32x4U
+            | O::F32x4DemoteF64x2Zero
+            | O::F64x2PromoteLowF32x4
+            | O::I32x4RelaxedTruncF32x4S
+            | O::I32x4RelaxedTruncF32x4U
+            | O::I32x4RelaxedTruncF64x2SZero
+            | O::I32x4RelaxedTruncF64x2UZero
+            | O::F32x4RelaxedMadd
+            | O::F32x4RelaxedNmadd
+            | O::F64x2RelaxedMadd
+            | O::F64x2RelaxedNmadd
+            | O::F32x4RelaxedMin
+            | O::F32x4RelaxedMax
+            | O::F64x2RelaxedMin
+            | O::F64x2RelaxedMax
+    )
+}
+
+fn summarize(wasm: &[u8], scan_floats: bool) -> anyhow::Result<ModuleSummary> {
+    let mut res = ModuleSummary::default();
+    let mut imported_functions = 0;
+    let mut defined_functions = 0;
+
+    for payload in wasmparser::Parser::new(0).parse_all(wasm) {
+        match payload? {
+            wasmparser::Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import?;
+                    if let wasmparser::TypeRef::Func(_) = import.ty {
+                        imported_functions += 1;
+                    }
+                    res.imports
+                        .push((import.module.to_owned(), import.name.to_owned()));
+                }
+            }
+            wasmparser::Payload::ExportSection(reader) => {
+                for export in reader {
+                    res.exports.insert(export?.name.to_owned());
+                }
+            }
+            wasmparser::Payload::CustomSection(reader) => {
+                if let wasmparser::KnownCustom::Name(names) = reader.as_known() {
+                    for name in names {
+                        match name? {
+                            wasmparser::Name::Module { name, .. } => {
+                                res.name = Some(name.to_owned());
+                            }
+                            wasmparser::Name::Function(map) => {
+                                for naming in map {
+                                    let naming = naming?;
+                                    res.functions.insert(naming.index, naming.name.to_owned());
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            wasmparser::Payload::CodeSectionEntry(body) if scan_floats => {
+                let func_index = imported_functions + defined_functions;
+                defined_functions += 1;
+
+                let mut reader = body.get_operators_reader()?;
+                while !reader.eof() {
+                    let op = reader.read()?;
+                    if !det_forbidden_float_op(&op) {
+                        continue;
+                    }
+
+                    res.forbidden_float_count += 1;
+                    if res.forbidden_float.is_none() {
+                        res.forbidden_float = Some((func_index, format!("{op:?}")));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(res)
+}
+
+struct Abort;
+
+struct Walker<'a, 'b> {
+    env: &'a Env<'a>,
+    mode: WasmMode,
+    contract: &'b Arc<ArchiveCache>,
+    contract_version: version::Version,
+    archives: &'b mut BTreeMap<String, Arc<ArchiveCache>>,
+    visited: BTreeSet<String>,
+    /// exports of linked modules, by module name
+    linked: BTreeMap<String, BTreeSet<String>>,
+    report: &'b mut Report,
+}
+
+impl Walker<'_, '_> {
+    /// Resolves `:latest` and `:test` the same way execution does
+    fn resolve(&self, uid: &str) -> anyhow::Result<(String, String)> {
+        let Some((id, hash)) = verify_runner(uid) else {
+            anyhow::bail!("invalid runner id: {uid}");
+        };
+
+        let hash = if hash == "latest" || hash == "test" {
+            self.env
+                .runners
+                .get_latest(symbol_table::GlobalSymbol::from(id))
+                .ok_or_else(|| anyhow::anyhow!("no latest version of {id}"))?
+        } else {
+            symbol_table::GlobalSymbol::from(hash)
+        };
+
+        if !self
+            .env
+            .runners
+            .has_in_all(symbol_table::GlobalSymbol::from(id), hash)
+        {
+            anyhow::bail!("runner {id}:{hash} not found");
+        }
+
+        Ok((id.to_owned(), hash.as_str().to_owned()))
+    }
+
+    fn load(&mut self, uid: &str) -> anyhow::Result<(String, Arc<ArchiveCache>)> {
+        let (id, hash) = self.resolve(uid)?;
+        let uid = format!("{id}:{hash}");
+
+        if let Some(arch) = self.archives.get(&uid) {
+            return Ok((uid, arch.clone()));
+        }
+
+        let (path, format) = find_runner_archive(self.env.runners.runners_path(), &id, &hash)
+            .ok_or_else(|| anyhow::anyhow!("runner {uid} not found"))?;
+        let data = read_runner_archive(&path, format, u32::MAX)?;
+
+        let uid_sym = symbol_table::GlobalSymbol::from(uid.as_str());
+        self.env
+            .runners
+            .verify(uid_sym, &hash, &path, data.as_ref())?;
+
+        let arch = Arc::new(ArchiveCache::new(uid_sym, Archive::from_ustar(data)?));
+
+        self.archives.insert(uid.clone(), arch.clone());
+        if !self.report.runners.contains(&uid) {
+            self.report.runners.push(uid.clone());
+        }
+
+        Ok((uid, arch))
+    }
+
+    fn actions(arch: &ArchiveCache) -> anyhow::Result<InitAction> {
+        let contents = arch.get_file("runner.json")?;
+
+        Ok(serde_json::from_slice(contents.as_ref())?)
+    }
+
+    fn check_module(&mut self, runner: &str, path: &str, wasm: &[u8]) -> ModuleSummary {
+        let is_det = self.mode == WasmMode::Det;
+        let engine = self.env.engines.get(is_det);
+        let features = rt::supervisor::validation_features(engine);
+
+        let all_features = rt::supervisor::validation_features(&self.env.engines.det)
+            | rt::supervisor::validation_features(&self.env.engines.non_det);
+
+        if let Err(e) = wasmparser::Validator::new_with_features(all_features).validate_all(wasm) {
+            self.report.problems.push(Problem::InvalidModule {
+                mode: self.mode,
+                runner: runner.to_owned(),
+                path: path.to_owned(),
+                error: format!("{e:#}"),
+            });
+            return ModuleSummary::default();
+        }
+
+        if let Err(e) = wasmparser::Validator::new_with_features(features).validate_all(wasm) {
+            self.report.problems.push(Problem::ForbiddenFeature {
+                mode: self.mode,
+                runner: runner.to_owned(),
+                path: path.to_owned(),
+                feature: "validation",
+                error: format!("{e:#}"),
+            });
+        } else if is_det
+            && self.contract_version
+                < version::Version::from_str(public_abi::DET_SIMD_MIN_VERSION).unwrap()
+        {
+            let features = features.difference(rt::supervisor::DET_GATED_FEATURES);
+            if let Err(e) = wasmparser::Validator::new_with_features(features).validate_all(wasm) {
+                self.report.problems.push(Problem::ForbiddenFeature {
+                    mode: self.mode,
+                    runner: runner.to_owned(),
+                    path: path.to_owned(),
+                    feature: "simd",
+                    error: format!(
+                        "{e:#}, SIMD requires contract version {} or newer",
+                        public_abi::DET_SIMD_MIN_VERSION
+                    ),
+                });
+            }
+        }
+
+        // floats trap only when executed, runners (such as interpreters) may have unused float code paths
+        let scan_floats = is_det && runner == self.contract.id.as_str();
+
+        let mut summary = match summarize(wasm, scan_floats) {
+            Ok(summary) => summary,
+            Err(e) => {
+                self.report.problems.push(Problem::InvalidModule {
+                    mode: self.mode,
+                    runner: runner.to_owned(),
+                    path: path.to_owned(),
+                    error: format!("{e:#}"),
+                });
+                return ModuleSummary::default();
+            }
+        };
+
+        if let Some((func_index, op)) = &summary.forbidden_float {
+            self.report.problems.push(Problem::ForbiddenFeature {
+                mode: self.mode,
+                runner: runner.to_owned(),
+                path: path.to_owned(),
+                feature: "floats",
+                error: format!(
+                    "{} floating point operations, first is {op} in function {func_index}",
+                    summary.forbidden_float_count
+                ),
+            });
+        }
+
+        for (module, name) in &summary.imports {
+            let resolved = host_functions()
+                .get(module)
+                .or_else(|| self.linked.get(module))
+                .is_some_and(|names| names.contains(name));
+
+            if !resolved {
+                self.report.problems.push(Problem::UnresolvedImport {
+                    mode: self.mode,
+                    runner: runner.to_owned(),
+                    path: path.to_owned(),
+                    module: module.clone(),
+                    name: name.clone(),
+                });
+            }
+        }
+
+        self.report.modules.push(Module {
+            mode: self.mode,
+            runner: runner.to_owned(),
+            path: path.to_owned(),
+            name: summary.name.clone(),
+            functions: std::mem::take(&mut summary.functions),
+        });
+
+        summary
+    }
+
+    /// Returns whenever module was started
+    fn apply(
+        &mut self,
+        action: &InitAction,
+        current: &str,
+        arch: &ArchiveCache,
+    ) -> Result<bool, Abort> {
+        match action {
+            InitAction::AddEnv { .. } | InitAction::SetArgs(_) => Ok(false),
+            InitAction::MapFile { file, .. } => {
+                let exists = if file.ends_with("/") {
+                    let prefix = if file.as_ref() == "/" { "" } else { &file[..] };
+                    arch.files.data.keys().any(|x| x.starts_with(prefix))
+                } else {
+                    arch.files.data.contains_key(file.as_ref())
+                };
+
+                if !exists {
+                    self.report.problems.push(Problem::MissingFile {
+                        mode: self.mode,
+                        runner: current.to_owned(),
+                        file: file.to_string(),
+                    });
+                }
+                Ok(false)
+            }
+            InitAction::LinkWasm(path) | InitAction::StartWasm(path) => {
+                let Ok(contents) = arch.get_file(path) else {
+                    self.report.problems.push(Problem::MissingFile {
+                        mode: self.mode,
+                        runner: current.to_owned(),
+                        file: path.to_string(),
+                    });
+                    return Err(Abort);
+                };
+
+                let summary = self.check_module(current, path, contents.as_ref());
+
+                if let InitAction::StartWasm(_) = action {
+                    return Ok(true);
+                }
+
+                if let Some(name) = summary.name {
+                    self.linked.insert(name, summary.exports);
+                }
+
+                Ok(false)
+            }
+            InitAction::When { cond, action } => {
+                if *cond != self.mode {
+                    return Ok(false);
+                }
+                self.apply(action, current, arch)
+            }
+            InitAction::WhenVersion { min, max, action } => {
+                if !version_in_bounds(self.contract_version, *min, *max) {
+                    return Ok(false);
+                }
+                self.apply(action, current, arch)
+            }
+            InitAction::Fail { message } => {
+                self.report.problems.push(Problem::RunnerFails {
+                    mode: self.mode,
+                    runner: current.to_owned(),
+                    message: message.clone(),
+                });
+                Err(Abort)
+            }
+            InitAction::Seq(actions) => {
+                for action in actions {
+                    if self.apply(action, current, arch)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            InitAction::With { runner, action } => {
+                let (uid, new_arch) = self.load_or_report(runner.as_str())?;
+                self.apply(action, &uid, &new_arch)
+            }
+            InitAction::Depends(runner) => {
+                if runner.as_str() == "<contract>" {
+                    return self.apply_contract();
+                }
+
+                let (uid, new_arch) = self.load_or_report(runner.as_str())?;
+                if !self.visited.insert(uid.clone()) {
+                    return Ok(false);
+                }
+
+                let new_action = match Self::actions(&new_arch) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        self.report.problems.push(Problem::UnresolvedRunner {
+                            mode: self.mode,
+                            runner: uid,
+                            error: format!("{e:#}"),
+                        });
+                        return Err(Abort);
+                    }
+                };
+
+                self.apply(&new_action, &uid, &new_arch)
+            }
+        }
+    }
+
+    fn load_or_report(&mut self, runner: &str) -> Result<(String, Arc<ArchiveCache>), Abort> {
+        if runner == "<contract>" {
+            return Ok((self.contract.id.as_str().to_owned(), self.contract.clone()));
+        }
+
+        self.load(runner).map_err(|e| {
+            self.report.problems.push(Problem::UnresolvedRunner {
+                mode: self.mode,
+                runner: runner.to_owned(),
+                error: format!("{e:#}"),
+            });
+            Abort
+        })
+    }
+
+    fn apply_contract(&mut self) -> Result<bool, Abort> {
+        let contract = self.contract;
+        if !self.visited.insert(contract.id.as_str().to_owned()) {
+            return Ok(false);
+        }
+
+        let action = match Self::actions(contract) {
+            Ok(x) => x,
+            Err(e) => {
+                self.report.problems.push(Problem::InvalidPackage {
+                    error: format!("{e:#}"),
+                });
+                return Err(Abort);
+            }
+        };
+
+        self.apply(&action, contract.id.as_str(), contract)
+    }
+}
+
+/// Checks contract code the same way it would be linked for execution, all found problems are collected into the report
+pub fn check_contract(code: util::SharedBytes, env: &Env<'_>) -> Report {
+    let mut report = Report {
+        code_size: code.len() as u64,
+        ..Default::default()
+    };
+
+    if let Some(limit) = env.max_code_size {
+        if report.code_size > limit {
+            report.problems.push(Problem::OversizedCode {
+                size: report.code_size,
+                limit,
+            });
+        }
+    }
+
+    let contract = match parse(code) {
+        Ok((arch, version_defaulted)) => {
+            if version_defaulted {
+                report.problems.push(Problem::MissingVersion);
+            }
+            Arc::new(ArchiveCache::new(
+                symbol_table::GlobalSymbol::from("<contract>"),
+                arch,
+            ))
+        }
+        Err(e) => {
+            report.problems.push(Problem::InvalidPackage {
+                error: format!("{e:#}"),
+            });
+            return report;
+        }
+    };
+
+    let contract_version = match contract.get_version() {
+        Ok(v) => v,
+        Err(e) => {
+            report.problems.push(Problem::InvalidVersion {
+                error: format!("{e:#}"),
+            });
+            version::Version::from_str(public_abi::ABSENT_VERSION).unwrap()
+        }
+    };
+    report.version = Some(contract_version.to_string());
+
+    let mut archives = BTreeMap::new();
+
+    for mode in [WasmMode::Det, WasmMode::Nondet] {
+        let mut walker = Walker {
+            env,
+            mode,
+            contract: &contract,
+            contract_version,
+            archives: &mut archives,
+            visited: BTreeSet::new(),
+            linked: BTreeMap::new(),
+            report: &mut report,
+        };
+
+        if let Ok(false) = walker.apply_contract() {
+            report.problems.push(Problem::NoStart { mode });
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks code against an empty registry, so only `<contract>` can be resolved
+    fn check(name: &str, code: &[u8]) -> Report {
+        check_with_runners(name, &[], code)
+    }
+
+    /// Checks code against a registry that has only given runners, which are pairs of id and tar archive
+    fn check_with_runners(name: &str, runners: &[(&str, &[u8])], code: &[u8]) -> Report {
+        let dir = std::env::temp_dir().join(format!("genvm-check-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut all = BTreeMap::<&str, Vec<String>>::new();
+        for (id, tar) in runners {
+            let hash = runner_hash(tar);
+            let mut path = dir.clone();
+            append_runner_subpath(id, &hash, &mut path);
+            let path = path.with_extension(ArchiveFormat::Tar.extension());
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, tar).unwrap();
+            all.entry(id).or_default().push(hash);
+        }
+        std::fs::write(dir.join("all.json"), serde_json::to_vec(&all).unwrap()).unwrap();
+
+        let runners = cache::Reader::new(&dir, &dir, false, None, &Default::default()).unwrap();
+        let engines = rt::supervisor::create_engines(|_| Ok(())).unwrap();
+
+        let report = check_contract(
+            util::SharedBytes::from(code),
+            &Env {
+                runners: &runners,
+                engines: &engines,
+                max_code_size: None,
+            },
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+        report
+    }
+
+    fn runner_hash(tar: &[u8]) -> String {
+        use sha2::Digest as _;
+
+        verify::digest_to_hash_id(&sha2::Sha256::digest(tar))
+    }
+
+    /// Minimal ustar archive with regular files
+    fn ustar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut res = Vec::new();
+        for (name, data) in files {
+            let mut header = [0_u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            header[124..135].copy_from_slice(format!("{:011o}", data.len()).as_bytes());
+            header[156] = b'0';
+            header[257..265].copy_from_slice(b"ustar\x0000");
+            header[148..156].fill(b' ');
+            let checksum: u32 = header.iter().map(|x| *x as u32).sum();
+            header[148..155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+
+            res.extend_from_slice(&header);
+            res.extend_from_slice(data);
+            res.resize(res.len().div_ceil(512) * 512, 0);
+        }
+        res.resize(res.len() + 1024, 0);
+        res
+    }
+
+    fn wasm_contract(version: &str, body: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            "(module (@custom \"genvm.version\" \"{version}\") {body})"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn unresolved_import() {
+        let code = wasm_contract(
+            public_abi::DET_SIMD_MIN_VERSION,
+            r#"(import "env" "missing" (func)) (func (export "_start"))"#,
+        );
+        let report = check("import", &code);
+
+        for mode in [WasmMode::Det, WasmMode::Nondet] {
+            assert!(
+                report.problems.iter().any(|p| matches!(
+                    p,
+                    Problem::UnresolvedImport { mode: m, module, name, .. }
+                        if *m == mode && module == "env" && name == "missing"
+                )),
+                "{report:?}"
+            );
+        }
+        assert!(!report
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::MissingVersion)));
+    }
+
+    #[test]
+    fn missing_version() {
+        let report = check("version", b"# { \"StartWasm\": \"file\" }\n");
+
+        assert!(
+            matches!(report.problems[0], Problem::MissingVersion),
+            "{report:?}"
+        );
+        assert_eq!(report.version.as_deref(), Some(public_abi::ABSENT_VERSION));
+
+        let versioned = check("versioned", b"# v0.2.5\n# { \"Seq\": [] }\n");
+        assert!(!versioned
+            .problems
+            .iter()
+            .any(|p| matches!(p, Problem::MissingVersion)));
+    }
+
+    #[test]
+    fn simd_below_gate() {
+        let body = "(func (export \"_start\") v128.const i32x4 0 0 0 0 drop)";
+
+        let report = check("simd-old", &wasm_contract(public_abi::ABSENT_VERSION, body));
+        assert!(
+            matches!(
+                &report.problems[..],
+                [Problem::ForbiddenFeature {
+                    mode: WasmMode::Det,
+                    feature: "simd",
+                    ..
+                }]
+            ),
+            "{report:?}"
+        );
+
+        let report = check(
+            "simd-new",
+            &wasm_contract(public_abi::DET_SIMD_MIN_VERSION, body),
+        );
+        assert!(report.is_ok(), "{report:?}");
+    }
+
+    #[test]
+    fn fail_reached() {
+        let report = check(
+            "fail",
+            b"# v0.2.5\n# { \"When\": { \"cond\": \"det\", \"action\": { \"Fail\": { \"message\": \"no det\" } } } }\n",
+        );
+
+        assert!(
+            report.problems.iter().any(|p| matches!(
+                p,
+                Problem::RunnerFails { mode: WasmMode::Det, message, .. } if message == "no det"
+            )),
+            "{report:?}"
+        );
+        assert!(report.problems.iter().any(|p| matches!(
+            p,
+            Problem::NoStart {
+                mode: WasmMode::Nondet
+            }
+        )));
+    }
+
+    #[test]
+    fn when_version() {
+        // fails only if contract of `version` is within the bound of the gate
+        let gated = |bound: &str, version: &str| {
+            let code = format!(
+                "{version}# {{ \"WhenVersion\": {{ \"{bound}\": \"v0.2.5\", \"action\": {{ \"Fail\": {{ \"message\": \"gated\" }} }} }} }}\n"
+            );
+            let report = check(&format!("when-{bound}"), code.as_bytes());
+            report.problems.iter().any(|p| {
+                matches!(p, Problem::RunnerFails { mode: WasmMode::Det, message, .. } if message == "gated")
+            })
+        };
+
+        // missing version is defaulted to the oldest one
+        for (version, min_gated, max_gated) in [
+            ("# v0.2.4\n", false, true),
+            ("# v0.2.5\n", true, true),
+            ("# v0.3.0\n", true, false),
+            ("", false, true),
+        ] {
+            assert_eq!(gated("min", version), min_gated, "min {version:?}");
+            assert_eq!(gated("max", version), max_gated, "max {version:?}");
+        }
+    }
+
+    #[test]
+    fn missing_start() {
+        let report = check(
+            "start",
+            b"# v0.2.5\n# { \"AddEnv\": { \"name\": \"a\", \"val\": \"b\" } }\n",
+        );
+
+        let modes: Vec<_> = report
+            .problems
+            .iter()
+            .map(|p| match p {
+                Problem::NoStart { mode } => *mode,
+                p => panic!("unexpected {p:?}"),
+            })
+            .collect();
+        assert_eq!(modes, [WasmMode::Det, WasmMode::Nondet]);
+    }
+
+    #[test]
+    fn det_float_ops() {
+        use wasmparser::Operator;
+
+        for op in [
+            Operator::F64Add,
+            Operator::F32x4Add,
+            Operator::F64x2Mul,
+            Operator::F32x4Splat,
+            Operator::F32x4ExtractLane { lane: 0 },
+            Operator::I32x4TruncSatF32x4S,
+            Operator::F32x4ConvertI32x4S,
+            Operator::F32x4RelaxedMadd,
+            Operator::I32x4RelaxedTruncF32x4S,
+        ] {
+            assert!(det_forbidden_float_op(&op), "{op:?}");
+        }
+
+        for op in [
+            Operator::F32ReinterpretI32,
+            Operator::F64ReinterpretI64,
+            Operator::I32x4Add,
+            Operator::I8x16Swizzle,
+            Operator::I16x8RelaxedQ15mulrS,
+            Operator::V128And,
+        ] {
+            assert!(!det_forbidden_float_op(&op), "{op:?}");
+        }
+    }
+
+    #[test]
+    fn floats_in_runner() {
+        let body = r#"(func (export "_start") f64.const 1 f64.const 2 f64.add drop)"#;
+
+        let runner = ustar(&[
+            ("runner.json", br#"{ "StartWasm": "float.wasm" }"#),
+            (
+                "float.wasm",
+                &wat::parse_str(format!("(module {body})")).unwrap(),
+            ),
+        ]);
+        let code = format!(
+            "# v0.2.5\n# {{ \"Depends\": \"floaty:{}\" }}\n",
+            runner_hash(&runner)
+        );
+        let report = check_with_runners("float-runner", &[("floaty", &runner)], code.as_bytes());
+        assert!(report.is_ok(), "{report:?}");
+        assert_eq!(report.runners.len(), 1);
+
+        let report = check(
+            "float-contract",
+            &wasm_contract(public_abi::DET_SIMD_MIN_VERSION, body),
+        );
+        assert!(
+            matches!(
+                &report.problems[..],
+                [Problem::ForbiddenFeature {
+                    mode: WasmMode::Det,
+                    feature: "floats",
+                    ..
+                }]
+            ),
+            "{report:?}"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 pub mod actions;
 pub mod cache;
+pub mod check;
+pub mod verify;
 
 mod parse;
 mod ustar;
@@ -23,6 +25,81 @@ pub fn append_runner_subpath(id: &str, hash: &str, path: &mut std::path::PathBuf
     path.push(&hash[2..]);
 }
 
+/// Formats in which runners can be stored in runners directory, in lookup order
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+    Tar,
+    TarZstd,
+    TarGzip,
+}
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 3] = [Self::Tar, Self::TarZstd, Self::TarGzip];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarZstd => "tar.zst",
+            Self::TarGzip => "tar.gz",
+        }
+    }
+}
+
+/// Returns path of the runner archive in `runners_dir` in any of supported formats
+pub fn find_runner_archive(
+    runners_dir: &std::path::Path,
+    id: &str,
+    hash: &str,
+) -> Option<(std::path::PathBuf, ArchiveFormat)> {
+    let mut path = runners_dir.to_owned();
+    append_runner_subpath(id, hash, &mut path);
+
+    ArchiveFormat::ALL.into_iter().find_map(|format| {
+        let path = path.with_extension(format.extension());
+        path.exists().then_some((path, format))
+    })
+}
+
+/// Reads uncompressed tar stream of the archive, compressed archives are decompressed into memory.
+/// Decompression stops after `max_size` bytes, which results in out of memory error
+pub fn read_runner_archive(
+    path: &std::path::Path,
+    format: ArchiveFormat,
+    max_size: u32,
+) -> anyhow::Result<util::SharedBytes> {
+    use std::io::Read as _;
+
+    if format == ArchiveFormat::Tar {
+        let data = util::mmap_file(path).with_context(|| format!("reading {path:?}"))?;
+        return Ok(util::SharedBytes::new(data));
+    }
+
+    let file = std::fs::File::open(path).with_context(|| format!("opening {path:?}"))?;
+    let file = std::io::BufReader::new(file);
+
+    let decoder: Box<dyn std::io::Read> = match format {
+        ArchiveFormat::TarZstd => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+        ArchiveFormat::TarGzip => Box::new(flate2::bufread::MultiGzDecoder::new(file)),
+        ArchiveFormat::Tar => unreachable!(),
+    };
+
+    let mut data = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("decompressing {path:?}"))?;
+
+    if data.len() as u64 > max_size as u64 {
+        return Err(
+            anyhow::Error::from(rt::errors::VMError::oom(None)).context(format!(
+                "decompressed {path:?} is larger than {max_size} bytes"
+            )),
+        );
+    }
+
+    Ok(util::SharedBytes::from(data))
+}
+
 pub fn get_runner_of_contract(address: calldata::Address) -> symbol_table::GlobalSymbol {
     let mut contract_id = String::from("on_chain:0x");
     contract_id.push_str(&hex::encode(address.raw()));
@@ -113,3 +190,107 @@ pub fn verify_runner(runner_id: &str) -> Option<(&str, &str)> {
     }
     Some((runner_id, runner_hash))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_runners_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("genvm-runners-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn write_archive(
+        dir: &std::path::Path,
+        hash: &str,
+        format: ArchiveFormat,
+        data: &[u8],
+    ) -> std::path::PathBuf {
+        use std::io::Write as _;
+
+        let mut path = dir.to_owned();
+        append_runner_subpath("test", hash, &mut path);
+        let path = path.with_extension(format.extension());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let contents = match format {
+            ArchiveFormat::Tar => data.to_vec(),
+            ArchiveFormat::TarZstd => zstd::encode_all(data, 0).unwrap(),
+            ArchiveFormat::TarGzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        };
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn lookup_order() {
+        let dir = temp_runners_dir("lookup");
+        let data = b"uncompressed tar stream".repeat(100);
+
+        assert!(find_runner_archive(&dir, "test", "hash").is_none());
+
+        for format in [
+            ArchiveFormat::TarGzip,
+            ArchiveFormat::TarZstd,
+            ArchiveFormat::Tar,
+        ] {
+            let path = write_archive(&dir, "hash", format, &data);
+            assert_eq!(
+                find_runner_archive(&dir, "test", "hash"),
+                Some((path.clone(), format))
+            );
+            let read = read_runner_archive(&path, format, u32::MAX).unwrap();
+            assert_eq!(read.as_ref(), data.as_slice());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        let dir = temp_runners_dir("bounded");
+        let data = vec![0u8; 1 << 16];
+
+        for format in [ArchiveFormat::TarZstd, ArchiveFormat::TarGzip] {
+            let path = write_archive(&dir, "hash", format, &data);
+
+            let read = read_runner_archive(&path, format, data.len() as u32).unwrap();
+            assert_eq!(read.len(), data.len());
+
+            let err = read_runner_archive(&path, format, data.len() as u32 - 1).unwrap_err();
+            assert!(err.downcast_ref::<rt::errors::VMError>().is_some());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hash_covers_uncompressed_stream() {
+        use sha2::Digest as _;
+
+        let dir = temp_runners_dir("hash");
+        let data = b"uncompressed tar stream".repeat(100);
+        let hash = verify::digest_to_hash_id(&sha2::Sha256::digest(&data));
+
+        let verifier = verify::Verifier::new(&Default::default()).unwrap();
+
+        for format in [ArchiveFormat::TarZstd, ArchiveFormat::TarGzip] {
+            let path = write_archive(&dir, &hash, format, &data);
+            let uid = symbol_table::GlobalSymbol::from(format!("test:{hash}:{format:?}"));
+
+            let compressed = std::fs::read(&path).unwrap();
+            assert!(verifier.verify(uid, &hash, &path, &compressed).is_err());
+
+            let read = read_runner_archive(&path, format, u32::MAX).unwrap();
+            verifier.verify(uid, &hash, &path, read.as_ref()).unwrap();
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -21,30 +21,34 @@ fn detect_version_from_wasm(code: &[u8]) -> anyhow::Result<String> {
     Err(anyhow::anyhow!("version section not found"))
 }
 
-pub fn parse(code: util::SharedBytes) -> anyhow::Result<super::Archive> {
+/// Returns archive and whenever code did not specify its version, so that [`public_abi::ABSENT_VERSION`] is used
+pub fn parse(code: util::SharedBytes) -> anyhow::Result<(super::Archive, bool)> {
     if let Ok(mut as_zip) = zip::ZipArchive::new(std::io::Cursor::new(code.clone())) {
-        return super::Archive::from_zip(&mut as_zip, code);
+        let arch = super::Archive::from_zip(&mut as_zip, code)?;
+        let defaulted = !arch.data.contains_key("version");
+        return Ok((arch, defaulted));
     }
 
     if wasmparser::Parser::is_core_wasm(code.as_ref()) {
-        let version = match detect_version_from_wasm(code.as_ref()) {
-            Ok(v) => v,
+        let (version, defaulted) = match detect_version_from_wasm(code.as_ref()) {
+            Ok(v) => (v, false),
             Err(e) => {
                 log_warn!(default = public_abi::ABSENT_VERSION, error = e; "could not detect version from wasm");
-                public_abi::ABSENT_VERSION.to_string()
+                (public_abi::ABSENT_VERSION.to_string(), true)
             }
         };
-        return Ok(super::Archive::from_file_and_runner(
+        let arch = super::Archive::from_file_and_runner(
             code,
             util::SharedBytes::from(version.as_bytes()),
             util::SharedBytes::from(b"{ \"StartWasm\": \"file\" }".as_ref()),
-        ));
+        );
+        return Ok((arch, defaulted));
     }
 
     code_to_archive_from_text(code)
 }
 
-fn code_to_archive_from_text(code: util::SharedBytes) -> anyhow::Result<super::Archive> {
+fn code_to_archive_from_text(code: util::SharedBytes) -> anyhow::Result<(super::Archive, bool)> {
     let code_str = std::str::from_utf8(code.as_ref()).map_err(|e| {
         rt::errors::VMError(
             format!(
@@ -73,6 +77,7 @@ fn code_to_archive_from_text(code: util::SharedBytes) -> anyhow::Result<super::A
     let mut version_string = String::new();
     let mut code_comment = String::new();
     let mut first = true;
+    let mut defaulted = false;
     for l in code_str.lines() {
         if !l.starts_with(code_start) {
             break;
@@ -87,6 +92,7 @@ fn code_to_archive_from_text(code: util::SharedBytes) -> anyhow::Result<super::A
             } else {
                 log_warn!(default = public_abi::ABSENT_VERSION; "runner comment does not start with version, using default");
                 version_string.push_str(public_abi::ABSENT_VERSION);
+                defaulted = true;
 
                 code_comment.push_str(l)
             }
@@ -95,9 +101,11 @@ fn code_to_archive_from_text(code: util::SharedBytes) -> anyhow::Result<super::A
         }
     }
 
-    Ok(super::Archive::from_file_and_runner(
+    let arch = super::Archive::from_file_and_runner(
         code,
         util::SharedBytes::from(version_string.as_bytes()),
         util::SharedBytes::from(code_comment.as_bytes()),
-    ))
+    );
+
+    Ok((arch, defaulted))
 }
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 
+use anyhow::Context as _;
 use genvm_common::*;
 
 pub struct Archive {
@@ -18,20 +19,264 @@ where
     }
 }
 
-fn trim_zeroes(x: &[u8]) -> &[u8] {
-    let mut idx = x.len() - 1;
-    while idx > 0 && x[idx - 1] == 0 {
-        idx -= 1;
+const BLOCK_SIZE: usize = 512;
+
+/// Limit of symbolic links followed while resolving a single path
+const MAX_LINK_DEPTH: usize = 32;
+/// Limit of entries that can be created by symbolic links to directories
+const MAX_LINKED_ENTRIES: usize = 1 << 16;
+
+/// Returns bytes up to the first zero
+fn c_str(x: &[u8]) -> &[u8] {
+    match x.iter().position(|c| *c == 0) {
+        Some(idx) => &x[..idx],
+        None => x,
+    }
+}
+
+/// Parses octal numeric field or GNU base-256 one
+fn parse_number(field: &[u8]) -> anyhow::Result<u64> {
+    if let Some(first) = field.first().filter(|x| *x & 0x80 != 0) {
+        if first & 0x40 != 0 {
+            anyhow::bail!("negative base-256 number");
+        }
+
+        let mut res = (first & 0x3f) as u64;
+        for c in &field[1..] {
+            res = res
+                .checked_mul(256)
+                .and_then(|x| x.checked_add(*c as u64))
+                .ok_or_else(|| anyhow::anyhow!("base-256 number overflow"))?;
+        }
+        return Ok(res);
+    }
+
+    let digits = c_str(field);
+    let digits = digits.trim_ascii();
+
+    let mut res = 0_u64;
+    for c in digits.iter().cloned() {
+        if !(b'0'..=b'7').contains(&c) {
+            anyhow::bail!("invalid octal ascii {}", c)
+        }
+        res = res
+            .checked_mul(8)
+            .and_then(|x| x.checked_add((c - b'0') as u64))
+            .ok_or_else(|| anyhow::anyhow!("octal number overflow"))?;
+    }
+
+    Ok(res)
+}
+
+fn verify_checksum(header: &[u8]) -> anyhow::Result<()> {
+    let expected = parse_number(&header[148..156])?;
+
+    let mut unsigned = 0_u64;
+    let mut signed = 0_i64;
+    for (i, c) in header.iter().cloned().enumerate() {
+        let c = if (148..156).contains(&i) { b' ' } else { c };
+        unsigned += c as u64;
+        signed += c as i8 as i64;
+    }
+
+    // some historic implementations used signed bytes
+    if expected != unsigned && expected as i64 != signed {
+        anyhow::bail!("header checksum mismatch")
+    }
+
+    Ok(())
+}
+
+/// Checks that name is relative and has neither `.` nor `..` components
+fn validate_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() {
+        anyhow::bail!("empty entry name");
+    }
+
+    if name.starts_with('/') {
+        anyhow::bail!("absolute entry name {name}");
     }
 
-    &x[0..idx]
+    if name
+        .split('/')
+        .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        anyhow::bail!("entry name {name} is not normalized");
+    }
+
+    Ok(())
+}
+
+/// Resolves symbolic link target relative to the directory of the link, result is a path from the archive root
+fn link_target(link: &str, target: &str) -> anyhow::Result<String> {
+    if target.starts_with('/') {
+        anyhow::bail!("symbolic link {link} points to absolute path {target}");
+    }
+
+    let mut components: Vec<&str> = link.split('/').collect();
+    components.pop();
+
+    for c in target.split('/') {
+        match c {
+            "" | "." => {}
+            ".." => {
+                if components.pop().is_none() {
+                    anyhow::bail!("symbolic link {link} points outside of the archive");
+                }
+            }
+            c => components.push(c),
+        }
+    }
+
+    if components.is_empty() {
+        anyhow::bail!("symbolic link {link} points to the archive root");
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Replaces all symbolic links in components of `path` with their targets
+fn canonicalize(path: &str, links: &BTreeMap<String, String>) -> anyhow::Result<String> {
+    let mut cur = path.to_owned();
+
+    for _ in 0..MAX_LINK_DEPTH {
+        let found = cur
+            .match_indices('/')
+            .map(|(i, _)| i)
+            .chain(std::iter::once(cur.len()))
+            .find_map(|i| links.get(&cur[..i]).map(|target| (i, target)));
+
+        let Some((i, target)) = found else {
+            return Ok(cur);
+        };
+
+        cur = format!("{}{}", target, &cur[i..]);
+    }
+
+    anyhow::bail!("too many levels of symbolic links in {path}")
+}
+
+/// Adds entries for symbolic links, links to directories expose all files of the directory
+fn resolve_links(
+    files: &mut BTreeMap<String, util::SharedBytes>,
+    links: &BTreeMap<String, String>,
+) -> anyhow::Result<()> {
+    let mut targets = Vec::with_capacity(links.len());
+    for link in links.keys() {
+        let target = canonicalize(link, links)?;
+
+        let location = match link.rfind('/') {
+            Some(idx) => canonicalize(&link[..idx], links)? + &link[idx..],
+            None => link.clone(),
+        };
+        if location == target || location.starts_with(&format!("{target}/")) {
+            anyhow::bail!("symbolic link {link} points to its ancestor");
+        }
+
+        targets.push((link, target));
+    }
+
+    let mut added = 0;
+    loop {
+        let mut new_entries = Vec::new();
+
+        for (link, target) in &targets {
+            if let Some(contents) = files.get(target) {
+                if !files.contains_key(*link) {
+                    new_entries.push(((*link).clone(), contents.clone()));
+                }
+                continue;
+            }
+
+            let dir_prefix = format!("{target}/");
+            for (name, contents) in files
+                .range(dir_prefix.clone()..)
+                .take_while(|(name, _)| name.starts_with(&dir_prefix))
+            {
+                let new_name = format!("{link}/{}", &name[dir_prefix.len()..]);
+                if !files.contains_key(&new_name) {
+                    new_entries.push((new_name, contents.clone()));
+                }
+            }
+        }
+
+        if new_entries.is_empty() {
+            break;
+        }
+
+        added += new_entries.len();
+        if added > MAX_LINKED_ENTRIES {
+            anyhow::bail!("symbolic links create too many entries");
+        }
+
+        files.extend(new_entries);
+    }
+
+    for (link, _) in &targets {
+        let dir_prefix = format!("{link}/");
+        let has_dir_entries = files
+            .range(dir_prefix.clone()..)
+            .next()
+            .is_some_and(|(name, _)| name.starts_with(&dir_prefix));
+
+        if !files.contains_key(*link) && !has_dir_entries {
+            anyhow::bail!("dangling symbolic link {link}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses records of PAX extended header: `<length> <key>=<value>\n`
+fn parse_pax(mut data: &[u8], into: &mut BTreeMap<String, Vec<u8>>) -> anyhow::Result<()> {
+    while !data.is_empty() {
+        let Some(space) = data.iter().position(|c| *c == b' ') else {
+            anyhow::bail!("malformed pax record");
+        };
+
+        let len: usize = std::str::from_utf8(&data[..space])?.parse()?;
+        if len <= space + 1 || len > data.len() || data[len - 1] != b'\n' {
+            anyhow::bail!("malformed pax record length {len}");
+        }
+
+        let record = &data[space + 1..len - 1];
+        let Some(eq) = record.iter().position(|c| *c == b'=') else {
+            anyhow::bail!("pax record without `=`");
+        };
+
+        let key = String::from_utf8(record[..eq].to_vec())?;
+        into.insert(key, record[eq + 1..].to_vec());
+
+        data = &data[len..];
+    }
+
+    Ok(())
+}
+
+/// Metadata of extension entries that applies to the next entry
+#[derive(Default)]
+struct Pending {
+    pax: BTreeMap<String, Vec<u8>>,
+    long_name: Option<Vec<u8>>,
+    long_link: Option<Vec<u8>>,
+}
+
+impl Pending {
+    fn pax_value<'a>(
+        &'a self,
+        global: &'a BTreeMap<String, Vec<u8>>,
+        key: &str,
+    ) -> Option<&'a [u8]> {
+        self.pax
+            .get(key)
+            .or_else(|| global.get(key))
+            .map(|x| x.as_slice())
+            .filter(|x| !x.is_empty())
+    }
 }
 
 impl Archive {
     pub fn from_ustar(original_data: util::SharedBytes) -> anyhow::Result<Self> {
-        const BLOCK_SIZE: usize = 512;
-        const _RECORD_SIZE: usize = BLOCK_SIZE * 20;
-
         if original_data.len() < BLOCK_SIZE * 2 {
             anyhow::bail!("archive is too short for tar")
         }
@@ -40,12 +285,23 @@ impl Archive {
             anyhow::bail!("tar len % 512 != 0")
         }
 
+        if original_data.len() > u32::MAX as usize {
+            anyhow::bail!("archive is too large")
+        }
+
         let mut res = BTreeMap::new();
+        let mut links = BTreeMap::new();
+
+        let mut global_pax = BTreeMap::new();
+        let mut pending = Pending::default();
+
+        let mut terminated = false;
 
         let mut begin = 0;
         while begin + 2 * BLOCK_SIZE <= original_data.len() {
             let data = original_data.slice(begin, original_data.len());
             let header = data.slice(0, BLOCK_SIZE);
+            let header = header.as_ref();
 
             if data
                 .slice(0, BLOCK_SIZE * 2)
@@ -53,12 +309,14 @@ impl Archive {
                 .iter()
                 .all(|x| *x == 0)
             {
+                terminated = true;
                 break;
             }
 
-            let header_signature = &header.as_ref()[257..265];
+            let header_signature = &header[257..265];
 
-            if header_signature != b"ustar\x0000" {
+            let is_posix = header_signature == b"ustar\x0000";
+            if !is_posix && header_signature != b"ustar  \x00" {
                 anyhow::bail!(
                     "invalid ustar header={:?}; offset={}",
                     header_signature,
@@ -66,43 +324,127 @@ impl Archive {
                 )
             }
 
-            let file_size_octal = trim_zeroes(&header.as_ref()[124..136]);
+            verify_checksum(header).with_context(|| format!("offset={begin}"))?;
 
-            let link_indicator = header.as_ref()[156];
-            if ![b'0', b'\x00', b'5'].contains(&link_indicator) {
-                anyhow::bail!("links are forbidden")
-            }
+            let entry_type = header[156];
 
-            let path_and_name = trim_zeroes(&header.as_ref()[0..100]);
-            let path_and_name_prefix = trim_zeroes(&header.as_ref()[345..345 + 155]);
+            let file_size = match pending.pax_value(&global_pax, "size") {
+                Some(size) => std::str::from_utf8(size)?.parse()?,
+                None => parse_number(&header[124..136])?,
+            };
+            let file_size = usize::try_from(file_size)?;
 
-            begin += BLOCK_SIZE;
+            if file_size > data.len() - BLOCK_SIZE {
+                anyhow::bail!("entry at offset={} exceeds archive", begin)
+            }
 
-            let mut name_vec = Vec::from(path_and_name_prefix);
-            name_vec.extend_from_slice(path_and_name);
+            let file_contents = data.slice(BLOCK_SIZE, BLOCK_SIZE + file_size);
 
-            let name = String::from_utf8(name_vec)?;
+            begin += BLOCK_SIZE + file_size;
+            begin += (BLOCK_SIZE - (begin % BLOCK_SIZE)) % BLOCK_SIZE;
 
-            if name.ends_with("/") {
-                continue;
+            match entry_type {
+                b'x' => {
+                    parse_pax(file_contents.as_ref(), &mut pending.pax)?;
+                    continue;
+                }
+                b'g' => {
+                    parse_pax(file_contents.as_ref(), &mut global_pax)?;
+                    continue;
+                }
+                b'L' => {
+                    pending.long_name = Some(c_str(file_contents.as_ref()).to_vec());
+                    continue;
+                }
+                b'K' => {
+                    pending.long_link = Some(c_str(file_contents.as_ref()).to_vec());
+                    continue;
+                }
+                _ => {}
             }
 
-            let mut file_size = 0_usize;
-            for c in file_size_octal.iter().cloned() {
-                if !(b'0'..=b'7').contains(&c) {
-                    anyhow::bail!("invalid octal ascii {}", c)
+            let name = if let Some(path) = pending.pax_value(&global_pax, "path") {
+                path.to_vec()
+            } else if let Some(long_name) = pending.long_name.take() {
+                long_name
+            } else {
+                let name = c_str(&header[0..100]);
+                // prefix field is present only in POSIX headers
+                let prefix = if is_posix {
+                    c_str(&header[345..345 + 155])
+                } else {
+                    &[]
+                };
+
+                let mut name_vec = Vec::from(prefix);
+                if !prefix.is_empty() {
+                    name_vec.push(b'/');
+                }
+                name_vec.extend_from_slice(name);
+                name_vec
+            };
+
+            let link_name = if let Some(path) = pending.pax_value(&global_pax, "linkpath") {
+                path.to_vec()
+            } else if let Some(long_link) = pending.long_link.take() {
+                long_link
+            } else {
+                c_str(&header[157..257]).to_vec()
+            };
+
+            pending = Pending::default();
+
+            let name = String::from_utf8(name)?;
+
+            match entry_type {
+                b'0' | b'\x00' | b'7' => {
+                    if name.ends_with("/") {
+                        continue;
+                    }
+
+                    validate_name(&name)?;
+                    if links.contains_key(&name) {
+                        anyhow::bail!("entry {} is already occupied", name);
+                    }
+                    map_try_insert(&mut res, name, file_contents)?;
+                }
+                b'5' => {}
+                b'1' | b'2' => {
+                    let name = name.trim_end_matches('/').to_owned();
+                    validate_name(&name)?;
+
+                    let link_name = String::from_utf8(link_name)?;
+                    let target = if entry_type == b'1' {
+                        // hard links are relative to the archive root
+                        validate_name(&link_name)?;
+                        link_name
+                    } else {
+                        link_target(&name, &link_name)?
+                    };
+
+                    if res.contains_key(&name) {
+                        anyhow::bail!("entry {} is already occupied", name);
+                    }
+                    map_try_insert(&mut links, name, target)?;
+                }
+                b'3' | b'4' | b'6' => {
+                    anyhow::bail!("device and fifo entries are not supported: {}", name)
                 }
-                file_size = file_size * 8 + (c - b'0') as usize;
+                b'S' => anyhow::bail!("sparse entries are not supported: {}", name),
+                other => anyhow::bail!("unsupported entry type {:?}: {}", other as char, name),
             }
+        }
 
-            begin += file_size;
-            begin += (BLOCK_SIZE - (begin % BLOCK_SIZE)) % BLOCK_SIZE;
-
-            let file_contents = data.slice(BLOCK_SIZE, BLOCK_SIZE + file_size);
+        if !terminated {
+            anyhow::bail!("archive is truncated")
+        }
 
-            map_try_insert(&mut res, name, file_contents)?;
+        if !pending.pax.is_empty() || pending.long_name.is_some() || pending.long_link.is_some() {
+            anyhow::bail!("extended header is not followed by an entry")
         }
 
+        resolve_links(&mut res, &links)?;
+
         Ok(Self {
             data: res,
             total_size: original_data.len() as u32,
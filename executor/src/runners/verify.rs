@@ -0,0 +1,256 @@
+use anyhow::Context as _;
+use sha2::Digest as _;
+
+use genvm_common::*;
+
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Converts a digest to a runner hash, see `support/runner-script.py`
+pub fn digest_to_hash_id(digest: &[u8]) -> String {
+    const CHARS: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+    let bytes_count = digest.len();
+    if bytes_count == 0 {
+        return String::new();
+    }
+
+    let base32_len = (bytes_count * 8 - 1) / 5 + 1;
+
+    let mut res = String::with_capacity(base32_len);
+    for n in (0..base32_len).rev() {
+        let b = n * 5;
+        let i = b / 8;
+        let j = b % 8;
+
+        let mut c = (digest[i] as u32) >> j;
+        if i < bytes_count - 1 {
+            c |= (digest[i + 1] as u32) << (8 - j);
+        }
+        res.push(CHARS[(c & 0x1f) as usize] as char);
+    }
+
+    res
+}
+
+pub fn parse_signer(key: &str) -> anyhow::Result<ed25519_dalek::VerifyingKey> {
+    let bytes: [u8; ed25519_dalek::PUBLIC_KEY_LENGTH] = hex::decode(key)
+        .with_context(|| format!("decoding signer key {key}"))?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("signer key {key} has invalid length"))?;
+
+    ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+        .with_context(|| format!("signer key {key} is not a valid ed25519 key"))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: std::time::SystemTime,
+}
+
+impl FileStamp {
+    fn of(path: &std::path::Path) -> anyhow::Result<Self> {
+        let meta = std::fs::metadata(path).with_context(|| format!("reading metadata {path:?}"))?;
+
+        Ok(Self {
+            len: meta.len(),
+            modified: meta.modified()?,
+        })
+    }
+}
+
+/// Checks that runner archives match their hash and are signed by a trusted signer.
+/// Results are remembered until the archive file changes
+pub struct Verifier {
+    trusted_signers: Vec<ed25519_dalek::VerifyingKey>,
+    verified: dashmap::DashMap<symbol_table::GlobalSymbol, FileStamp>,
+}
+
+impl Verifier {
+    pub fn new(config: &crate::config::RunnerVerification) -> anyhow::Result<Self> {
+        let trusted_signers = config
+            .trusted_signers
+            .iter()
+            .map(|x| parse_signer(x))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            trusted_signers,
+            verified: dashmap::DashMap::new(),
+        })
+    }
+
+    /// `data` must be uncompressed contents of the archive at `path`
+    pub fn verify(
+        &self,
+        uid: symbol_table::GlobalSymbol,
+        hash: &str,
+        path: &std::path::Path,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        let stamp = FileStamp::of(path)?;
+
+        if self.verified.get(&uid).is_some_and(|x| *x == stamp) {
+            return Ok(());
+        }
+
+        let digest = sha2::Sha256::digest(data);
+        let actual_hash = digest_to_hash_id(&digest);
+        if actual_hash != hash {
+            anyhow::bail!("runner {uid} content hash mismatch, actual hash is {actual_hash}");
+        }
+
+        if !self.trusted_signers.is_empty() {
+            self.verify_signature(uid, path, &digest)?;
+        }
+
+        log_debug!(runner = uid; "runner verified");
+
+        self.verified.insert(uid, stamp);
+
+        Ok(())
+    }
+
+    /// Signature file consists of lines `<hex public key> <hex signature of sha256 digest>`
+    fn verify_signature(
+        &self,
+        uid: symbol_table::GlobalSymbol,
+        path: &std::path::Path,
+        digest: &[u8],
+    ) -> anyhow::Result<()> {
+        // signature is made for the uncompressed archive
+        let file_name = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or_default();
+        let base = file_name.split_once('.').map_or(file_name, |x| x.0);
+        let sig_path = path.with_file_name(format!(
+            "{base}.{}.{SIGNATURE_EXTENSION}",
+            super::ArchiveFormat::Tar.extension(),
+        ));
+
+        let contents = std::fs::read_to_string(&sig_path)
+            .with_context(|| format!("reading signatures of runner {uid}"))?;
+
+        for line in contents.lines().map(str::trim).filter(|x| !x.is_empty()) {
+            // lines of other signers must not prevent verification with trusted ones
+            let Some((key, signature)) = line.split_once(' ') else {
+                log_warn!(runner = uid, line = line; "malformed signature line");
+                continue;
+            };
+
+            let key = match parse_signer(key.trim()) {
+                Ok(key) => key,
+                Err(e) => {
+                    log_warn!(error:ah = e, runner = uid; "skipping signature line");
+                    continue;
+                }
+            };
+            if !self.trusted_signers.contains(&key) {
+                continue;
+            }
+
+            let Some(signature) = hex::decode(signature.trim())
+                .ok()
+                .and_then(|x| ed25519_dalek::Signature::from_slice(&x).ok())
+            else {
+                log_warn!(runner = uid, signer = hex::encode(key.as_bytes()); "malformed runner signature");
+                continue;
+            };
+
+            if key.verify_strict(digest, &signature).is_ok() {
+                return Ok(());
+            }
+
+            log_warn!(runner = uid, signer = hex::encode(key.as_bytes()); "invalid runner signature");
+        }
+
+        anyhow::bail!("runner {uid} is not signed by any trusted signer")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signer as _;
+
+    const DATA: &[u8] = b"runner archive";
+
+    fn signing_key(seed: u8) -> ed25519_dalek::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&[seed; ed25519_dalek::SECRET_KEY_LENGTH])
+    }
+
+    fn signature_line(key: &ed25519_dalek::SigningKey) -> String {
+        let digest = sha2::Sha256::digest(DATA);
+        format!(
+            "{} {}",
+            hex::encode(key.verifying_key().as_bytes()),
+            hex::encode(key.sign(&digest).to_bytes())
+        )
+    }
+
+    /// verifies [`DATA`] signed with `signatures` against signer of seed `0`
+    fn verify_signed(name: &str, signatures: &[String]) -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("genvm-verify-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(format!("runner.tar.{SIGNATURE_EXTENSION}")),
+            signatures.join("\n"),
+        )
+        .unwrap();
+
+        let verifier = Verifier {
+            trusted_signers: vec![signing_key(0).verifying_key()],
+            verified: dashmap::DashMap::new(),
+        };
+        let res = verifier.verify_signature(
+            symbol_table::GlobalSymbol::new("runner"),
+            &dir.join("runner.tar.zst"),
+            &sha2::Sha256::digest(DATA),
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        res
+    }
+
+    #[test]
+    fn trusted_signature() {
+        verify_signed("trusted", &[signature_line(&signing_key(0))]).unwrap();
+    }
+
+    #[test]
+    fn untrusted_signature() {
+        assert!(verify_signed("untrusted", &[signature_line(&signing_key(1))]).is_err());
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        verify_signed(
+            "malformed",
+            &[
+                "garbage".to_owned(),
+                "nothex 00".to_owned(),
+                format!(
+                    "{} 00",
+                    hex::encode(signing_key(0).verifying_key().as_bytes())
+                ),
+                signature_line(&signing_key(1)),
+                signature_line(&signing_key(0)),
+            ],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn hash_id_matches_runner_script() {
+        assert_eq!(
+            digest_to_hash_id(&sha2::Sha256::digest(b"")),
+            "0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73"
+        );
+        assert_eq!(
+            digest_to_hash_id(&sha2::Sha256::digest(b"genvm")),
+            "1rsl128v59rvjq5g3s1qh6vw745zbii7afdscydf2ll72g1scx1s"
+        );
+    }
+}
@@ -113,11 +113,31 @@ pub struct SingleVMData {
     pub storage: rt::vm::storage::Storage<StorageHostHolder>,
     pub should_capture_fp: Arc<std::sync::atomic::AtomicBool>,
     pub events: Vec<Vec<bytes::Bytes>>,
+    /// cancellation of this VM, it is either shared with the supervisor or derived from the parent VM one
+    pub cancellation: Arc<cancellation::Token>,
+    /// storages of other contracts that were modified by successful write calls
+    pub foreign_storages: BTreeMap<calldata::Address, rt::vm::storage::Storage<StorageHostHolder>>,
+    /// messages that are not sent to the host yet, see `buffer_messages`
+    pub messages: Vec<rt::vm::PendingMessage>,
+    /// whenever messages of this VM must be delayed till the end of the transaction, which is the case for write calls
+    pub buffer_messages: bool,
+    /// seed of deterministic `random_get`, `None` if host did not provide entropy, see [`rt::random`]
+    pub random_seed: Option<rt::random::Seed>,
+    /// number of VMs this one is nested in, it is zero for the entrypoint
+    pub depth: u32,
 }
 
 pub struct Context {
     pub data: SingleVMData,
     pub messages_decremented: primitive_types::U256,
+    /// deterministic VMs spawned by this one, used to derive their random seeds
+    pub spawned_det_vms: u32,
+    /// set only if debugger is enabled, see [`rt::debugger`]
+    pub debug_thread: Option<rt::debugger::Thread>,
+    /// sections marked by the guest, see [`rt::spans`]
+    pub spans: rt::spans::Tree,
+    /// set while runner initialization function is called, see [`super::Context::begin_initialization`]
+    pub(super) initializing: bool,
 
     pub start_time: std::time::Instant,
     pub prev_time: std::time::Instant,
@@ -187,6 +207,17 @@ impl SlotID {
     }
 }
 
+fn check_limits(
+    limits: &rt::limits::SubVMLimits,
+    is_deterministic: bool,
+) -> Result<(), generated::types::Error> {
+    limits.validate(is_deterministic).map_err(|e| {
+        log_info!(error:ah = e, limits:? = limits; "sub-VM limits rejected");
+
+        generated::types::Errno::Inval.into()
+    })
+}
+
 fn read_owned_vec(
     mem: &mut wiggle::GuestMemory<'_>,
     ptr: wiggle::GuestPtr<[u8]>,
@@ -201,10 +232,46 @@ impl Context {
         Self {
             data,
             messages_decremented: primitive_types::U256::zero(),
+            spawned_det_vms: 0,
+            debug_thread: None,
+            spans: rt::spans::Tree::new(),
+            initializing: false,
             start_time: now,
             prev_time: now,
         }
     }
+
+    /// Nesting depth of a VM spawned by this one. Sub-VMs of all kinds hold a pool slot while their parent holds one,
+    /// so they are limited by the same [`public_abi::MAX_VM_DEPTH`]
+    fn child_depth(&self) -> Result<u32, generated::types::Error> {
+        let depth = self.data.depth + 1;
+        if depth > public_abi::MAX_VM_DEPTH {
+            log_warn!(depth = depth, max = public_abi::MAX_VM_DEPTH; "VM depth exceeded");
+
+            return Err(generated::types::Error::trap(
+                rt::errors::VMError(
+                    public_abi::VmError::CallDepthExceeded.value().to_owned(),
+                    None,
+                )
+                .into(),
+            ));
+        }
+
+        Ok(depth)
+    }
+
+    fn child_random_seed(
+        &mut self,
+        address: calldata::Address,
+        entry_kind: public_abi::EntryKind,
+    ) -> Option<rt::random::Seed> {
+        let call_no = self.spawned_det_vms;
+        self.spawned_det_vms += 1;
+
+        self.data
+            .random_seed
+            .map(|seed| rt::random::child_seed(&seed, call_no, address, entry_kind))
+    }
 }
 
 impl wiggle::GuestErrorType for generated::types::Errno {
@@ -294,6 +361,17 @@ impl From<serde_json::Error> for generated::types::Error {
 }
 
 impl ContextVFS<'_> {
+    /// see [`super::Context::begin_initialization`]
+    fn forbid_during_init(&self, call: &str) -> Result<(), generated::types::Error> {
+        if self.context.initializing {
+            return Err(generated::types::Error::trap(anyhow::anyhow!(
+                "`{call}` is forbidden during runner initialization"
+            )));
+        }
+
+        Ok(())
+    }
+
     fn set_vm_run_result(
         &mut self,
         data: rt::vm::RunOk,
@@ -347,6 +425,20 @@ where
     }
 }
 
+fn storage_event(
+    account: calldata::Address,
+    slot: SlotID,
+    index: u32,
+    data: &[u8],
+) -> serde_json::Value {
+    serde_json::json!({
+        "account": format!("{account}"),
+        "slot": format!("0x{}", hex::encode(slot.0)),
+        "index": index,
+        "data": format!("0x{}", hex::encode(data)),
+    })
+}
+
 const NO_FILE: u32 = u32::MAX;
 
 #[inline]
@@ -363,6 +455,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         request: wiggle::GuestPtr<u8>,
         request_len: u32,
     ) -> Result<generated::types::Fd, generated::types::Error> {
+        self.forbid_during_init("gl_call")?;
+
         let request = request.as_array(request_len);
         let request = read_owned_vec(mem, request)?;
 
@@ -377,6 +471,16 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
 
         log_trace!(request:serde = request; "gl_call");
 
+        if let Some(thread) = &self.context.debug_thread {
+            thread
+                .hook(
+                    rt::debugger::gl_call_kind(&request),
+                    || rt::debugger::calldata_to_json(&request),
+                    &self.context.data.cancellation,
+                )
+                .await;
+        }
+
         let request: gl_call::Message = match calldata::from_value(request) {
             Ok(v) => v,
             Err(e) => {
@@ -386,6 +490,10 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
             }
         };
 
+        if !matches!(request, gl_call::Message::Trace(_)) {
+            self.context.spans.record_gl_call();
+        }
+
         match request {
             gl_call::Message::EthSend {
                 address,
@@ -455,6 +563,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 address,
                 calldata,
                 mut state,
+                allow_write_ops,
+                limits,
             } => {
                 if !self.context.data.conf.is_deterministic {
                     return Err(generated::types::Errno::Forbidden.into());
@@ -462,11 +572,23 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 if !self.context.data.conf.can_call_others {
                     return Err(generated::types::Errno::Forbidden.into());
                 }
+                check_limits(&limits, true)?;
 
                 if state == public_abi::StorageType::Default {
                     state = public_abi::StorageType::LatestNonFinal;
                 }
 
+                if allow_write_ops {
+                    if !self.context.data.conf.can_write_storage {
+                        return Err(generated::types::Errno::Forbidden.into());
+                    }
+                    if state != public_abi::StorageType::LatestNonFinal {
+                        log_warn!(state:? = state; "write call must read latest non-final state");
+
+                        return Err(generated::types::Errno::Inval.into());
+                    }
+                }
+
                 let supervisor = self.context.data.supervisor.clone();
 
                 let my_conf = self.context.data.conf;
@@ -480,14 +602,38 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                     .fork(public_abi::EntryKind::Main, calldata_encoded);
                 my_data.stack.push(my_data.contract_address);
 
+                let depth = self.context.child_depth()?;
+
                 let calldata_encoded = calldata::encode(&calldata);
 
+                let (storage, foreign_storages, write_limiter) = if allow_write_ops {
+                    let (storage, foreign_storages, limiter) =
+                        self.context.storages_for_write_call(address);
+                    (storage, foreign_storages, Some(limiter))
+                } else {
+                    (
+                        rt::vm::storage::Storage::new(
+                            address,
+                            self.context.data.storage.limiter().clone(),
+                            StorageHostHolder(
+                                supervisor.host.clone(),
+                                ReadToken {
+                                    account: address,
+                                    mode: state,
+                                },
+                            ),
+                        ),
+                        BTreeMap::new(),
+                        None,
+                    )
+                };
+
                 let vm_data = SingleVMData {
                     conf: base::Config {
                         needs_error_fingerprint: true,
                         is_deterministic: true,
                         can_read_storage: my_conf.can_read_storage,
-                        can_write_storage: false,
+                        can_write_storage: allow_write_ops,
                         can_spawn_nondet: my_conf.can_spawn_nondet,
                         can_call_others: my_conf.can_call_others,
                         can_send_messages: my_conf.can_send_messages,
@@ -506,27 +652,41 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                         entry_stage_data: default_entry_stage_data(),
                         stack: my_data.stack,
                     },
-                    storage: rt::vm::storage::Storage::new(
-                        address,
-                        supervisor.get_storage_limiter(),
-                        StorageHostHolder(
-                            supervisor.host.clone(),
-                            ReadToken {
-                                account: address,
-                                mode: state,
-                            },
-                        ),
-                    ),
+                    storage,
                     supervisor: supervisor.clone(),
                     should_capture_fp: Arc::new(std::sync::atomic::AtomicBool::new(true)),
                     events: Vec::new(),
+                    cancellation: self.context.data.cancellation.clone(),
+                    foreign_storages,
+                    messages: Vec::new(),
+                    buffer_messages: allow_write_ops,
+                    random_seed: self.context.child_random_seed(address, my_data.entry_kind),
+                    depth,
                 };
 
-                let res = self
+                let run_res = self
                     .context
-                    .spawn_and_run(&supervisor, vm_data)
-                    .await
-                    .map_err(generated::types::Error::trap)?;
+                    .spawn_and_run(&supervisor, vm_data, &limits)
+                    .await;
+
+                let (res, callee_data) = match run_res {
+                    Ok(v) => v,
+                    Err(e) => {
+                        if let Some(write_limiter) = write_limiter {
+                            write_limiter.release_tracked();
+                        }
+                        return Err(generated::types::Error::trap(e));
+                    }
+                };
+
+                if let Some(write_limiter) = write_limiter {
+                    if let (rt::vm::RunOk::Return(_), Some(callee_data)) = (&res, callee_data) {
+                        self.context.merge_write_call(callee_data);
+                    } else {
+                        // writes of failed callee are discarded, so are pages they consumed
+                        write_limiter.release_tracked();
+                    }
+                }
 
                 self.set_vm_run_result(res).map(|x| x.0)
             }
@@ -557,12 +717,12 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
 
                 let blob_data = calldata::encode(&calldata::Value::Map(blob));
 
-                let supervisor = self.context.data.supervisor.clone();
-
                 let size = topics.len() + (blob_data.len() + 31) / 32;
                 let size = size as u64;
-                supervisor
-                    .get_storage_limiter()
+                self.context
+                    .data
+                    .storage
+                    .limiter()
                     .consume(size)
                     .map_err(generated::types::Error::trap)?;
 
@@ -603,15 +763,26 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 });
                 let data_str = serde_json::to_string(&data_json).unwrap();
 
-                let res = self
-                    .context
-                    .data
-                    .supervisor
-                    .host
-                    .lock()
-                    .await
-                    .post_message(&address, &calldata_encoded, &data_str)
-                    .map_err(generated::types::Error::trap)?;
+                if self.context.data.buffer_messages {
+                    self.context
+                        .data
+                        .messages
+                        .push(rt::vm::PendingMessage::Post {
+                            sender: self.context.data.message_data.contract_address,
+                            address,
+                            calldata: calldata_encoded,
+                            data: data_str,
+                        });
+                } else {
+                    self.context
+                        .data
+                        .supervisor
+                        .host
+                        .lock()
+                        .await
+                        .post_message(&address, &calldata_encoded, &data_str)
+                        .map_err(generated::types::Error::trap)?;
+                }
 
                 self.context.messages_decremented += value;
 
@@ -651,15 +822,26 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 });
                 let data_str = serde_json::to_string(&data_json).unwrap();
 
-                let res = self
-                    .context
-                    .data
-                    .supervisor
-                    .host
-                    .lock()
-                    .await
-                    .deploy_contract(&calldata_encoded, &code, &data_str)
-                    .map_err(generated::types::Error::trap)?;
+                if self.context.data.buffer_messages {
+                    self.context
+                        .data
+                        .messages
+                        .push(rt::vm::PendingMessage::Deploy {
+                            sender: self.context.data.message_data.contract_address,
+                            calldata: calldata_encoded,
+                            code,
+                            data: data_str,
+                        });
+                } else {
+                    self.context
+                        .data
+                        .supervisor
+                        .host
+                        .lock()
+                        .await
+                        .deploy_contract(&calldata_encoded, &code, &data_str)
+                        .map_err(generated::types::Error::trap)?;
+                }
 
                 self.context.messages_decremented += value;
 
@@ -865,11 +1047,29 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
             gl_call::Message::RunNondet {
                 data_leader,
                 data_validator,
-            } => self.run_nondet(data_leader, data_validator).await,
+                limits,
+            } => self.run_nondet(data_leader, data_validator, limits).await,
             gl_call::Message::Sandbox {
                 data,
                 allow_write_ops,
-            } => self.sandbox(data, allow_write_ops).await,
+                limits,
+            } => self.sandbox(data, allow_write_ops, limits).await,
+            gl_call::Message::RandomSeedCommitment => {
+                let commitment = match &self.context.data.random_seed {
+                    Some(seed) => calldata::Value::Bytes(rt::random::commitment(seed).to_vec()),
+                    None => calldata::Value::Null,
+                };
+
+                Ok(generated::types::Fd::from(
+                    self.vfs
+                        .place_content(vfs::FileContents {
+                            contents: util::SharedBytes::new(calldata::encode(&commitment)),
+                            pos: 0,
+                            release_memory: true,
+                        })
+                        .map_err(generated::types::Error::trap)?,
+                ))
+            }
             gl_call::Message::Trace(message) => self.gl_call_trace(message).await,
         }
     }
@@ -882,6 +1082,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         buf: wiggle::GuestPtr<u8>,
         buf_len: u32,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_during_init("storage_read")?;
+
         let buf = buf.as_array(buf_len);
 
         if !self.context.data.conf.can_read_storage {
@@ -923,6 +1125,16 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
                 .map_err(generated::types::Error::trap)?;
         }
 
+        if let Some(thread) = &self.context.debug_thread {
+            thread
+                .hook(
+                    rt::debugger::STORAGE_READ,
+                    || storage_event(account, slot, index, &vec),
+                    &self.context.data.cancellation,
+                )
+                .await;
+        }
+
         mem.copy_from_slice(&vec, buf)?;
         Ok(())
     }
@@ -935,6 +1147,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         buf: wiggle::GuestPtr<u8>,
         buf_len: u32,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_during_init("storage_write")?;
+
         let buf = buf.as_array(buf_len);
 
         if !self.context.data.conf.is_deterministic {
@@ -956,6 +1170,23 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
 
         let ptr = mem.as_cow(buf)?;
 
+        if let Some(thread) = &self.context.debug_thread {
+            thread
+                .hook(
+                    rt::debugger::STORAGE_WRITE,
+                    || {
+                        storage_event(
+                            self.context.data.message_data.contract_address,
+                            slot,
+                            index,
+                            &ptr,
+                        )
+                    },
+                    &self.context.data.cancellation,
+                )
+                .await;
+        }
+
         self.context
             .data
             .storage
@@ -970,6 +1201,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         account: wiggle::GuestPtr<u8>,
         result: wiggle::GuestPtr<u8>,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_during_init("get_balance")?;
+
         let address = read_addr_from_mem(mem, account)?;
 
         self.context
@@ -982,6 +1215,8 @@ impl generated::genlayer_sdk::GenlayerSdk for ContextVFS<'_> {
         mem: &mut wiggle::GuestMemory<'_>,
         result: wiggle::GuestPtr<u8>,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_during_init("get_self_balance")?;
+
         if !self.context.data.conf.is_deterministic {
             return Err(generated::types::Errno::Forbidden.into());
         }
@@ -1053,7 +1288,8 @@ impl Context {
         &mut self,
         supervisor: &Arc<rt::supervisor::Supervisor>,
         essential_data: SingleVMData,
-    ) -> anyhow::Result<rt::vm::RunOk> {
+        limits: &rt::limits::SubVMLimits,
+    ) -> anyhow::Result<(rt::vm::RunOk, Option<SingleVMData>)> {
         let limiter = self
             .data
             .supervisor
@@ -1061,17 +1297,61 @@ impl Context {
             .get(essential_data.conf.is_deterministic)
             .derived();
 
-        let vm = rt::supervisor::spawn(supervisor, essential_data, limiter).await;
-        let vm = match vm {
-            Ok(vm) => rt::supervisor::apply_contract_actions(supervisor, vm).await,
-            Err(e) => Err(e),
-        };
-        let result = match vm {
-            Ok(vm) => vm.run().await,
-            Err(e) => Err(e),
-        };
+        rt::supervisor::run_limited(supervisor, essential_data, limiter, limits).await
+    }
 
-        result.map(|x| x.run_ok)
+    /// Returns storage of `address` and storages of all other contracts that callee must see.
+    /// Caller storage is included, so that reentrant calls observe its writes.
+    /// Returned limiter tracks pages consumed by the callee, to release them if it fails
+    fn storages_for_write_call(
+        &self,
+        address: calldata::Address,
+    ) -> (
+        rt::vm::storage::Storage<StorageHostHolder>,
+        BTreeMap<calldata::Address, rt::vm::storage::Storage<StorageHostHolder>>,
+        rt::vm::storage::Limiter,
+    ) {
+        let limiter = self.data.storage.limiter().tracked();
+
+        let mut foreign = self.data.foreign_storages.clone();
+        foreign.insert(self.data.storage.address, self.data.storage.clone());
+
+        let mut storage = foreign.remove(&address).unwrap_or_else(|| {
+            rt::vm::storage::Storage::new(
+                address,
+                self.data.storage.limiter().clone(),
+                StorageHostHolder(
+                    self.data.supervisor.host.clone(),
+                    ReadToken {
+                        account: address,
+                        mode: public_abi::StorageType::LatestNonFinal,
+                    },
+                ),
+            )
+        });
+        storage.set_limiter(limiter.clone());
+
+        (storage, foreign, limiter)
+    }
+
+    /// Adopts storages, events and messages of successfully finished write call
+    fn merge_write_call(&mut self, callee: SingleVMData) {
+        let limiter = self.data.storage.limiter().clone();
+
+        let mut storages = callee.foreign_storages;
+        storages.insert(callee.storage.address, callee.storage);
+
+        for storage in storages.values_mut() {
+            storage.set_limiter(limiter.clone());
+        }
+
+        if let Some(own) = storages.remove(&self.data.storage.address) {
+            self.data.storage = own;
+        }
+
+        self.data.foreign_storages = storages;
+        self.data.events.extend(callee.events);
+        self.data.messages.extend(callee.messages);
     }
 }
 
@@ -1093,6 +1373,18 @@ impl ContextVFS<'_> {
                     "trace"
                 );
 
+                if let Some(thread) = &self.context.debug_thread {
+                    thread.output(&text);
+                }
+
+                Ok(file_fd_none())
+            }
+            gl_call::TracePayload::SpanBegin { name } => {
+                self.context.spans.begin(name);
+                Ok(file_fd_none())
+            }
+            gl_call::TracePayload::SpanEnd => {
+                self.context.spans.end();
                 Ok(file_fd_none())
             }
             gl_call::TracePayload::RuntimeMicroSec => {
@@ -1125,10 +1417,13 @@ impl ContextVFS<'_> {
         &mut self,
         data_leader: Vec<u8>,
         data_validator: Vec<u8>,
+        limits: rt::limits::SubVMLimits,
     ) -> Result<generated::types::Fd, generated::types::Error> {
         if !self.context.data.conf.can_spawn_nondet {
             return Err(generated::types::Errno::Forbidden.into());
         }
+        check_limits(&limits, false)?;
+        let depth = self.context.child_depth()?;
 
         let call_no = self
             .context
@@ -1198,6 +1493,12 @@ impl ContextVFS<'_> {
                 should_capture_fp: Arc::new(std::sync::atomic::AtomicBool::new(false)),
                 storage: storage_checkpoint,
                 events: Vec::new(),
+                cancellation: self.context.data.cancellation.clone(),
+                foreign_storages: BTreeMap::new(),
+                messages: Vec::new(),
+                buffer_messages: false,
+                random_seed: None,
+                depth,
             };
 
             let task_done = Arc::new(tokio::sync::Notify::new());
@@ -1205,6 +1506,7 @@ impl ContextVFS<'_> {
                 task: vm_data,
                 call_no,
                 tasks_done: task_done.clone(),
+                limits,
             };
 
             match leaders_res {
@@ -1241,7 +1543,11 @@ impl ContextVFS<'_> {
         &mut self,
         data: Vec<u8>,
         allow_write_ops: bool,
+        limits: rt::limits::SubVMLimits,
     ) -> Result<generated::types::Fd, generated::types::Error> {
+        check_limits(&limits, self.context.data.conf.is_deterministic)?;
+        let depth = self.context.child_depth()?;
+
         let supervisor = self.context.data.supervisor.clone();
 
         let message_data = self
@@ -1250,6 +1556,15 @@ impl ContextVFS<'_> {
             .message_data
             .fork(public_abi::EntryKind::Sandbox, data);
 
+        let random_seed = if self.context.data.conf.is_deterministic {
+            self.context.child_random_seed(
+                self.context.data.message_data.contract_address,
+                public_abi::EntryKind::Sandbox,
+            )
+        } else {
+            None
+        };
+
         let zelf_conf = &self.context.data.conf;
 
         let storage_checkpoint = self.context.data.storage.clone();
@@ -1270,11 +1585,25 @@ impl ContextVFS<'_> {
             should_capture_fp: Arc::new(std::sync::atomic::AtomicBool::new(false)),
             storage: storage_checkpoint,
             events: Vec::new(),
+            cancellation: self.context.data.cancellation.clone(),
+            foreign_storages: BTreeMap::new(),
+            messages: Vec::new(),
+            buffer_messages: self.context.data.buffer_messages,
+            random_seed,
+            depth,
         };
 
-        let my_res = self.context.spawn_and_run(&supervisor, vm_data).await;
+        let my_res = self
+            .context
+            .spawn_and_run(&supervisor, vm_data, &limits)
+            .await;
         let my_res = match my_res {
-            Ok(res) => Ok(res),
+            Ok((res, sandbox_data)) => {
+                if let (rt::vm::RunOk::Return(_), Some(sandbox_data)) = (&res, sandbox_data) {
+                    self.context.data.messages.extend(sandbox_data.messages);
+                }
+                Ok(res)
+            }
             Err(e) => rt::errors::unwrap_vm_errors(e),
         }
         .map_err(generated::types::Error::trap)?;
@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{calldata, public_abi};
+use crate::{calldata, public_abi, rt};
 
 #[derive(Clone, Deserialize, Serialize, Copy, PartialEq, Eq, Debug)]
 pub enum On {
@@ -49,8 +49,35 @@ where
 pub enum TracePayload {
     Message(String),
     RuntimeMicroSec,
+    /// opens a named section of [`rt::spans::Tree`], nothing is returned to the guest
+    SpanBegin {
+        name: String,
+    },
+    /// closes innermost section opened by [`TracePayload::SpanBegin`]
+    SpanEnd,
 }
 
+/// Names of [`Message`] variants, which are used as breakpoints by [`rt::debugger`],
+/// kept in sync with the enum by a test
+pub const MESSAGE_KINDS: [&str; 16] = [
+    "EthSend",
+    "EthCall",
+    "CallContract",
+    "PostMessage",
+    "DeployContract",
+    "RunNondet",
+    "Sandbox",
+    "WebRender",
+    "WebRequest",
+    "ExecPrompt",
+    "ExecPromptTemplate",
+    "Rollback",
+    "Return",
+    "EmitEvent",
+    "RandomSeedCommitment",
+    "Trace",
+];
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -71,6 +98,10 @@ pub enum Message {
         calldata: calldata::Value,
         #[serde(deserialize_with = "storage_type_from_bigint")]
         state: public_abi::StorageType,
+        #[serde(default)]
+        allow_write_ops: bool,
+        #[serde(default)]
+        limits: rt::limits::SubVMLimits,
     },
     PostMessage {
         address: calldata::Address,
@@ -92,6 +123,8 @@ pub enum Message {
         data_leader: Vec<u8>,
         #[serde(with = "serde_bytes")]
         data_validator: Vec<u8>,
+        #[serde(default)]
+        limits: rt::limits::SubVMLimits,
     },
 
     Sandbox {
@@ -99,6 +132,8 @@ pub enum Message {
         data: Vec<u8>,
 
         allow_write_ops: bool,
+        #[serde(default)]
+        limits: rt::limits::SubVMLimits,
     },
 
     WebRender(genvm_modules_interfaces::web::RenderPayload),
@@ -114,9 +149,54 @@ pub enum Message {
         blob: BTreeMap<String, calldata::Value>,
     },
 
+    /// commitment to the seed of deterministic `random_get`, see [`crate::rt::random::commitment`]
+    RandomSeedCommitment,
+
     Trace(TracePayload),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(transparent)]
 pub struct Bytes(#[serde(with = "serde_bytes")] pub Vec<u8>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records variant names that serde derived for an enum and fails
+    struct VariantNames(Option<&'static [&'static str]>);
+
+    impl<'de> serde::Deserializer<'de> for &mut VariantNames {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not an enum"))
+        }
+
+        fn deserialize_enum<V: serde::de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            variants: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            self.0 = Some(variants);
+            Err(serde::de::Error::custom("variants recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map struct identifier ignored_any
+        }
+    }
+
+    #[test]
+    fn message_kinds_match_enum() {
+        let mut names = VariantNames(None);
+        assert!(Message::deserialize(&mut names).is_err());
+        assert_eq!(names.0, Some(&MESSAGE_KINDS[..]));
+    }
+}
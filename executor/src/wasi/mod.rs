@@ -1,3 +1,5 @@
+use std::sync::{Arc, Mutex};
+
 use crate::{calldata, rt};
 
 pub mod base;
@@ -6,7 +8,7 @@ pub mod preview1;
 pub mod vfs;
 
 mod common;
-mod gl_call;
+pub(crate) mod gl_call;
 
 pub struct Context {
     vfs: vfs::VFS,
@@ -18,15 +20,45 @@ impl Context {
     pub fn new(
         data: genlayer_sdk::SingleVMData,
         limiter: rt::memlimiter::Limiter,
+        output: Option<Arc<Mutex<vfs::CapturedOutput>>>,
     ) -> anyhow::Result<Self> {
         let as_value = calldata::to_value(&data.message_data)?;
         let as_bytes = calldata::encode(&as_value);
         Ok(Self {
-            vfs: vfs::VFS::new(as_bytes, limiter),
-            preview1: preview1::Context::new(data.message_data.datetime, data.conf),
+            vfs: vfs::VFS::new(as_bytes, limiter, output),
+            preview1: preview1::Context::new(
+                data.message_data.datetime,
+                data.conf,
+                data.cancellation.clone(),
+                data.supervisor.shared_data.gep(|x| &x.metrics.supervisor),
+                data.random_seed,
+            ),
             genlayer_sdk: genlayer_sdk::Context::new(data),
         })
     }
+
+    /// Drops seed derived from host entropy, which contracts below
+    /// [`crate::public_abi::RANDOM_SEED_MIN_VERSION`] must not observe, see [`rt::random`]
+    pub fn use_legacy_random_seed(&mut self) {
+        self.genlayer_sdk.data.random_seed = None;
+        self.preview1.use_legacy_random_seed();
+    }
+
+    /// Until [`Context::end_initialization`], calls that would make runner initialization observable
+    /// outside of guest memory trap: `random_get`, clocks, modifying files, using descriptors opened before it
+    /// and any genlayer sdk call. Files mapped from runners can be opened for reading
+    pub fn begin_initialization(&mut self) {
+        self.preview1.initializing = true;
+        self.genlayer_sdk.initializing = true;
+        self.vfs.begin_initialization();
+    }
+
+    /// Fails if initialization left descriptors open, see [`vfs::VFS::end_initialization`]
+    pub fn end_initialization(&mut self) -> anyhow::Result<()> {
+        self.preview1.initializing = false;
+        self.genlayer_sdk.initializing = false;
+        self.vfs.end_initialization()
+    }
 }
 
 #[cfg(any())]
@@ -6,10 +6,12 @@ use wiggle::{GuestError, GuestMemory, GuestPtr};
 use genvm_common::*;
 
 use crate::wasi::{base, common::align_slice};
+use crate::{public_abi, rt};
 use genvm_common::util::SharedBytes;
 
 use super::vfs;
 use std::collections::BTreeMap;
+use std::sync::Arc;
 
 pub struct Context {
     args_buf: Vec<u8>,
@@ -19,9 +21,16 @@ pub struct Context {
 
     fs: Box<FilesTrie>,
     unix_timestamp: u64,
+    /// nanoseconds slept in `poll_oneoff`, added to [`Context::unix_timestamp`]
+    clock_offset: u64,
 
     conf: base::Config,
     mt19937_rng: mt19937::MT19937,
+
+    cancellation: Arc<cancellation::Token>,
+    metrics: sync::DArc<rt::Metrics>,
+    /// set while runner initialization function is called, see [`crate::wasi::Context::begin_initialization`]
+    pub(super) initializing: bool,
 }
 
 pub struct ContextVFS<'a> {
@@ -71,6 +80,7 @@ pub(crate) mod generated {
                 fd_close,
                 fd_read, fd_pread,
                 fd_filestat_get, fd_seek, fd_tell,
+                poll_oneoff,
             },
         },
     });
@@ -86,6 +96,7 @@ pub(crate) mod generated {
                 fd_close,
                 fd_read, fd_pread,
                 fd_filestat_get, fd_seek, fd_tell,
+                poll_oneoff,
             },
         },
     });
@@ -132,16 +143,381 @@ impl From<GuestError> for generated::types::Error {
     }
 }
 
+/// Per-VM file system. Runner mappings are read-only [`FilesTrie::File`] entries,
+/// writing to one replaces it with a [`FilesTrie::Memory`] copy, so changes are discarded with the VM.
+/// `charge` holds memory consumed for entries created by the contract, entries without it can't be removed or replaced
 enum FilesTrie {
     Dir {
         children: BTreeMap<String, Box<FilesTrie>>,
+        charge: Option<vfs::Charge>,
     },
     File {
         data: SharedBytes,
+        /// contents are not part of the snapshot key, so such files can't be read during runner initialization
+        from_contract: bool,
+    },
+    Memory {
+        file: vfs::MemFile,
+        charge: Option<vfs::Charge>,
     },
 }
 
+impl FilesTrie {
+    fn new_dir(charge: Option<vfs::Charge>) -> Self {
+        FilesTrie::Dir {
+            children: BTreeMap::new(),
+            charge,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self, FilesTrie::Dir { .. })
+    }
+
+    fn get(&self, path: &[String]) -> Result<&FilesTrie, generated::types::Error> {
+        let mut cur = self;
+        for name in path {
+            cur = match cur {
+                FilesTrie::Dir { children, .. } => children
+                    .get(name)
+                    .map(Box::as_ref)
+                    .ok_or(generated::types::Errno::Noent)?,
+                _ => return Err(generated::types::Errno::Notdir.into()),
+            };
+        }
+        Ok(cur)
+    }
+
+    fn children_mut(
+        &mut self,
+        path: &[String],
+    ) -> Result<&mut BTreeMap<String, Box<FilesTrie>>, generated::types::Error> {
+        let mut cur = self;
+        for name in path {
+            cur = match cur {
+                FilesTrie::Dir { children, .. } => children
+                    .get_mut(name)
+                    .map(Box::as_mut)
+                    .ok_or(generated::types::Errno::Noent)?,
+                _ => return Err(generated::types::Errno::Notdir.into()),
+            };
+        }
+        match cur {
+            FilesTrie::Dir { children, .. } => Ok(children),
+            _ => Err(generated::types::Errno::Notdir.into()),
+        }
+    }
+
+    /// Whenever entry comes from a runner (or is a copy of one) and can't be removed or replaced
+    fn is_mapped(&self) -> bool {
+        match self {
+            FilesTrie::File { .. } => true,
+            FilesTrie::Dir { charge, .. } | FilesTrie::Memory { charge, .. } => charge.is_none(),
+        }
+    }
+
+    fn create_dir(
+        &mut self,
+        limiter: &rt::memlimiter::Limiter,
+        path: &[String],
+    ) -> Result<(), generated::types::Error> {
+        let Some((name, parent)) = path.split_last() else {
+            return Err(generated::types::Errno::Exist.into());
+        };
+        let charge = entry_charge(limiter, name)?;
+        let children = self.children_mut(parent)?;
+        match children.entry(name.clone()) {
+            std::collections::btree_map::Entry::Occupied(_) => {
+                Err(generated::types::Errno::Exist.into())
+            }
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                entry.insert(Box::new(FilesTrie::new_dir(Some(charge))));
+                Ok(())
+            }
+        }
+    }
+
+    fn remove_dir(&mut self, path: &[String]) -> Result<(), generated::types::Error> {
+        let Some((name, parent)) = path.split_last() else {
+            return Err(generated::types::Errno::Busy.into());
+        };
+        let children = self.children_mut(parent)?;
+        match children.get(name).map(|x| &**x) {
+            None => Err(generated::types::Errno::Noent.into()),
+            Some(entry @ FilesTrie::Dir { children: sub, .. }) => {
+                if entry.is_mapped() {
+                    return Err(generated::types::Errno::Rofs.into());
+                }
+                if !sub.is_empty() {
+                    return Err(generated::types::Errno::Notempty.into());
+                }
+                children.remove(name);
+                Ok(())
+            }
+            Some(_) => Err(generated::types::Errno::Notdir.into()),
+        }
+    }
+
+    fn unlink_file(&mut self, path: &[String]) -> Result<(), generated::types::Error> {
+        let Some((name, parent)) = path.split_last() else {
+            return Err(generated::types::Errno::Isdir.into());
+        };
+        let children = self.children_mut(parent)?;
+        match children.get(name) {
+            None => Err(generated::types::Errno::Noent.into()),
+            Some(entry) if entry.is_dir() => Err(generated::types::Errno::Isdir.into()),
+            Some(entry) if entry.is_mapped() => Err(generated::types::Errno::Rofs.into()),
+            Some(_) => {
+                children.remove(name);
+                Ok(())
+            }
+        }
+    }
+
+    fn rename(&mut self, src: &[String], dest: &[String]) -> Result<(), generated::types::Error> {
+        let (Some((src_name, src_parent)), Some((dest_name, dest_parent))) =
+            (src.split_last(), dest.split_last())
+        else {
+            return Err(generated::types::Errno::Busy.into());
+        };
+
+        let src_entry = self.get(src)?;
+        if src_entry.is_mapped() {
+            return Err(generated::types::Errno::Rofs.into());
+        }
+        let src_is_dir = src_entry.is_dir();
+        if src == dest {
+            return Ok(());
+        }
+        if dest.starts_with(src) {
+            return Err(generated::types::Errno::Inval.into());
+        }
+
+        match self.children_mut(dest_parent)?.get(dest_name) {
+            None => {}
+            Some(existing) if existing.is_mapped() => {
+                return Err(generated::types::Errno::Rofs.into());
+            }
+            Some(existing) => match &**existing {
+                FilesTrie::Dir { children, .. } => {
+                    if !src_is_dir {
+                        return Err(generated::types::Errno::Isdir.into());
+                    }
+                    if !children.is_empty() {
+                        return Err(generated::types::Errno::Notempty.into());
+                    }
+                }
+                _ => {
+                    if src_is_dir {
+                        return Err(generated::types::Errno::Notdir.into());
+                    }
+                }
+            },
+        }
+
+        let Some(entry) = self.children_mut(src_parent)?.remove(src_name) else {
+            return Err(generated::types::Errno::Noent.into());
+        };
+        self.children_mut(dest_parent)?
+            .insert(dest_name.clone(), entry);
+
+        Ok(())
+    }
+
+    /// Directory listing in `fd_readdir` order: `.`, `..` and then children by name
+    fn dir_entries(
+        &self,
+        path: &[String],
+    ) -> Result<Vec<(generated::types::Dirent, String)>, generated::types::Error> {
+        let FilesTrie::Dir {
+            children: direntries,
+            ..
+        } = self.get(path)?
+        else {
+            return Err(generated::types::Errno::Badf.into());
+        };
+
+        let head = [
+            (
+                generated::types::Dirent {
+                    d_next: 1u64,
+                    d_ino: 0,
+                    d_type: generated::types::Filetype::Directory,
+                    d_namlen: 1u32,
+                },
+                ".".into(),
+            ),
+            (
+                generated::types::Dirent {
+                    d_next: 2u64,
+                    d_ino: 0,
+                    d_type: generated::types::Filetype::Directory,
+                    d_namlen: 2u32,
+                },
+                "..".into(),
+            ),
+        ];
+
+        let dirent_actual_iter = direntries.iter().zip(3u64..).map(|(x, idx)| {
+            let name_len: u32 = x.0.len().try_into().unwrap();
+            (
+                generated::types::Dirent {
+                    d_next: idx,
+                    d_ino: 0,
+                    d_type: match **x.1 {
+                        FilesTrie::Dir { .. } => generated::types::Filetype::Directory,
+                        FilesTrie::File { .. } | FilesTrie::Memory { .. } => {
+                            generated::types::Filetype::RegularFile
+                        }
+                    },
+                    d_namlen: name_len,
+                },
+                x.0.clone(),
+            )
+        });
+
+        Ok(head.into_iter().chain(dirent_actual_iter).collect())
+    }
+
+    fn open(
+        &mut self,
+        limiter: &rt::memlimiter::Limiter,
+        path: Vec<String>,
+        oflags: generated::types::Oflags,
+        write: bool,
+        append: bool,
+    ) -> Result<vfs::FileDescriptor, generated::types::Error> {
+        if path.is_empty() {
+            if write {
+                return Err(generated::types::Errno::Isdir.into());
+            }
+            return Ok(vfs::FileDescriptor::Dir { path });
+        }
+        let Some((name, parent)) = path.split_last() else {
+            return Err(generated::types::Errno::Inval.into());
+        };
+
+        let charge = if oflags.contains(generated::types::Oflags::CREAT) && self.get(&path).is_err()
+        {
+            Some(entry_charge(limiter, name)?)
+        } else {
+            None
+        };
+
+        let children = self.children_mut(parent)?;
+        let entry = match children.entry(name.clone()) {
+            std::collections::btree_map::Entry::Occupied(entry) => {
+                if oflags.contains(generated::types::Oflags::CREAT | generated::types::Oflags::EXCL)
+                {
+                    return Err(generated::types::Errno::Exist.into());
+                }
+                entry.into_mut()
+            }
+            std::collections::btree_map::Entry::Vacant(entry) => {
+                let Some(charge) = charge else {
+                    return Err(generated::types::Errno::Noent.into());
+                };
+                let file = vfs::MemFile::new(limiter, Vec::new())
+                    .map_err(generated::types::Error::trap)?;
+                entry.insert(Box::new(FilesTrie::Memory {
+                    file,
+                    charge: Some(charge),
+                }))
+            }
+        };
+
+        let file = match &mut **entry {
+            FilesTrie::Dir { .. } => {
+                if write {
+                    return Err(generated::types::Errno::Isdir.into());
+                }
+                return Ok(vfs::FileDescriptor::Dir { path });
+            }
+            _ if oflags.contains(generated::types::Oflags::DIRECTORY) => {
+                return Err(generated::types::Errno::Notdir.into());
+            }
+            FilesTrie::File { data, .. } => {
+                if !write {
+                    return Ok(vfs::FileDescriptor::File(vfs::FileContents {
+                        contents: data.clone(),
+                        pos: 0,
+                        release_memory: false,
+                    }));
+                }
+
+                // copy on write
+                let contents = if oflags.contains(generated::types::Oflags::TRUNC) {
+                    Vec::new()
+                } else {
+                    data.as_ref().to_vec()
+                };
+                let file =
+                    vfs::MemFile::new(limiter, contents).map_err(generated::types::Error::trap)?;
+                **entry = FilesTrie::Memory {
+                    file: file.clone(),
+                    charge: None,
+                };
+                file
+            }
+            FilesTrie::Memory { file, .. } => file.clone(),
+        };
+
+        if oflags.contains(generated::types::Oflags::TRUNC) {
+            file.set_len(0).map_err(generated::types::Error::trap)?;
+        }
+
+        Ok(vfs::FileDescriptor::MemFile {
+            file,
+            pos: 0,
+            append,
+            writable: write,
+        })
+    }
+}
+
+fn entry_charge(
+    limiter: &rt::memlimiter::Limiter,
+    name: &str,
+) -> Result<vfs::Charge, generated::types::Error> {
+    let amount = (public_abi::MemoryLimiterConsts::FileMapping as u32)
+        .saturating_add(name.len().try_into()?);
+    vfs::Charge::new(limiter, amount).map_err(generated::types::Error::trap)
+}
+
+/// Returns path from the root, `.` and `..` are resolved lexically
+fn resolve(dir_path: &[String], path: &str) -> Vec<String> {
+    let mut result = dir_path.to_vec();
+    for name in path.split("/") {
+        match name {
+            "" | "." => {}
+            ".." => {
+                result.pop();
+            }
+            name => result.push(name.to_owned()),
+        }
+    }
+    result
+}
+
+/// End of `len` octets at `offset`, files can't grow past [`u32::MAX`]
+fn file_end(offset: u64, len: u64) -> Result<u32, generated::types::Error> {
+    let end = offset
+        .checked_add(len)
+        .ok_or(generated::types::Errno::Fbig)?;
+    Ok(end.try_into().map_err(|_| generated::types::Errno::Fbig)?)
+}
+
 impl Context {
+    /// see [`crate::wasi::Context::use_legacy_random_seed`]
+    pub(super) fn use_legacy_random_seed(&mut self) {
+        self.mt19937_rng = rt::random::rng(None);
+    }
+
+    /// Transaction timestamp advanced by sleeps of this VM
+    fn now(&self) -> u64 {
+        self.unix_timestamp.saturating_add(self.clock_offset)
+    }
+
     pub fn log(&self) -> serde_json::Value {
         serde_json::json!(
             {
@@ -182,7 +558,12 @@ impl Context {
         Ok(())
     }
 
-    pub fn map_file(&mut self, location: &str, contents: SharedBytes) -> anyhow::Result<()> {
+    pub fn map_file(
+        &mut self,
+        location: &str,
+        contents: SharedBytes,
+        from_contract: bool,
+    ) -> anyhow::Result<()> {
         let mut location_patched = String::new();
         location_patched.reserve(location.len());
 
@@ -203,17 +584,15 @@ impl Context {
         let locs_arr: Vec<&str> = location_patched.split("/").collect();
         for loc in &locs_arr[0..locs_arr.len() - 1] {
             cur_trie = match cur_trie.borrow_mut() {
-                FilesTrie::Dir { children } => match children.entry(String::from(*loc)) {
+                FilesTrie::Dir { children, .. } => match children.entry(String::from(*loc)) {
                     std::collections::btree_map::Entry::Occupied(entry) => {
                         Ok::<&mut FilesTrie, anyhow::Error>(entry.into_mut())
                     }
                     std::collections::btree_map::Entry::Vacant(entry) => {
-                        Ok(&mut **entry.insert(Box::new(FilesTrie::Dir {
-                            children: BTreeMap::new(),
-                        })))
+                        Ok(&mut **entry.insert(Box::new(FilesTrie::new_dir(None))))
                     }
                 },
-                FilesTrie::File { data: _ } => {
+                FilesTrie::File { .. } | FilesTrie::Memory { .. } => {
                     return Err(anyhow::anyhow!(
                         "super path is already mapped as a file {}",
                         location_patched
@@ -225,17 +604,20 @@ impl Context {
         let fname = locs_arr[locs_arr.len() - 1];
 
         match cur_trie.borrow_mut() {
-            FilesTrie::Dir { children } => match children.entry(String::from(fname)) {
+            FilesTrie::Dir { children, .. } => match children.entry(String::from(fname)) {
                 std::collections::btree_map::Entry::Occupied(_entry) => Err(anyhow::anyhow!(
                     "duplicate file mapping {}",
                     location_patched
                 )),
                 std::collections::btree_map::Entry::Vacant(entry) => {
-                    entry.insert(Box::new(FilesTrie::File { data: contents }));
+                    entry.insert(Box::new(FilesTrie::File {
+                        data: contents,
+                        from_contract,
+                    }));
                     Ok(())
                 }
             },
-            FilesTrie::File { data: _ } => {
+            FilesTrie::File { .. } | FilesTrie::Memory { .. } => {
                 return Err(anyhow::anyhow!("super path is already mapped as a file"))
             }
         }?;
@@ -274,22 +656,27 @@ where
 }
 
 impl Context {
-    pub fn new(datetime: chrono::DateTime<chrono::Utc>, conf: base::Config) -> Self {
-        const SEED_ARR: [u32; 2] = [u32::from_le_bytes(*b"GenL"), u32::from_le_bytes(*b"ayer")];
-        let seed = mt19937::MT19937::new_with_slice_seed(&SEED_ARR);
-
+    pub fn new(
+        datetime: chrono::DateTime<chrono::Utc>,
+        conf: base::Config,
+        cancellation: Arc<cancellation::Token>,
+        metrics: sync::DArc<rt::Metrics>,
+        random_seed: Option<rt::random::Seed>,
+    ) -> Self {
         Self {
             args_buf: Vec::new(),
             args_offsets: Vec::new(),
             env_buf: Vec::new(),
             env_offsets: Vec::new(),
-            fs: Box::new(FilesTrie::Dir {
-                children: BTreeMap::new(),
-            }),
+            fs: Box::new(FilesTrie::new_dir(None)),
             unix_timestamp: datetime.timestamp() as u64 * 1_000_000_000
                 + datetime.timestamp_subsec_nanos() as u64,
+            clock_offset: 0,
             conf,
-            mt19937_rng: seed,
+            mt19937_rng: rt::random::rng(random_seed.as_ref()),
+            cancellation,
+            metrics,
+            initializing: false,
         }
     }
 }
@@ -382,7 +769,9 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         _id: generated::types::Clockid,
         _precision: generated::types::Timestamp,
     ) -> Result<generated::types::Timestamp, generated::types::Error> {
-        Ok(self.context.unix_timestamp)
+        self.forbid_during_init("clock_time_get")?;
+
+        Ok(self.context.now())
     }
 
     fn fd_advise(
@@ -402,10 +791,22 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         &mut self,
         _memory: &mut GuestMemory<'_>,
         fd: generated::types::Fd,
-        _offset: generated::types::Filesize,
-        _len: generated::types::Filesize,
+        offset: generated::types::Filesize,
+        len: generated::types::Filesize,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_pre_init_fd(fd, "fd_allocate")?;
+
+        let vfs::FileDescriptor::MemFile { file, writable, .. } = self.get_fd_desc(fd)? else {
+            return Err(generated::types::Errno::Rofs.into());
+        };
+        if !*writable {
+            return Err(generated::types::Errno::Badf.into());
+        }
+        let end = file_end(offset, len)?;
+        if end > file.len() {
+            file.set_len(end).map_err(generated::types::Error::trap)?;
+        }
+        Ok(())
     }
 
     /// Close a file descriptor.
@@ -415,6 +816,8 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         _memory: &mut GuestMemory<'_>,
         fd: generated::types::Fd,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_pre_init_fd(fd, "fd_close")?;
+
         let fdi: u32 = fd.into();
         if self.vfs.pop_fd(fdi).is_none() {
             return Err(generated::types::Errno::Badf.into());
@@ -473,6 +876,32 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
                     fs_rights_inheriting: rights,
                 })
             }
+            vfs::FileDescriptor::MemFile {
+                append, writable, ..
+            } => {
+                let mut rights = generated::types::Rights::FD_DATASYNC
+                    | generated::types::Rights::FD_READ
+                    | generated::types::Rights::FD_SEEK
+                    | generated::types::Rights::FD_SYNC
+                    | generated::types::Rights::FD_TELL
+                    | generated::types::Rights::FD_ADVISE
+                    | generated::types::Rights::FD_FILESTAT_GET;
+                if *writable {
+                    rights |= generated::types::Rights::FD_WRITE
+                        | generated::types::Rights::FD_ALLOCATE
+                        | generated::types::Rights::FD_FILESTAT_SET_SIZE;
+                }
+                let mut fs_flags = generated::types::Fdflags::empty();
+                if *append {
+                    fs_flags |= generated::types::Fdflags::APPEND;
+                }
+                Ok(generated::types::Fdstat {
+                    fs_filetype: generated::types::Filetype::RegularFile,
+                    fs_flags,
+                    fs_rights_base: rights,
+                    fs_rights_inheriting: rights,
+                })
+            }
             vfs::FileDescriptor::Dir { .. } => {
                 let rights = generated::types::Rights::FD_READ
                     | generated::types::Rights::PATH_OPEN
@@ -501,7 +930,13 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         fd: generated::types::Fd,
         flags: generated::types::Fdflags,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        match self.get_fd_desc_mut(fd)? {
+            vfs::FileDescriptor::MemFile { append, .. } => {
+                *append = flags.contains(generated::types::Fdflags::APPEND);
+                Ok(())
+            }
+            _ => Err(generated::types::Errno::Rofs.into()),
+        }
     }
 
     /// Does not do anything if `fd` corresponds to a valid descriptor and returns `[stub::types::Errno::Badf]` error otherwise.
@@ -544,6 +979,16 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
                 mtim: 0,
                 ctim: 0,
             }),
+            vfs::FileDescriptor::MemFile { file, .. } => Ok(generated::types::Filestat {
+                dev: 0,
+                ino: 0,
+                filetype: generated::types::Filetype::RegularFile,
+                nlink: 1,
+                size: file.len().into(),
+                atim: 0,
+                mtim: 0,
+                ctim: 0,
+            }),
             vfs::FileDescriptor::Dir { .. } => Ok(generated::types::Filestat {
                 dev: 0,
                 ino: 0,
@@ -565,8 +1010,16 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         fd: generated::types::Fd,
         size: generated::types::Filesize,
     ) -> Result<(), generated::types::Error> {
-        self.get_fd_desc(fd)?;
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_pre_init_fd(fd, "fd_filestat_set_size")?;
+
+        let vfs::FileDescriptor::MemFile { file, writable, .. } = self.get_fd_desc(fd)? else {
+            return Err(generated::types::Errno::Rofs.into());
+        };
+        if !*writable {
+            return Err(generated::types::Errno::Badf.into());
+        }
+        let size: u32 = size.try_into().map_err(|_| generated::types::Errno::Fbig)?;
+        file.set_len(size).map_err(generated::types::Error::trap)
     }
 
     /// Adjust the timestamps of an open file or directory.
@@ -579,8 +1032,9 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         mtim: generated::types::Timestamp,
         fst_flags: generated::types::Fstflags,
     ) -> Result<(), generated::types::Error> {
+        // timestamps are fixed
         self.get_fd_desc(fd)?;
-        Err(generated::types::Errno::Rofs.into())
+        Ok(())
     }
 
     /// Read from a file descriptor.
@@ -592,6 +1046,8 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         fd: generated::types::Fd,
         iovs: generated::types::IovecArray,
     ) -> Result<generated::types::Size, generated::types::Error> {
+        self.forbid_pre_init_fd(fd, "fd_read")?;
+
         match self.get_fd_desc_mut(fd)? {
             vfs::FileDescriptor::Stdin => Ok(0),
             vfs::FileDescriptor::Stdout | vfs::FileDescriptor::Stderr => {
@@ -612,6 +1068,12 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
                 }
                 Ok(written)
             }
+            vfs::FileDescriptor::MemFile { file, pos, .. } => {
+                let read =
+                    file.with_contents(|contents| read_iovs(memory, iovs, contents, *pos))?;
+                *pos += u64::from(read);
+                Ok(read)
+            }
             vfs::FileDescriptor::Dir { .. } => Err(generated::types::Errno::Isdir.into()),
         }
     }
@@ -626,6 +1088,8 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         iovs: generated::types::IovecArray,
         offset: generated::types::Filesize,
     ) -> Result<generated::types::Size, generated::types::Error> {
+        self.forbid_pre_init_fd(fd, "fd_pread")?;
+
         match self.get_fd_desc(fd)? {
             vfs::FileDescriptor::Stdin => Ok(0),
             vfs::FileDescriptor::Stdout | vfs::FileDescriptor::Stderr => {
@@ -647,6 +1111,9 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
                 }
                 Ok(written.try_into().unwrap_or(u32::MAX))
             }
+            vfs::FileDescriptor::MemFile { file, .. } => {
+                file.with_contents(|contents| read_iovs(memory, iovs, contents, offset))
+            }
             vfs::FileDescriptor::Dir { .. } => Err(generated::types::Errno::Isdir.into()),
         }
     }
@@ -660,12 +1127,36 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         fd: generated::types::Fd,
         ciovs: generated::types::CiovecArray,
     ) -> Result<generated::types::Size, generated::types::Error> {
-        let mut stream: Box<dyn Write> = match self.get_fd_desc(fd)? {
-            vfs::FileDescriptor::Stdout => Box::new(std::io::stdout().lock()),
-            vfs::FileDescriptor::Stderr => Box::new(std::io::stderr().lock()),
+        if !matches!(
+            self.get_fd_desc(fd)?,
+            vfs::FileDescriptor::Stdout | vfs::FileDescriptor::Stderr
+        ) {
+            self.forbid_pre_init_fd(fd, "fd_write")?;
+        }
+
+        if let vfs::FileDescriptor::MemFile {
+            file,
+            pos,
+            append,
+            writable,
+        } = self.get_fd_desc_mut(fd)?
+        {
+            if !*writable {
+                return Err(generated::types::Errno::Badf.into());
+            }
+            let offset = if *append { file.len().into() } else { *pos };
+            let written = write_iovs(memory, ciovs, file, offset)?;
+            *pos = offset + u64::from(written);
+            return Ok(written);
+        }
+
+        let (to_stderr, mut stream): (bool, Box<dyn Write>) = match self.get_fd_desc(fd)? {
+            vfs::FileDescriptor::Stdout => (false, Box::new(std::io::stdout().lock())),
+            vfs::FileDescriptor::Stderr => (true, Box::new(std::io::stderr().lock())),
             vfs::FileDescriptor::Stdin => return Err(generated::types::Errno::Notsup.into()),
             _ => return Err(generated::types::Errno::Rofs.into()),
         };
+        let mut output = self.vfs.output.as_ref().map(|x| x.lock().unwrap());
         let mut size: u32 = 0;
         for ciov in ciovs.iter() {
             let ciov_read = memory.read(ciov?)?;
@@ -676,6 +1167,9 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
             let cow = memory.as_cow(buf_to_rewrite)?;
             let add_size: u32 = cow.len().try_into()?;
             size += add_size;
+            if let Some(output) = &mut output {
+                output.write(to_stderr, &cow);
+            }
             if let Err(e) = stream.write_all(&cow) {
                 log_error!(e: err = e; "Failed to write to stream");
             }
@@ -696,8 +1190,21 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         ciovs: generated::types::CiovecArray,
         offset: generated::types::Filesize,
     ) -> Result<generated::types::Size, generated::types::Error> {
-        self.get_fd_desc(fd)?;
-        Err(generated::types::Errno::Notsup.into())
+        self.forbid_pre_init_fd(fd, "fd_pwrite")?;
+
+        match self.get_fd_desc(fd)? {
+            vfs::FileDescriptor::MemFile { file, writable, .. } => {
+                if !*writable {
+                    return Err(generated::types::Errno::Badf.into());
+                }
+                write_iovs(memory, ciovs, file, offset)
+            }
+            vfs::FileDescriptor::Stdout | vfs::FileDescriptor::Stderr => {
+                Err(generated::types::Errno::Spipe.into())
+            }
+            vfs::FileDescriptor::Dir { .. } => Err(generated::types::Errno::Isdir.into()),
+            _ => Err(generated::types::Errno::Rofs.into()),
+        }
     }
 
     /// Return a description of the given preopened file descriptor.
@@ -770,6 +1277,8 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         offset: generated::types::Filedelta,
         whence: generated::types::Whence,
     ) -> Result<generated::types::Filesize, generated::types::Error> {
+        self.forbid_pre_init_fd(fd, "fd_seek")?;
+
         match self.get_fd_desc_mut(fd)? {
             vfs::FileDescriptor::Stdin
             | vfs::FileDescriptor::Stderr
@@ -811,6 +1320,18 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
                 };
                 return Ok(u64::from(*pos));
             }
+            vfs::FileDescriptor::MemFile { file, pos, .. } => {
+                let base = match whence {
+                    generated::types::Whence::Cur => *pos,
+                    generated::types::Whence::End => file.len().into(),
+                    generated::types::Whence::Set => 0,
+                };
+                let new_pos = i128::from(base) + i128::from(offset);
+                *pos = new_pos
+                    .try_into()
+                    .map_err(|_| generated::types::Errno::Inval)?;
+                Ok(*pos)
+            }
             vfs::FileDescriptor::Dir { .. } => Err(generated::types::Errno::Notsup.into()),
         }
     }
@@ -837,6 +1358,7 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
             | vfs::FileDescriptor::Stderr
             | vfs::FileDescriptor::Stdout => Err(generated::types::Errno::Spipe.into()),
             vfs::FileDescriptor::File(file) => Ok(file.pos.into()),
+            vfs::FileDescriptor::MemFile { pos, .. } => Ok(*pos),
             vfs::FileDescriptor::Dir { .. } => Err(generated::types::Errno::Notsup.into()),
         }
     }
@@ -854,55 +1376,12 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
             return Err(generated::types::Errno::Badf.into());
         };
 
-        let dirent = self.dir_fd_follow_trie(dir_path, &self.context.fs, None)?;
-        let FilesTrie::Dir {
-            children: direntries,
-        } = dirent
-        else {
-            return Err(generated::types::Errno::Badf.into());
-        };
-
-        let head = [
-            (
-                generated::types::Dirent {
-                    d_next: 1u64,
-                    d_ino: 0,
-                    d_type: generated::types::Filetype::Directory,
-                    d_namlen: 1u32,
-                },
-                ".".into(),
-            ),
-            (
-                generated::types::Dirent {
-                    d_next: 2u64,
-                    d_ino: 0,
-                    d_type: generated::types::Filetype::Directory,
-                    d_namlen: 2u32,
-                },
-                "..".into(),
-            ),
-        ];
-
-        let dirent_actual_iter = direntries.iter().zip(3u64..).map(|(x, idx)| {
-            let name_len: u32 = x.0.len().try_into().unwrap();
-            (
-                generated::types::Dirent {
-                    d_next: idx,
-                    d_ino: 0,
-                    d_type: match **x.1 {
-                        FilesTrie::Dir { .. } => generated::types::Filetype::Directory,
-                        FilesTrie::File { .. } => generated::types::Filetype::RegularFile,
-                    },
-                    d_namlen: name_len,
-                },
-                x.0.clone(),
-            )
-        });
+        let entries = self.context.fs.dir_entries(dir_path)?;
 
         let mut buf = buf;
         let mut cap = buf_len;
         let cookie = cookie.try_into()?;
-        for (ref entry, path) in head.into_iter().chain(dirent_actual_iter).skip(cookie) {
+        for (ref entry, path) in entries.into_iter().skip(cookie) {
             const DIRENT_SIZE_BOUND: usize = 100;
             let mut dirent_mem_buf: [u8; DIRENT_SIZE_BOUND] = [0; DIRENT_SIZE_BOUND];
 
@@ -942,7 +1421,11 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         dirfd: generated::types::Fd,
         path: GuestPtr<str>,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_during_init("path_create_directory")?;
+
+        let path = self.resolve_path(memory, dirfd, path)?;
+        let limiter = self.vfs.limiter.clone();
+        self.context.fs.create_dir(&limiter, &path)
     }
 
     /// Return the attributes of a file or directory.
@@ -955,33 +1438,33 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         flags: generated::types::Lookupflags,
         path: GuestPtr<str>,
     ) -> Result<generated::types::Filestat, generated::types::Error> {
-        let fdi: u32 = dirfd.into();
-        let path = super::common::read_string(memory, path)?;
-        let Some(vfs::FileDescriptor::Dir { path: dir_path }) = self.vfs.fds.get(&fdi) else {
-            return Err(generated::types::Errno::Badf.into());
-        };
-        let mut result_path = dir_path.clone();
-        let mut cur_trie =
-            self.dir_fd_follow_trie(dir_path, &self.context.fs, Some(&mut result_path))?;
-        for fname in path.split("/") {
-            cur_trie = self.dir_fd_get_trie(fname, cur_trie, &mut Some(&mut result_path))?;
-        }
-        match cur_trie {
-            FilesTrie::File { data } => Ok(generated::types::Filestat {
+        let path = self.resolve_path(memory, dirfd, path)?;
+        match self.context.fs.get(&path)? {
+            FilesTrie::File { data, .. } => Ok(generated::types::Filestat {
                 dev: 0,
                 ino: 0,
                 filetype: generated::types::Filetype::RegularFile,
-                nlink: 0,
+                nlink: 1,
                 size: data.len().try_into()?,
                 atim: 0,
                 mtim: 0,
                 ctim: 0,
             }),
+            FilesTrie::Memory { file, .. } => Ok(generated::types::Filestat {
+                dev: 0,
+                ino: 0,
+                filetype: generated::types::Filetype::RegularFile,
+                nlink: 1,
+                size: file.len().into(),
+                atim: 0,
+                mtim: 0,
+                ctim: 0,
+            }),
             FilesTrie::Dir { .. } => Ok(generated::types::Filestat {
                 dev: 0,
                 ino: 0,
                 filetype: generated::types::Filetype::Directory,
-                nlink: 0,
+                nlink: 1,
                 size: 0,
                 atim: 0,
                 mtim: 0,
@@ -1003,7 +1486,10 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         mtim: generated::types::Timestamp,
         fst_flags: generated::types::Fstflags,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        // timestamps are fixed
+        let path = self.resolve_path(memory, dirfd, path)?;
+        self.context.fs.get(&path)?;
+        Ok(())
     }
 
     /// Create a hard link.
@@ -1035,42 +1521,32 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         _fs_rights_inheriting: generated::types::Rights,
         fdflags: generated::types::Fdflags,
     ) -> Result<generated::types::Fd, generated::types::Error> {
-        let file_path = super::common::read_string(memory, path)?;
-        let fdi: u32 = dirfd.into();
-        let fdi: u32 = dirfd.into();
-        let new_fd = self.vfs.alloc_fd().map_err(generated::types::Error::trap)?;
-        {
-            let Some(vfs::FileDescriptor::Dir { path: dir_path }) = self.vfs.fds.get(&fdi) else {
-                return Err(generated::types::Errno::Badf.into());
-            };
-            let mut resulting_path = dir_path.clone();
-            let mut cur_trie =
-                self.dir_fd_follow_trie(dir_path, &self.context.fs, Some(&mut resulting_path))?;
-            for fname in file_path.split("/") {
-                cur_trie = self.dir_fd_get_trie(fname, cur_trie, &mut Some(&mut resulting_path))?;
-            }
-            match cur_trie {
-                FilesTrie::File { data } => {
-                    let f = vfs::FileDescriptor::File(vfs::FileContents {
-                        contents: data.clone(),
-                        pos: 0,
-                        release_memory: false,
-                    });
-                    self.vfs.fds.insert(new_fd, f);
-                    Ok(new_fd.into())
-                }
-                FilesTrie::Dir { .. } => {
-                    let f = vfs::FileDescriptor::Dir {
-                        path: resulting_path,
-                    };
-                    self.vfs.fds.insert(new_fd, f);
-                    Ok(new_fd.into())
-                }
-            }
+        let path = self.resolve_path(memory, dirfd, path)?;
+        let write = fs_rights_base.contains(generated::types::Rights::FD_WRITE)
+            || oflags.contains(generated::types::Oflags::TRUNC);
+        if write || oflags.contains(generated::types::Oflags::CREAT) {
+            self.forbid_during_init("path_open for writing")?;
         }
-        .inspect_err(|e| {
-            self.vfs.free_fd(new_fd);
-        })
+        if matches!(
+            self.context.fs.get(&path),
+            Ok(FilesTrie::File {
+                from_contract: true,
+                ..
+            })
+        ) {
+            self.forbid_during_init("path_open of a file mapped from the contract")?;
+        }
+        let append = fdflags.contains(generated::types::Fdflags::APPEND);
+
+        let limiter = self.vfs.limiter.clone();
+        let desc = self
+            .context
+            .fs
+            .open(&limiter, path, oflags, write, append)?;
+
+        let new_fd = self.vfs.alloc_fd().map_err(generated::types::Error::trap)?;
+        self.vfs.fds.insert(new_fd, desc);
+        Ok(new_fd.into())
     }
 
     /// Read the contents of a symbolic link.
@@ -1094,7 +1570,10 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         dirfd: generated::types::Fd,
         path: GuestPtr<str>,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_during_init("path_remove_directory")?;
+
+        let path = self.resolve_path(memory, dirfd, path)?;
+        self.context.fs.remove_dir(&path)
     }
 
     /// Rename a file or directory.
@@ -1108,7 +1587,12 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         dest_fd: generated::types::Fd,
         dest_path: GuestPtr<str>,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_during_init("path_rename")?;
+
+        let src = self.resolve_path(memory, src_fd, src_path)?;
+        let dest = self.resolve_path(memory, dest_fd, dest_path)?;
+
+        self.context.fs.rename(&src, &dest)
     }
 
     #[instrument(skip(self, memory))]
@@ -1129,18 +1613,75 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         dirfd: generated::types::Fd,
         path: GuestPtr<str>,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Rofs.into())
+        self.forbid_during_init("path_unlink_file")?;
+
+        let path = self.resolve_path(memory, dirfd, path)?;
+        self.context.fs.unlink_file(&path)
     }
 
     #[instrument(skip(self, memory))]
-    fn poll_oneoff(
+    async fn poll_oneoff(
         &mut self,
         memory: &mut GuestMemory<'_>,
         subs: GuestPtr<generated::types::Subscription>,
         events: GuestPtr<generated::types::Event>,
         nsubscriptions: generated::types::Size,
     ) -> Result<generated::types::Size, generated::types::Error> {
-        Err(generated::types::Errno::Notsup.into())
+        self.forbid_during_init("poll_oneoff")?;
+
+        if nsubscriptions == 0 {
+            return Err(generated::types::Errno::Inval.into());
+        }
+
+        let now = self.context.now();
+
+        let mut ready = Vec::new();
+        let mut clocks = Vec::new();
+        for sub in subs.as_array(nsubscriptions).iter() {
+            let sub = memory.read(sub?)?;
+            match sub.u {
+                generated::types::SubscriptionU::Clock(clock) => {
+                    let delay = if clock
+                        .flags
+                        .contains(generated::types::Subclockflags::SUBSCRIPTION_CLOCK_ABSTIME)
+                    {
+                        clock.timeout.saturating_sub(now)
+                    } else {
+                        clock.timeout
+                    };
+                    clocks.push((sub.userdata, delay));
+                }
+                generated::types::SubscriptionU::FdRead(_) => ready.push(poll_event(
+                    sub.userdata,
+                    generated::types::Errno::Notsup,
+                    generated::types::Eventtype::FdRead,
+                )),
+                generated::types::SubscriptionU::FdWrite(_) => ready.push(poll_event(
+                    sub.userdata,
+                    generated::types::Errno::Notsup,
+                    generated::types::Eventtype::FdWrite,
+                )),
+            }
+        }
+
+        // unsupported subscriptions complete immediately, so there is nothing to wait for
+        if ready.is_empty() {
+            if let Some(delay) = clocks.iter().map(|x| x.1).min() {
+                self.sleep(delay).await?;
+                ready.extend(clocks.iter().filter(|x| x.1 == delay).map(|x| {
+                    poll_event(
+                        x.0,
+                        generated::types::Errno::Success,
+                        generated::types::Eventtype::Clock,
+                    )
+                }));
+            }
+        }
+
+        for (event, i) in ready.iter().zip(0u32..) {
+            memory.write(events.add(i)?, event.clone())?;
+        }
+        Ok(ready.len().try_into()?)
     }
 
     fn proc_exit(
@@ -1168,7 +1709,7 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         &mut self,
         _memory: &mut GuestMemory<'_>,
     ) -> Result<(), generated::types::Error> {
-        Err(generated::types::Errno::Notsup.into())
+        Ok(())
     }
 
     #[instrument(skip(self, memory))]
@@ -1178,6 +1719,8 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
         buf: GuestPtr<u8>,
         buf_len: generated::types::Size,
     ) -> Result<(), generated::types::Error> {
+        self.forbid_during_init("random_get")?;
+
         let mut mem: Vec<u8> = std::iter::repeat_n(0, buf_len as usize).collect();
 
         if self.context.conf.is_deterministic {
@@ -1239,49 +1782,71 @@ impl generated::wasi_snapshot_preview1::WasiSnapshotPreview1 for ContextVFS<'_>
 }
 
 impl ContextVFS<'_> {
-    fn dir_fd_get_trie<'a>(
+    /// Initialization may be replaced with restoring a snapshot, so it must not leave state outside of the guest memory
+    fn forbid_during_init(&self, call: &str) -> Result<(), generated::types::Error> {
+        if self.context.initializing {
+            return Err(generated::types::Error::trap(anyhow::anyhow!(
+                "`{call}` is forbidden during runner initialization"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Descriptors opened before initialization are not restored from a snapshot,
+    /// so initialization must not change or observe them
+    fn forbid_pre_init_fd(
         &self,
-        dir_path: &str,
-        cur_trie: &'a FilesTrie,
-        path: &mut Option<&mut Vec<String>>,
-    ) -> Result<&'a FilesTrie, generated::types::Error> {
-        if dir_path == "." || dir_path.is_empty() {
-            return Ok(cur_trie);
+        fd: generated::types::Fd,
+        call: &str,
+    ) -> Result<(), generated::types::Error> {
+        if self.vfs.is_pre_init_fd(fd.into()) {
+            return Err(generated::types::Error::trap(anyhow::anyhow!(
+                "`{call}` of a descriptor opened before runner initialization is forbidden during it"
+            )));
         }
-        if dir_path == ".." {
-            match path {
-                None => return Err(generated::types::Errno::Noent.into()),
-                Some(rf) => {
-                    let _ = rf.pop();
-                    let goto = rf.clone();
-                    self.dir_fd_follow_trie(&goto, &self.context.fs, Some(rf))?;
-                }
+
+        Ok(())
+    }
+
+    /// Sleeps for real only in non-deterministic mode, both modes advance the virtual clock
+    async fn sleep(&mut self, nanos: u64) -> Result<(), generated::types::Error> {
+        let duration = std::time::Duration::from_nanos(nanos);
+
+        if self.context.conf.is_deterministic {
+            self.context.metrics.guest_virtual_sleep.add(duration);
+        } else {
+            let start = std::time::Instant::now();
+            let cancelled = tokio::select! {
+                _ = tokio::time::sleep(duration) => false,
+                _ = self.context.cancellation.chan.closed() => true,
+            };
+            self.context.metrics.guest_sleep.add(start.elapsed());
+
+            if cancelled {
+                return Err(generated::types::Error::trap(
+                    rt::errors::VMError(public_abi::VmError::Timeout.value().into(), None).into(),
+                ));
             }
         }
-        match cur_trie {
-            FilesTrie::File { .. } => Err(generated::types::Errno::Badf.into()),
-            FilesTrie::Dir { children } => match children.get(dir_path) {
-                Some(new_trie) => {
-                    if let Some(rf) = path {
-                        rf.push(dir_path.into());
-                    }
-                    Ok(new_trie)
-                }
-                None => Err(generated::types::Errno::Noent.into()),
-            },
-        }
+
+        self.context.clock_offset = self.context.clock_offset.saturating_add(nanos);
+        Ok(())
     }
 
-    fn dir_fd_follow_trie<'a>(
+    /// Returns path from the root, `.` and `..` are resolved lexically
+    fn resolve_path(
         &self,
-        dir_path: &Vec<String>,
-        mut cur_trie: &'a FilesTrie,
-        mut path: Option<&mut Vec<String>>,
-    ) -> Result<&'a FilesTrie, generated::types::Error> {
-        for dir in dir_path {
-            cur_trie = self.dir_fd_get_trie(dir, cur_trie, &mut path)?;
-        }
-        Ok(cur_trie)
+        memory: &mut GuestMemory<'_>,
+        dirfd: generated::types::Fd,
+        path: GuestPtr<str>,
+    ) -> Result<Vec<String>, generated::types::Error> {
+        let path = super::common::read_string(memory, path)?;
+        let vfs::FileDescriptor::Dir { path: dir_path } = self.get_fd_desc(dirfd)? else {
+            return Err(generated::types::Errno::Notdir.into());
+        };
+
+        Ok(resolve(dir_path, &path))
     }
 
     fn get_fd_desc(
@@ -1307,6 +1872,65 @@ impl ContextVFS<'_> {
     }
 }
 
+fn read_iovs(
+    memory: &mut GuestMemory<'_>,
+    iovs: generated::types::IovecArray,
+    contents: &[u8],
+    offset: u64,
+) -> Result<generated::types::Size, generated::types::Error> {
+    let mut offset = usize::try_from(offset)
+        .unwrap_or(usize::MAX)
+        .min(contents.len());
+    let mut read: u32 = 0;
+    for iov in iovs.iter() {
+        let iov = memory.read(iov?)?;
+        let len = (contents.len() - offset).min(iov.buf_len as usize);
+        memory.copy_from_slice(
+            &contents[offset..offset + len],
+            iov.buf.as_array(len as u32),
+        )?;
+        offset += len;
+        read += len as u32;
+    }
+    Ok(read)
+}
+
+fn write_iovs(
+    memory: &mut GuestMemory<'_>,
+    ciovs: generated::types::CiovecArray,
+    file: &vfs::MemFile,
+    offset: u64,
+) -> Result<generated::types::Size, generated::types::Error> {
+    let mut bytes = Vec::new();
+    for ciov in ciovs.iter() {
+        let ciov = memory.read(ciov?)?;
+        bytes.extend_from_slice(&memory.as_cow(ciov.buf.as_array(ciov.buf_len))?);
+    }
+
+    let written: u32 = bytes.len().try_into()?;
+    file_end(offset, written.into())?;
+
+    file.write_at(offset as u32, &bytes)
+        .map_err(generated::types::Error::trap)?;
+    Ok(written)
+}
+
+fn poll_event(
+    userdata: generated::types::Userdata,
+    error: generated::types::Errno,
+    type_: generated::types::Eventtype,
+) -> generated::types::Event {
+    generated::types::Event {
+        userdata,
+        error,
+        type_,
+        fd_readwrite: generated::types::EventFdReadwrite {
+            nbytes: 0,
+            flags: generated::types::Eventrwflags::empty(),
+        },
+    }
+}
+
 fn write_bytes_capacity(
     memory: &mut GuestMemory<'_>,
     ptr: GuestPtr<u8>,
@@ -1320,3 +1944,380 @@ fn write_bytes_capacity(
     let next = ptr.add(len)?;
     Ok(next)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use generated::types::{Errno, Oflags};
+
+    fn errno<T>(res: Result<T, generated::types::Error>) -> Errno {
+        match res {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.downcast().unwrap(),
+        }
+    }
+
+    fn path(p: &str) -> Vec<String> {
+        resolve(&[], p)
+    }
+
+    /// root with runner-mapped `lib/a.py` and empty `empty` directory
+    fn mapped_fs() -> FilesTrie {
+        let mut fs = FilesTrie::new_dir(None);
+        let root = fs.children_mut(&[]).unwrap();
+        let mut lib = FilesTrie::new_dir(None);
+        lib.children_mut(&[]).unwrap().insert(
+            "a.py".into(),
+            Box::new(FilesTrie::File {
+                data: SharedBytes::from(&b"abc"[..]),
+                from_contract: false,
+            }),
+        );
+        root.insert("lib".into(), Box::new(lib));
+        root.insert("empty".into(), Box::new(FilesTrie::new_dir(None)));
+        fs
+    }
+
+    fn open_file(
+        fs: &mut FilesTrie,
+        limiter: &rt::memlimiter::Limiter,
+        p: &str,
+        oflags: Oflags,
+    ) -> vfs::MemFile {
+        match fs.open(limiter, path(p), oflags, true, false).unwrap() {
+            vfs::FileDescriptor::MemFile { file, .. } => file,
+            _ => panic!("expected a writable file"),
+        }
+    }
+
+    #[test]
+    fn create_excl_trunc_append() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut fs = mapped_fs();
+
+        assert_eq!(
+            errno(fs.open(&limiter, path("x"), Oflags::empty(), true, false)),
+            Errno::Noent
+        );
+
+        let file = open_file(&mut fs, &limiter, "x", Oflags::CREAT);
+        file.write_at(0, b"hello").unwrap();
+
+        assert_eq!(
+            errno(fs.open(
+                &limiter,
+                path("x"),
+                Oflags::CREAT | Oflags::EXCL,
+                true,
+                false
+            )),
+            Errno::Exist
+        );
+
+        let reopened = open_file(&mut fs, &limiter, "x", Oflags::CREAT);
+        assert_eq!(reopened.with_contents(|x| x.to_vec()), b"hello");
+
+        open_file(&mut fs, &limiter, "x", Oflags::TRUNC);
+        assert_eq!(file.len(), 0);
+
+        match fs
+            .open(&limiter, path("x"), Oflags::empty(), true, true)
+            .unwrap()
+        {
+            vfs::FileDescriptor::MemFile { append, .. } => assert!(append),
+            _ => panic!("expected a writable file"),
+        }
+
+        assert_eq!(
+            errno(fs.open(&limiter, path("x"), Oflags::DIRECTORY, false, false)),
+            Errno::Notdir
+        );
+        assert_eq!(
+            errno(fs.open(&limiter, path("lib"), Oflags::empty(), true, false)),
+            Errno::Isdir
+        );
+
+        // copy on write keeps the original contents
+        let copy = open_file(&mut fs, &limiter, "lib/a.py", Oflags::empty());
+        assert_eq!(copy.with_contents(|x| x.to_vec()), b"abc");
+        assert!(matches!(
+            fs.get(&path("lib/a.py")).unwrap(),
+            FilesTrie::Memory { charge: None, .. }
+        ));
+    }
+
+    #[test]
+    fn mapped_entries_are_read_only() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut fs = mapped_fs();
+
+        assert_eq!(errno(fs.unlink_file(&path("lib/a.py"))), Errno::Rofs);
+        assert_eq!(errno(fs.remove_dir(&path("empty"))), Errno::Rofs);
+        assert_eq!(
+            errno(fs.rename(&path("lib/a.py"), &path("b.py"))),
+            Errno::Rofs
+        );
+        assert_eq!(errno(fs.rename(&path("empty"), &path("e"))), Errno::Rofs);
+
+        open_file(&mut fs, &limiter, "b.py", Oflags::CREAT);
+        assert_eq!(
+            errno(fs.rename(&path("b.py"), &path("lib/a.py"))),
+            Errno::Rofs
+        );
+
+        // written copy of a mapped file stays mapped
+        open_file(&mut fs, &limiter, "lib/a.py", Oflags::TRUNC);
+        assert_eq!(errno(fs.unlink_file(&path("lib/a.py"))), Errno::Rofs);
+
+        fs.unlink_file(&path("b.py")).unwrap();
+        assert!(fs.get(&path("lib/a.py")).is_ok());
+    }
+
+    #[test]
+    fn rename_over_non_empty_dir() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut fs = mapped_fs();
+
+        fs.create_dir(&limiter, &path("a")).unwrap();
+        fs.create_dir(&limiter, &path("b")).unwrap();
+        fs.create_dir(&limiter, &path("b/c")).unwrap();
+        open_file(&mut fs, &limiter, "f", Oflags::CREAT);
+
+        assert_eq!(errno(fs.rename(&path("a"), &path("b"))), Errno::Notempty);
+        assert_eq!(errno(fs.rename(&path("b"), &path("b/c/d"))), Errno::Inval);
+        assert_eq!(errno(fs.rename(&path("f"), &path("a"))), Errno::Isdir);
+        assert_eq!(errno(fs.rename(&path("a"), &path("f"))), Errno::Notdir);
+        assert_eq!(errno(fs.remove_dir(&path("b"))), Errno::Notempty);
+
+        fs.rename(&path("a"), &path("b/c")).unwrap();
+        assert!(fs.get(&path("a")).is_err());
+        assert!(fs.get(&path("b/c")).unwrap().is_dir());
+    }
+
+    #[test]
+    fn dotdot_at_root() {
+        assert_eq!(path("../../a/./b/.."), vec!["a".to_owned()]);
+        assert_eq!(resolve(&["x".into()], "../.."), Vec::<String>::new());
+        assert_eq!(
+            resolve(&["x".into()], "//y/"),
+            vec!["x".to_owned(), "y".to_owned()]
+        );
+    }
+
+    #[test]
+    fn fbig_boundary() {
+        assert_eq!(file_end(0, u32::MAX.into()).unwrap(), u32::MAX);
+        assert_eq!(file_end(u32::MAX.into(), 0).unwrap(), u32::MAX);
+        assert_eq!(errno(file_end(1, u32::MAX.into())), Errno::Fbig);
+        assert_eq!(errno(file_end(u64::MAX, 1)), Errno::Fbig);
+    }
+
+    #[test]
+    fn unlink_releases_memory() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut fs = mapped_fs();
+        let before = limiter.get_remaining_memory();
+
+        fs.create_dir(&limiter, &path("d")).unwrap();
+        let file = open_file(&mut fs, &limiter, "d/f", Oflags::CREAT);
+        file.write_at(10, &[1; 100]).unwrap();
+        let during = limiter.get_remaining_memory();
+        assert!(during < before - 110);
+
+        fs.unlink_file(&path("d/f")).unwrap();
+        // open descriptor keeps the contents alive
+        assert!(limiter.get_remaining_memory() < before - 110);
+        drop(file);
+
+        fs.remove_dir(&path("d")).unwrap();
+        assert_eq!(limiter.get_remaining_memory(), before);
+    }
+
+    #[test]
+    fn readdir_order() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut fs = mapped_fs();
+
+        for name in ["b", "a", "B", "_"] {
+            open_file(&mut fs, &limiter, name, Oflags::CREAT);
+        }
+        fs.create_dir(&limiter, &path("c")).unwrap();
+
+        let entries = fs.dir_entries(&[]).unwrap();
+        let names: Vec<&str> = entries.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, [".", "..", "B", "_", "a", "b", "c", "empty", "lib"]);
+        for (i, (dirent, name)) in entries.iter().enumerate() {
+            assert_eq!(dirent.d_next, i as u64 + 1);
+            assert_eq!(dirent.d_namlen as usize, name.len());
+        }
+        assert_eq!(entries[6].0.d_type, generated::types::Filetype::Directory);
+        assert_eq!(entries[5].0.d_type, generated::types::Filetype::RegularFile);
+
+        assert_eq!(errno(fs.dir_entries(&path("a"))), Errno::Badf);
+    }
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn det_context() -> (vfs::VFS, Context) {
+        let conf = base::Config {
+            needs_error_fingerprint: false,
+            is_deterministic: true,
+            can_read_storage: false,
+            can_write_storage: false,
+            can_spawn_nondet: false,
+            can_send_messages: false,
+            can_call_others: false,
+            state_mode: public_abi::StorageType::Default,
+        };
+        let context = Context::new(
+            chrono::DateTime::from_timestamp(1000, 0).unwrap(),
+            conf,
+            cancellation::make().0,
+            sync::DArc::new(rt::Metrics::default()),
+            None,
+        );
+        let vfs = vfs::VFS::new(Vec::new(), rt::memlimiter::Limiter::new("test"), None);
+
+        (vfs, context)
+    }
+
+    fn clock_sub(userdata: u64, timeout: u64, absolute: bool) -> generated::types::Subscription {
+        let flags = if absolute {
+            generated::types::Subclockflags::SUBSCRIPTION_CLOCK_ABSTIME
+        } else {
+            generated::types::Subclockflags::empty()
+        };
+
+        generated::types::Subscription {
+            userdata,
+            u: generated::types::SubscriptionU::Clock(generated::types::SubscriptionClock {
+                id: generated::types::Clockid::Realtime,
+                timeout,
+                precision: 0,
+                flags,
+            }),
+        }
+    }
+
+    /// Polls subscriptions, returns `(userdata, error, type)` of ready events
+    async fn poll(
+        ctx: &mut ContextVFS<'_>,
+        subs: &[generated::types::Subscription],
+    ) -> Vec<(u64, Errno, generated::types::Eventtype)> {
+        use generated::wasi_snapshot_preview1::WasiSnapshotPreview1 as _;
+
+        let mut buf = vec![0u8; 4096];
+        let mut memory = GuestMemory::Unshared(&mut buf);
+
+        let subs_ptr = GuestPtr::<generated::types::Subscription>::new(0);
+        let events_ptr = GuestPtr::<generated::types::Event>::new(2048);
+        for (sub, i) in subs.iter().zip(0u32..) {
+            memory.write(subs_ptr.add(i).unwrap(), sub.clone()).unwrap();
+        }
+
+        let count = ctx
+            .poll_oneoff(&mut memory, subs_ptr, events_ptr, subs.len() as u32)
+            .await
+            .unwrap();
+
+        (0..count)
+            .map(|i| {
+                let event = memory.read(events_ptr.add(i).unwrap()).unwrap();
+                (event.userdata, event.error, event.type_)
+            })
+            .collect()
+    }
+
+    fn now(ctx: &mut ContextVFS<'_>) -> u64 {
+        use generated::wasi_snapshot_preview1::WasiSnapshotPreview1 as _;
+
+        let mut memory = GuestMemory::Unshared(&mut []);
+        ctx.clock_time_get(&mut memory, generated::types::Clockid::Realtime, 0)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn det_poll_clock() {
+        let (mut vfs, mut context) = det_context();
+        let mut ctx = ContextVFS {
+            vfs: &mut vfs,
+            context: &mut context,
+        };
+
+        let start = now(&mut ctx);
+        let wall_start = std::time::Instant::now();
+
+        assert_eq!(
+            poll(
+                &mut ctx,
+                &[
+                    clock_sub(1, 60 * SECOND, false),
+                    clock_sub(2, 5 * SECOND, false)
+                ]
+            )
+            .await,
+            [(2, Errno::Success, generated::types::Eventtype::Clock)]
+        );
+        assert_eq!(now(&mut ctx), start + 5 * SECOND);
+
+        assert_eq!(
+            poll(&mut ctx, &[clock_sub(3, start + 7 * SECOND, true)]).await,
+            [(3, Errno::Success, generated::types::Eventtype::Clock)]
+        );
+        assert_eq!(now(&mut ctx), start + 7 * SECOND);
+
+        // deadline in the past is ready without advancing the clock
+        poll(&mut ctx, &[clock_sub(4, start, true)]).await;
+        assert_eq!(now(&mut ctx), start + 7 * SECOND);
+
+        // virtual clock doesn't wait
+        assert!(wall_start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn det_poll_fd_and_clock() {
+        let (mut vfs, mut context) = det_context();
+        let mut ctx = ContextVFS {
+            vfs: &mut vfs,
+            context: &mut context,
+        };
+
+        let start = now(&mut ctx);
+
+        let fd_sub = |userdata, u| generated::types::Subscription { userdata, u };
+        let events = poll(
+            &mut ctx,
+            &[
+                clock_sub(1, SECOND, false),
+                fd_sub(
+                    2,
+                    generated::types::SubscriptionU::FdRead(
+                        generated::types::SubscriptionFdReadwrite {
+                            file_descriptor: generated::types::Fd::from(0),
+                        },
+                    ),
+                ),
+                fd_sub(
+                    3,
+                    generated::types::SubscriptionU::FdWrite(
+                        generated::types::SubscriptionFdReadwrite {
+                            file_descriptor: generated::types::Fd::from(1),
+                        },
+                    ),
+                ),
+            ],
+        )
+        .await;
+
+        // fd subscriptions complete immediately, so clock one is not waited for
+        assert_eq!(
+            events,
+            [
+                (2, Errno::Notsup, generated::types::Eventtype::FdRead),
+                (3, Errno::Notsup, generated::types::Eventtype::FdWrite),
+            ]
+        );
+        assert_eq!(now(&mut ctx), start);
+    }
+}
@@ -1,4 +1,6 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 use crate::{public_abi, rt};
 use genvm_common::*;
@@ -10,12 +12,152 @@ pub struct FileContents {
     pub release_memory: bool,
 }
 
+/// Memory consumed from the limiter for as long as this value is alive
+pub struct Charge {
+    limiter: rt::memlimiter::Limiter,
+    amount: u32,
+}
+
+impl Charge {
+    pub fn new(limiter: &rt::memlimiter::Limiter, amount: u32) -> anyhow::Result<Self> {
+        if !limiter.consume(amount) {
+            return Err(rt::errors::VMError::oom(None).into());
+        }
+
+        Ok(Self {
+            limiter: limiter.clone(),
+            amount,
+        })
+    }
+
+    /// Consumes or releases the difference with the current amount
+    pub fn resize(&mut self, amount: u32) -> anyhow::Result<()> {
+        if amount > self.amount {
+            if !self.limiter.consume(amount - self.amount) {
+                return Err(rt::errors::VMError::oom(None).into());
+            }
+        } else {
+            self.limiter.release(self.amount - amount);
+        }
+        self.amount = amount;
+        Ok(())
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.limiter.release(self.amount);
+    }
+}
+
+struct MemFileData {
+    data: Vec<u8>,
+    charge: Charge,
+}
+
+/// Contents of a file that was created or written by the contract.
+/// Shared between the file system entry and all descriptors opened for it
+#[derive(Clone)]
+pub struct MemFile(Arc<Mutex<MemFileData>>);
+
+impl MemFile {
+    pub fn new(limiter: &rt::memlimiter::Limiter, data: Vec<u8>) -> anyhow::Result<Self> {
+        let charge = Charge::new(limiter, data.len().try_into()?)?;
+        Ok(Self(Arc::new(Mutex::new(MemFileData { data, charge }))))
+    }
+
+    pub fn len(&self) -> u32 {
+        self.0.lock().unwrap().data.len() as u32
+    }
+
+    pub fn with_contents<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        f(&self.0.lock().unwrap().data)
+    }
+
+    /// Truncates or extends the file with zeroes
+    pub fn set_len(&self, len: u32) -> anyhow::Result<()> {
+        let mut file = self.0.lock().unwrap();
+        file.charge.resize(len)?;
+        file.data.resize(len as usize, 0);
+        Ok(())
+    }
+
+    /// Writes `bytes` at `offset`, gap after the current end is filled with zeroes
+    pub fn write_at(&self, offset: u32, bytes: &[u8]) -> anyhow::Result<()> {
+        let end: u32 = (offset as u64 + bytes.len() as u64).try_into()?;
+
+        let mut file = self.0.lock().unwrap();
+        if end as usize > file.data.len() {
+            file.charge.resize(end)?;
+            file.data.resize(end as usize, 0);
+        }
+        file.data[offset as usize..end as usize].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Maximal amount of bytes kept for each of captured stdout and stderr of a VM
+pub const CAPTURED_OUTPUT_LIMIT: usize = 1 << 20;
+
+/// Maximal amount of bytes kept for captured output of all VMs of an execution
+pub const CAPTURED_OUTPUT_TOTAL_LIMIT: usize = 16 << 20;
+
+/// Copy of guest stdout and stderr, captured in debug mode to be reported with the result
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// whenever some bytes were dropped because of [`CAPTURED_OUTPUT_LIMIT`] or [`CAPTURED_OUTPUT_TOTAL_LIMIT`]
+    pub truncated: bool,
+    /// bytes that can still be kept by all VMs of an execution, shared between them
+    remaining: Arc<AtomicUsize>,
+}
+
+impl CapturedOutput {
+    pub fn new(remaining: Arc<AtomicUsize>) -> Self {
+        Self {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            truncated: false,
+            remaining,
+        }
+    }
+
+    pub fn write(&mut self, to_stderr: bool, data: &[u8]) {
+        let buf = if to_stderr {
+            &mut self.stderr
+        } else {
+            &mut self.stdout
+        };
+
+        let len = data.len().min(CAPTURED_OUTPUT_LIMIT - buf.len());
+        let len = match self
+            .remaining
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |r| Some(r - r.min(len)))
+        {
+            Ok(remaining) | Err(remaining) => len.min(remaining),
+        };
+
+        buf.extend_from_slice(&data[..len]);
+        if len < data.len() {
+            self.truncated = true;
+        }
+    }
+}
+
 pub enum FileDescriptor {
     Stdin,
     Stdout,
     Stderr,
     File(FileContents),
-    Dir { path: Vec<String> },
+    MemFile {
+        file: MemFile,
+        pos: u64,
+        append: bool,
+        writable: bool,
+    },
+    Dir {
+        path: Vec<String>,
+    },
 }
 
 #[allow(dead_code)]
@@ -27,10 +169,26 @@ pub(crate) struct VFS {
     pub next_free_descriptor: u32,
 
     pub limiter: rt::memlimiter::Limiter,
+
+    /// if present, stdout and stderr are additionally copied here
+    pub output: Option<Arc<Mutex<CapturedOutput>>>,
+
+    /// descriptor state at the start of runner initialization, see [`VFS::begin_initialization`]
+    init_state: Option<InitState>,
+}
+
+struct InitState {
+    fds: BTreeSet<u32>,
+    free_descriptors: Vec<u32>,
+    next_free_descriptor: u32,
 }
 
 impl VFS {
-    pub fn new(stdin: Vec<u8>, limiter: rt::memlimiter::Limiter) -> Self {
+    pub fn new(
+        stdin: Vec<u8>,
+        limiter: rt::memlimiter::Limiter,
+        output: Option<Arc<Mutex<CapturedOutput>>>,
+    ) -> Self {
         let stdin_data = util::SharedBytes::new(stdin);
 
         let fds = BTreeMap::from([
@@ -52,9 +210,53 @@ impl VFS {
             next_free_descriptor,
             free_descriptors: Vec::new(),
             limiter,
+            output,
+            init_state: None,
         }
     }
 
+    /// Remembers descriptors that exist before runner initialization, they can't be used during it
+    pub fn begin_initialization(&mut self) {
+        self.init_state = Some(InitState {
+            fds: self.fds.keys().copied().collect(),
+            free_descriptors: self.free_descriptors.clone(),
+            next_free_descriptor: self.next_free_descriptor,
+        });
+    }
+
+    pub fn is_pre_init_fd(&self, fd: u32) -> bool {
+        self.init_state
+            .as_ref()
+            .is_some_and(|state| state.fds.contains(&fd))
+    }
+
+    /// Fails if descriptors opened during initialization are left open. Descriptor allocation is reset,
+    /// because restoring from a snapshot does not allocate descriptors
+    pub fn end_initialization(&mut self) -> anyhow::Result<()> {
+        let Some(state) = self.init_state.take() else {
+            return Ok(());
+        };
+
+        if !self.fds.keys().eq(state.fds.iter()) {
+            return Err(rt::errors::VMError(
+                format!(
+                    "{} runner_init_open_fds",
+                    public_abi::VmError::InvalidContract.value()
+                ),
+                None,
+            )
+            .into());
+        }
+
+        let allocated = self.next_free_descriptor - state.next_free_descriptor;
+        self.limiter
+            .release(allocated * public_abi::MemoryLimiterConsts::FdAllocation as u32);
+        self.next_free_descriptor = state.next_free_descriptor;
+        self.free_descriptors = state.free_descriptors;
+
+        Ok(())
+    }
+
     /// gives vacant fd
     pub fn alloc_fd(&mut self) -> anyhow::Result<u32> {
         match self.free_descriptors.pop() {
@@ -104,3 +306,58 @@ impl VFS {
         Ok(fd)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_output_limits() {
+        let remaining = Arc::new(AtomicUsize::new(CAPTURED_OUTPUT_LIMIT + 10));
+
+        let mut first = CapturedOutput::new(remaining.clone());
+        first.write(false, &vec![b'a'; CAPTURED_OUTPUT_LIMIT + 1]);
+        first.write(true, b"err");
+        assert_eq!(first.stdout.len(), CAPTURED_OUTPUT_LIMIT);
+        assert_eq!(first.stderr, b"err");
+        assert!(first.truncated);
+
+        let mut second = CapturedOutput::new(remaining.clone());
+        second.write(false, b"0123456789");
+        assert_eq!(second.stdout, b"0123456");
+        assert!(second.truncated);
+        assert_eq!(remaining.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn initialization_fds() {
+        let limiter = rt::memlimiter::Limiter::new("test");
+        let mut vfs = VFS::new(Vec::new(), limiter.clone(), None);
+
+        vfs.begin_initialization();
+        assert!(vfs.is_pre_init_fd(0) && vfs.is_pre_init_fd(3));
+
+        let contents = || FileContents {
+            contents: util::SharedBytes::from(b"mapped".as_ref()),
+            pos: 0,
+            release_memory: false,
+        };
+        let left_open = vfs.place_content(contents()).unwrap();
+        assert!(!vfs.is_pre_init_fd(left_open));
+        assert!(vfs.end_initialization().is_err());
+
+        vfs.pop_fd(left_open);
+        let remaining = limiter.get_remaining_memory();
+        vfs.begin_initialization();
+        let first = vfs.place_content(contents()).unwrap();
+        let second = vfs.place_content(contents()).unwrap();
+        vfs.pop_fd(first);
+        vfs.pop_fd(second);
+        vfs.end_initialization().unwrap();
+        assert_eq!(limiter.get_remaining_memory(), remaining);
+
+        // allocation continues as if initialization did not open anything
+        assert_eq!(vfs.place_content(contents()).unwrap(), left_open);
+        assert!(!vfs.is_pre_init_fd(0));
+    }
+}
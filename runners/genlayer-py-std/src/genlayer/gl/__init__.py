@@ -31,6 +31,7 @@ __all__ = (
 
 import typing
 import os
+import contextlib
 
 import genlayer.py.calldata as calldata
 import _genlayer_wasi as wasi
@@ -128,6 +129,23 @@ def trace_time_micro() -> int:
 	).get()
 
 
+@contextlib.contextmanager
+def trace_span(name: str) -> typing.Iterator[None]:
+	"""
+	Marks a logical section of execution, which is reported by the executor with its duration and count of gl calls
+
+	.. code:: python
+
+		with gl.trace_span('parse'):
+			...
+	"""
+	wasi.gl_call(calldata.encode({'Trace': {'SpanBegin': {'name': name}}}))
+	try:
+		yield
+	finally:
+		wasi.gl_call(calldata.encode({'Trace': {'SpanEnd': None}}))
+
+
 if os.getenv('GENERATING_DOCS', 'false') == 'true':
 	message_raw: MessageRawType = ...  # type: ignore
 	"""
@@ -8,6 +8,7 @@ This module provides some "advanced" features that can be used for optimizations
 __all__ = (
 	'user_error_immediate',
 	'emit_raw_event',
+	'random_seed_commitment',
 )
 
 import typing
@@ -42,3 +43,17 @@ def user_error_immediate(reason: str) -> typing.NoReturn:
 	"""
 
 	gl_call.rollback(reason)
+
+
+def random_seed_commitment() -> bytes | None:
+	"""
+	Returns commitment to the seed of deterministic :py:mod:`random`,
+	or ``None`` if host did not provide entropy and legacy constant seed is used
+	"""
+
+	return gl_call.gl_call_generic(
+		{
+			'RandomSeedCommitment': None,
+		},
+		lambda x: typing.cast(bytes | None, calldata.decode(x)),
+	).get()
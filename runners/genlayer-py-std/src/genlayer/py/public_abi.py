@@ -41,6 +41,9 @@ class VmError(StrEnum):
 	VERSION_TOO_BIG = 'version_too_big'
 	OOM = 'OOM'
 	INVALID_CONTRACT = 'invalid_contract'
+	SUB_LIMIT_EXCEEDED = 'sub_limit_exceeded'
+	CALL_DEPTH_EXCEEDED = 'call_depth_exceeded'
+	REENTRANCY_FORBIDDEN = 'reentrancy_forbidden'
 
 
 EVENT_MAX_TOPICS: typing.Final[int] = 4
@@ -49,4 +52,19 @@ EVENT_MAX_TOPICS: typing.Final[int] = 4
 ABSENT_VERSION: typing.Final[str] = 'v0.1.0'
 
 
+DET_SIMD_MIN_VERSION: typing.Final[str] = 'v0.2.5'
+
+
+RANDOM_SEED_MIN_VERSION: typing.Final[str] = 'v0.2.5'
+
+
+MAX_VM_DEPTH: typing.Final[int] = 16
+
+
+REENTRANCY_FORBIDDEN_MIN_VERSION: typing.Final[str] = 'v0.2.5'
+
+
+RESULT_FORMAT_VERSION: typing.Final[int] = 2
+
+
 CODE_SLOT_OFFSET: typing.Final[int] = 1
@@ -8,6 +8,10 @@ ruby ./configure.rb
 
 ninja -v -C build all/bin
 
+GENVM_CONFIG="$(dirname "$(readlink -f ./build/out/bin/genvm)")/../config/genvm.yaml"
+sed "s|^registry_dir: .*|registry_dir: $(pwd)/build/shipped-registry|" "$GENVM_CONFIG" > build/shipped-registry/genvm.yaml
+./build/out/bin/genvm --config build/shipped-registry/genvm.yaml runners verify
+
 python3 ./build/out/bin/post-install.py \
     --error-on-missing-executor=false \
     --default-download=false
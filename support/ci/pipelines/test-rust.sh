@@ -31,4 +31,16 @@ nix develop .#rust-test --command python3 \
     upload \
     --root build/out/runners --registry build/out/executor/vTEST/data/all.json || true
 
+# every runner ever shipped must still be accepted by the executor, see `genvm runners verify` in src/test-rust.sh
+mkdir -p build/shipped-registry
+nix eval --verbose --impure --read-only --show-trace --json --expr \
+    'let build-config = builtins.fromJSON (builtins.readFile ./flake-config.json); drv = import ./runners/support/all/all.nix { inherit build-config; } ; in builtins.mapAttrs (id: xs: builtins.map (x: builtins.convertHash { hash = x.hash; toHashFormat = "nix32"; }) xs) (builtins.groupBy (x: x.id) drv)' \
+    > build/shipped-registry/all-revisions.json
+# same runner can be shipped by several revisions
+python3 ./support/runner-script.py merge-registries build/shipped-registry/all-revisions.json > build/shipped-registry/all.json
+
+python3 ./support/runner-script.py \
+    download \
+    --dest build/out/runners --registry build/shipped-registry/all.json
+
 nix develop .#rust-test --command bash ./support/ci/pipelines/src/test-rust.sh
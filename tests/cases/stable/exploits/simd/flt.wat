@@ -0,0 +1,15 @@
+(module
+	(import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+	(func (export "_start")
+		(v128.const f32x4 1 2 3 4)
+		(v128.const f32x4 1 1 1 1)
+		f32x4.add
+		i32x4.extract_lane 0
+		(call $proc_exit)
+		unreachable
+	)
+
+	(memory $mem 1)
+	(export "memory" (memory $mem))
+)
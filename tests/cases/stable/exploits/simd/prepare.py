@@ -0,0 +1,33 @@
+import zipfile
+import io
+import subprocess
+import tempfile
+from pathlib import Path
+
+DEFAULT_TIME = (1980, 1, 1, 0, 0, 0)
+
+root = Path(__file__).parent
+
+for name in ['flt', 'int']:
+	with tempfile.TemporaryDirectory() as tmp:
+		wasm_path = Path(tmp).joinpath(f'{name}.wasm')
+		subprocess.run(
+			['wat2wasm', '-o', wasm_path, root.joinpath(f'{name}.wat')],
+			check=True,
+		)
+		wasm = wasm_path.read_bytes()
+
+	fake_zip = io.BytesIO()
+	with zipfile.ZipFile(fake_zip, mode='w', compression=zipfile.ZIP_STORED) as zip_file:
+
+		def add_file(dst: str, contents: bytes):
+			info = zipfile.ZipInfo(dst, date_time=DEFAULT_TIME)
+			zip_file.writestr(info, contents)
+
+		add_file('contract.wasm', wasm)
+		# SIMD in deterministic mode requires det_simd_min_version
+		add_file('version', b'v0.2.5')
+		add_file('runner.json', b'{ "StartWasm": "contract.wasm" }')
+	fake_zip.flush()
+
+	root.joinpath(f'{name}.zip').write_bytes(fake_zip.getvalue())
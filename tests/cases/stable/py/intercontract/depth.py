@@ -0,0 +1,37 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+import genlayer.gl._internal.gl_call as gl_call
+from genlayer.gl.vm import _decode_sub_vm_result_retn
+from genlayer.py.public_abi import StorageType
+
+
+class Contract(gl.Contract):
+	def __init__(self):
+		pass
+
+	@gl.public.view
+	def depth(self, n: int):
+		if n == 0:
+			return 0
+
+		res = gl_call.gl_call_generic(
+			{
+				'CallContract': {
+					'address': gl.message.contract_address,
+					'calldata': {'method': 'depth', 'args': [n - 1]},
+					'state': StorageType.LATEST_NON_FINAL.value,
+				}
+			},
+			_decode_sub_vm_result_retn,
+		).get()
+
+		if not isinstance(res, gl.vm.Return):
+			return repr(res)
+		if isinstance(res.calldata, str):
+			return res.calldata
+		return res.calldata + 1
+
+	@gl.public.write
+	def main(self, n: int):
+		print(self.depth(n))
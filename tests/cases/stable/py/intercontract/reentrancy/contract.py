@@ -0,0 +1,30 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+import genlayer.gl._internal.gl_call as gl_call
+from genlayer.gl.vm import _decode_sub_vm_result_retn
+from genlayer.py.public_abi import StorageType
+
+
+class Contract(gl.Contract):
+	def __init__(self):
+		pass
+
+	@gl.public.view
+	def foo(self):
+		return 1
+
+	@gl.public.write
+	def main(self, write: bool):
+		res = gl_call.gl_call_generic(
+			{
+				'CallContract': {
+					'address': gl.message.contract_address,
+					'calldata': {'method': 'foo', 'args': []},
+					'state': StorageType.LATEST_NON_FINAL.value,
+					'allow_write_ops': write,
+				}
+			},
+			_decode_sub_vm_result_retn,
+		).get()
+		print(res)
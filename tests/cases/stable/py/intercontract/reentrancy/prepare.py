@@ -0,0 +1,11 @@
+from pathlib import Path
+
+test_dir = Path(__file__).parent
+
+import zipfile
+
+# same contract, but of version that forbids reentrancy
+with zipfile.ZipFile(test_dir.joinpath('contract.zip'), 'w') as f:
+	f.write(test_dir.joinpath('contract.py'), 'contract/__init__.py')
+	f.writestr('runner.json', '{ "Depends": "py-genlayer-multi:test" }')
+	f.writestr('version', 'v0.2.5')
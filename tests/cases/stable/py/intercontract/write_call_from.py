@@ -0,0 +1,64 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+import _genlayer_wasi as wasi
+
+import genlayer.gl._internal.gl_call as gl_call
+from genlayer.gl.vm import _decode_sub_vm_result_retn
+from genlayer.py.public_abi import StorageType
+
+
+def write_call(addr, method, *args, limits={}):
+	return gl_call.gl_call_generic(
+		{
+			'CallContract': {
+				'address': addr,
+				'calldata': {'method': method, 'args': list(args)},
+				'state': StorageType.LATEST_NON_FINAL.value,
+				'allow_write_ops': True,
+				'limits': limits,
+			}
+		},
+		_decode_sub_vm_result_retn,
+	).get()
+
+
+class Contract(gl.Contract):
+	local: u32
+
+	def __init__(self):
+		pass
+
+	@gl.public.write
+	def main(self, addr: Address):
+		me = gl.message.contract_address
+
+		print('commit', write_call(addr, 'set', 5), write_call(addr, 'get'))
+		print('rollback', write_call(addr, 'set_fail', 7), write_call(addr, 'get'))
+		print(
+			'nested commit',
+			write_call(addr, 'set_both', me, 9),
+			write_call(addr, 'get'),
+			self.local,
+		)
+		print(
+			'nested rollback',
+			write_call(addr, 'set_both_fail', me, 11),
+			write_call(addr, 'get'),
+			self.local,
+		)
+
+	@gl.public.write
+	def refund(self, addr: Address):
+		me = gl.message.contract_address
+
+		print(write_call(addr, 'fill_twice', me, limits={'storage_pages': 48}))
+
+	@gl.public.write
+	def set_local(self, v: int):
+		self.local = v
+
+	@gl.public.write
+	def fill_fail(self):
+		wasi.storage_write(b'\x01' * 32, 0, b'\x02' * 1024)
+		gl.advanced.user_error_immediate('fill')
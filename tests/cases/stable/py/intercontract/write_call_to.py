@@ -0,0 +1,57 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+import genlayer.gl._internal.gl_call as gl_call
+from genlayer.gl.vm import _decode_sub_vm_result_retn
+from genlayer.py.public_abi import StorageType
+
+
+def write_call(addr, method, *args):
+	return gl_call.gl_call_generic(
+		{
+			'CallContract': {
+				'address': addr,
+				'calldata': {'method': method, 'args': list(args)},
+				'state': StorageType.LATEST_NON_FINAL.value,
+				'allow_write_ops': True,
+			}
+		},
+		_decode_sub_vm_result_retn,
+	).get()
+
+
+class Contract(gl.Contract):
+	value: u32
+
+	def __init__(self):
+		pass
+
+	@gl.public.write
+	def get(self) -> int:
+		return self.value
+
+	@gl.public.write
+	def set(self, v: int):
+		self.value = v
+		gl.get_contract_at(Address(b'\x30' * 20)).emit().set(v)
+
+	@gl.public.write
+	def set_fail(self, v: int):
+		self.set(v)
+		gl.advanced.user_error_immediate('fail')
+
+	@gl.public.write
+	def set_both(self, caller: Address, v: int):
+		self.value = v
+		print('set_local', write_call(caller, 'set_local', v + 1))
+
+	@gl.public.write
+	def set_both_fail(self, caller: Address, v: int):
+		self.set_both(caller, v)
+		gl.advanced.user_error_immediate('fail')
+
+	@gl.public.write
+	def fill_twice(self, caller: Address):
+		# pages of the first failed call must be released for the second one to fit
+		print('fill', write_call(caller, 'fill_fail'))
+		print('fill', write_call(caller, 'fill_fail'))
@@ -0,0 +1,20 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+
+def nest(n: int):
+	if n == 0:
+		return 0
+
+	res = gl.vm.spawn_sandbox(lambda: nest(n - 1))
+	if not isinstance(res, gl.vm.Return):
+		return repr(res)
+	if isinstance(res.calldata, str):
+		return res.calldata
+	return res.calldata + 1
+
+
+class Contract(gl.Contract):
+	@gl.public.write
+	def main(self, n: int):
+		print(nest(n))
@@ -0,0 +1,57 @@
+# { "Depends": "py-genlayer:test" }
+from genlayer import *
+
+import cloudpickle
+import _genlayer_wasi as wasi
+
+import genlayer.gl._internal.gl_call as gl_call
+from genlayer.gl.vm import _decode_sub_vm_result_retn
+
+
+def sandbox(fn, limits, *, allow_write_ops=False):
+	try:
+		return gl_call.gl_call_generic(
+			{
+				'Sandbox': {
+					'data': cloudpickle.dumps(fn),
+					'allow_write_ops': allow_write_ops,
+					'limits': limits,
+				}
+			},
+			_decode_sub_vm_result_retn,
+		).get()
+	except SystemError as e:
+		return e
+
+
+def spin():
+	while True:
+		pass
+
+
+def write_storage():
+	wasi.storage_write(b'\x01' * 32, 0, b'\x02' * 1024)
+
+
+class Contract(gl.Contract):
+	@gl.public.write
+	def memory(self):
+		print(sandbox(lambda: 1, {'memory': 1024 * 1024}))
+
+	@gl.public.write
+	def storage(self):
+		print(sandbox(write_storage, {'storage_pages': 4}, allow_write_ops=True))
+		print(sandbox(write_storage, {'storage_pages': 64}, allow_write_ops=True))
+
+	@gl.public.write
+	def timeout(self):
+		print(repr(sandbox(spin, {'wall_time_ms': 100})))
+
+		def run_ndet():
+			print(sandbox(spin, {'wall_time_ms': 100}))
+
+		gl.eq_principle.strict_eq(run_ndet)
+
+	@gl.public.write
+	def fuel(self):
+		print(repr(sandbox(lambda: 1, {'fuel': 1000})))
@@ -0,0 +1,17 @@
+(module
+	(type $void_fn (func))
+	(import "wasi_snapshot_preview1" "random_get" (func $random_get (param i32 i32) (result i32)))
+	(import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+
+	(func (export "_start") (type $void_fn)
+		(drop (call $random_get (i32.const 0) (i32.const 4)))
+		(i32.load (i32.const 0))
+		(i32.const 255)
+		i32.and
+		(call $proc_exit)
+		unreachable
+	)
+
+	(memory $mem 1)
+	(export "memory" (memory $mem))
+)
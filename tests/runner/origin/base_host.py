@@ -298,8 +298,12 @@ class RunHostAndProgramRes:
 	result_kind: public_abi.ResultCode
 	result_data: typing.Any
 	result_fingerprint: typing.Any
-	result_storage_changes: list[tuple[bytes, bytes]]
+	result_storage_changes: list[tuple[gvm_calldata.Address, list[tuple[bytes, bytes]]]]
 	result_events: list[list[bytes]]
+	result_messages: list[dict[str, typing.Any]]
+	"""
+	messages of contracts called with write access, each is either ``{"Post": ...}`` or ``{"Deploy": ...}``
+	"""
 
 
 async def _send_timeout(manager_uri: str, genvm_id: str, logger: Logger):
@@ -478,13 +482,19 @@ async def run_genvm(
 			result_fingerprint = None
 			result_storage_changes = []
 			result_events = []
+			result_messages = []
 		else:
 			result_kind = result_host[0]
 			decoded = gvm_calldata.decode(result_host[1])
+			if decoded.get('format_version') != public_abi.RESULT_FORMAT_VERSION:
+				raise Exception(
+					f'unsupported result format version {decoded.get("format_version")}'
+				)
 			result_data = decoded.get('data')
 			result_fingerprint = decoded.get('fingerprint')
 			result_storage_changes = decoded.get('storage_changes', [])
 			result_events = decoded.get('events', [])
+			result_messages = decoded.get('messages', [])
 
 		return RunHostAndProgramRes(
 			stdout=status['stdout'],
@@ -495,6 +505,7 @@ async def run_genvm(
 			result_fingerprint=result_fingerprint,
 			result_storage_changes=result_storage_changes,
 			result_events=result_events,
+			result_messages=result_messages,
 		)
 
 	raise Exception('Execution failed')
@@ -526,6 +526,7 @@ class TestRunner:
 			'host': host,
 			'message': single_conf['message'],
 			'sync': single_conf.get('sync', False),
+			'host_data': single_conf.get('host_data', {'node_address': '0x', 'tx_id': '0x'}),
 			'tmp_dir': my_tmp_dir,
 			'expected_output': jsonnet_path.with_suffix(f'{suff}.stdout'),
 			'suff': suff,
@@ -558,7 +559,7 @@ class TestRunner:
 						timeout=config['deadline'],
 						capture_output=True,
 						is_sync=config['sync'],
-						host_data='{"node_address": "0x", "tx_id": "0x"}',
+						host_data=json.dumps(config['host_data']),
 						logger=logger,
 						host='unix://' + config['host'].path,
 						extra_args=['--debug-mode', '--print=result'],
@@ -568,13 +569,29 @@ class TestRunner:
 				)
 				for evs in res.result_events:
 					mock_host.post_event(evs[:-1], evs[-1])
-				for k, v in res.result_storage_changes:
-					mock_host.storage.write(
-						config['host'].running_address,
-						k[:32],
-						int.from_bytes(k[32:], byteorder='little'),
-						v,
-					)
+				for msg in res.result_messages:
+					if 'Post' in msg:
+						post = msg['Post']
+						asyncio.run(
+							mock_host.post_message(
+								post['address'].as_bytes, post['calldata'], json.loads(post['data'])
+							)
+						)
+					else:
+						deploy = msg['Deploy']
+						asyncio.run(
+							mock_host.deploy_contract(
+								deploy['calldata'], deploy['code'], json.loads(deploy['data'])
+							)
+						)
+				for address, changes in res.result_storage_changes:
+					for k, v in changes:
+						mock_host.storage.write(
+							address,
+							k[:32],
+							int.from_bytes(k[32:], byteorder='little'),
+							v,
+						)
 			except Exception as e:
 				time_elapsed = time.monotonic() - time_start
 				self.reporter.report_single(